@@ -1,38 +1,61 @@
-use clap::Parser;
-use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
-pub(crate) static API_KEY: &str = "QXlj";
-
-#[derive(Debug, Serialize, Deserialize)]
-/// an item on the menu
-pub(crate) struct MenuItem {
-    /// the number of the menu, i.e., 1 for Potato Fries, 2 for Karaage, etc.
-    pub(crate) item_number: u64,
-    /// the duration the menu item needs to cook in minutes. We do not need finer granularity.
-    pub(crate) duration_in_minutes: u64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-/// A table in the restaurant having various menuitems
-pub(crate) struct Table {
-    pub(crate) table_number: usize,
-    pub(crate) items: Vec<MenuItem>,
-}
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use restaurant_sdk::{ListOptions, RestaurantClient, DEFAULT_BASE_URL};
+use restaurant_types::{
+    Adjustment, AdjustmentKind, Bill, BillPayments, IssuedKey, ItemStatus, KeyInfo, MenuEntry,
+    MenuItem, NewAdjustment, NewPayment, NewRefund, NewTable, Payment, PaymentMethod, QueueEntry,
+    QueueGroup, Role, Session, SessionDetails, SessionInfo, SplitBill, SplitMode, TableInfo,
+    API_KEY,
+};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 /// Argument Parsing
 struct Args {
+    /// The server to talk to
+    #[clap(long, default_value = DEFAULT_BASE_URL)]
+    url: String,
+
+    /// The api key to authenticate with
+    #[clap(long, default_value = API_KEY)]
+    key: String,
+
+    /// Sign requests with the key instead of sending it, given the id it is listed under
+    #[clap(long)]
+    key_id: Option<u64>,
+
+    /// Also trust the CA certificates in this PEM file, for a server with a self signed certificate
+    #[clap(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// Present this PEM certificate to a server requiring client certificates, needs --client-key
+    #[clap(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// The PKCS#8 PEM key of --client-cert
+    #[clap(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
     /// Limit the number of results
     #[clap(short, long)]
     limit: Option<usize>,
 
+    /// Only show items with this status (ordered, cooking, ready, served, cancelled)
+    #[clap(short, long)]
+    status: Option<ItemStatus>,
+
     #[clap(short, long, value_parser, num_args = 2..,value_delimiter = ' ', group="input", value_names = ["table_number", "menu_item", "menu_item"])]
     /// add menu items to a table, given as `table_number menu_item1 menu_item2...`
     add: Option<Vec<usize>>,
 
-    /// delete a menu item given as `table_number menu_item1 menu_item2...`
-    #[clap(short, long, value_parser, num_args = 2..,value_delimiter = ' ', group="input", value_names = ["table_number", "menu_item", "menu_item"])]
+    /// with --add, the seat of the guest ordering the items, counting from 1
+    #[clap(long, requires = "add")]
+    seat: Option<u32>,
+
+    /// delete menu items given as `table_number item_id1 item_id2...`
+    #[clap(short, long, value_parser, num_args = 2..,value_delimiter = ' ', group="input", value_names = ["table_number", "item_id", "item_id"])]
     delete: Option<Vec<usize>>,
 
     /// get all menuitems
@@ -43,89 +66,750 @@ struct Args {
     #[clap(short = 't', long, group = "input", value_name = "table_number")]
     get_table: Option<usize>,
 
+    /// move a menu item to a new status given as `table_number item_id status`
+    #[clap(long, num_args = 3, group = "input", value_names = ["table_number", "item_id", "status"])]
+    set_status: Option<Vec<String>>,
+
+    /// show the menu
+    #[clap(long, group = "input")]
+    menu: bool,
+
     /// get specific one
-    #[clap(short = 'i', long, num_args = 2, group = "input", value_names = ["table_number", "menu_item"])]
+    #[clap(short = 'i', long, num_args = 2, group = "input", value_names = ["table_number", "item_id"])]
     get_item: Option<Vec<usize>>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// show bills and discount them, discounts and comps need a manager key
+    Bill {
+        #[clap(subcommand)]
+        command: BillCommand,
+    },
+    /// show what the kitchen still has to cook, the next item first
+    Kitchen {
+        /// only items cooked at this station
+        #[clap(long)]
+        station: Option<String>,
+
+        /// one line per dish instead of one per item
+        #[clap(short, long)]
+        group: bool,
+    },
+    /// manage the api keys, needs an admin key
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCommand,
+    },
+    /// move items to another table, all of them if no item ids are given
+    Move {
+        from: usize,
+        to: usize,
+        item_ids: Vec<u64>,
+    },
+    /// take payments and show them, refunds need a manager key
+    Payment {
+        #[clap(subcommand)]
+        command: PaymentCommand,
+    },
+    /// move an item to the guest on a seat, or to the whole table without a seat
+    Seat {
+        table_number: usize,
+        item_id: u64,
+        seat: Option<u32>,
+    },
+    /// seat parties and clear tables
+    Session {
+        #[clap(subcommand)]
+        command: SessionCommand,
+    },
+    /// show and change the floor plan, changes need a manager key
+    Tables {
+        #[clap(subcommand)]
+        command: TablesCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SessionCommand {
+    /// show who is seated at a table
+    Show { table_number: usize },
+    /// seat a party at a table
+    Open {
+        table_number: usize,
+        #[clap(long)]
+        guests: Option<u32>,
+        /// who looks after the party
+        #[clap(long)]
+        staff: Option<String>,
+    },
+    /// change the guest count or the staff of a session
+    Update {
+        table_number: usize,
+        #[clap(long)]
+        guests: Option<u32>,
+        #[clap(long)]
+        staff: Option<String>,
+    },
+    /// archive the session once everything is served, clearing the table
+    Close { table_number: usize },
+    /// list the closed sessions of a table
+    History { table_number: usize },
+}
+
+#[derive(Debug, Subcommand)]
+enum BillCommand {
+    /// show what the party at a table owes so far
+    Show { table_number: usize },
+    /// split the bill of a table by seat, by item or evenly
+    Split {
+        table_number: usize,
+        /// one share per seat
+        #[clap(long, group = "split")]
+        by_seat: bool,
+        /// this many equal shares
+        #[clap(long, group = "split")]
+        ways: Option<u32>,
+        /// the item ids of one share, i.e., `--items 3,4 --items 5`
+        #[clap(long, group = "split")]
+        items: Vec<String>,
+    },
+    /// take a percentage or an amount off the bill of a table
+    Discount {
+        table_number: usize,
+        #[clap(long, group = "off")]
+        percent: Option<u32>,
+        /// in yen
+        #[clap(long, group = "off")]
+        amount: Option<u64>,
+        #[clap(long)]
+        reason: String,
+    },
+    /// put an item on the house
+    Comp {
+        table_number: usize,
+        item_id: u64,
+        #[clap(long)]
+        reason: String,
+    },
+    /// take a discount or comp back
+    Remove {
+        table_number: usize,
+        adjustment_id: u64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PaymentCommand {
+    /// show what the party at a table paid so far and what is still open
+    Show { table_number: usize },
+    /// pay towards the bill of a table
+    Pay {
+        table_number: usize,
+        /// cash, card or deferred
+        method: PaymentMethod,
+        /// in yen, everything still open without it
+        #[clap(long)]
+        amount: Option<u64>,
+    },
+    /// list all payments and refunds, or those of one session
+    List {
+        #[clap(long)]
+        session: Option<u64>,
+    },
+    /// pay a payment back
+    Refund {
+        payment_id: u64,
+        /// in yen, everything not refunded yet without it
+        #[clap(long)]
+        amount: Option<u64>,
+        #[clap(long)]
+        reason: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TablesCommand {
+    /// list all tables, including inactive ones
+    List,
+    /// add a table and print its number
+    Add {
+        /// i.e., "Terrace 3"
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(long)]
+        section: Option<String>,
+        /// how many guests it seats
+        #[clap(long)]
+        capacity: Option<u32>,
+    },
+    /// change a table, options not given stay as they are
+    Update {
+        table_number: usize,
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(long)]
+        section: Option<String>,
+        #[clap(long)]
+        capacity: Option<u32>,
+        /// take no new orders at the table
+        #[clap(long, conflicts_with = "activate")]
+        deactivate: bool,
+        /// take orders at the table again
+        #[clap(long)]
+        activate: bool,
+    },
+    /// remove a table without items
+    Remove { table_number: usize },
+}
+
+#[derive(Debug, Subcommand)]
+enum KeysCommand {
+    /// list all keys
+    List,
+    /// hand out a new key and print its secret
+    Create {
+        /// the device or person the key is for
+        name: String,
+        /// waiter, kitchen, manager or admin
+        role: Role,
+    },
+    /// revoke a key
+    Revoke { key_id: u64 },
+    /// give a key a new secret and print it
+    Rotate { key_id: u64 },
 }
-fn main() -> anyhow::Result<()> {
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let mut builder = RestaurantClient::builder()
+        .base_url(args.url)
+        .api_key(args.key);
+    if let Some(key_id) = args.key_id {
+        builder = builder.sign_with(key_id);
+    }
+    if let Some(path) = &args.ca_cert {
+        builder = builder.ca_bundle(read(path)?);
+    }
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        builder = builder.client_certificate(read(cert)?, read(key)?);
+    }
+    let client = builder.build()?;
+    let options = ListOptions {
+        limit: args.limit.map(|l| l as u64),
+        status: args.status,
+    };
 
+    // keys
+    if let Some(Command::Keys { command }) = args.command {
+        match command {
+            KeysCommand::List => {
+                for key in client.keys().await? {
+                    print_key(&key);
+                }
+            }
+            KeysCommand::Create { name, role } => {
+                print_issued_key(&client.create_key(&name, role).await?)
+            }
+            KeysCommand::Revoke { key_id } => {
+                println!("Revoked: {}", client.revoke_key(key_id).await?);
+            }
+            KeysCommand::Rotate { key_id } => print_issued_key(&client.rotate_key(key_id).await?),
+        }
+    // bills
+    } else if let Some(Command::Bill { command }) = args.command {
+        match command {
+            BillCommand::Show { table_number } => print_bill(&client.bill(table_number).await?),
+            BillCommand::Split {
+                table_number,
+                by_seat,
+                ways,
+                items,
+            } => {
+                let mode = match (by_seat, ways) {
+                    (true, _) => SplitMode::BySeat,
+                    (false, Some(ways)) => SplitMode::Even { ways },
+                    (false, None) if !items.is_empty() => SplitMode::ByItem {
+                        shares: items
+                            .iter()
+                            .map(|share| {
+                                share
+                                    .split(',')
+                                    .map(|id| id.trim().parse::<u64>())
+                                    .collect::<Result<Vec<u64>, _>>()
+                            })
+                            .collect::<Result<_, _>>()
+                            .context("Item ids are numbers separated by commas")?,
+                    },
+                    (false, None) => anyhow::bail!("Give --by-seat, --ways or --items"),
+                };
+                print_split(&client.split_bill(table_number, &mode).await?)
+            }
+            BillCommand::Discount {
+                table_number,
+                percent,
+                amount,
+                reason,
+            } => {
+                let kind = match (percent, amount) {
+                    (Some(percent), _) => AdjustmentKind::PercentOff { percent },
+                    (None, Some(amount)) => AdjustmentKind::AmountOff { amount },
+                    (None, None) => anyhow::bail!("Give either --percent or --amount"),
+                };
+                print_adjustment(
+                    &client
+                        .adjust_bill(table_number, &NewAdjustment { kind, reason })
+                        .await?,
+                )
+            }
+            BillCommand::Comp {
+                table_number,
+                item_id,
+                reason,
+            } => print_adjustment(
+                &client
+                    .adjust_bill(
+                        table_number,
+                        &NewAdjustment {
+                            kind: AdjustmentKind::Comp { item_id },
+                            reason,
+                        },
+                    )
+                    .await?,
+            ),
+            BillCommand::Remove {
+                table_number,
+                adjustment_id,
+            } => {
+                println!(
+                    "Removed: {}",
+                    client
+                        .remove_adjustment(table_number, adjustment_id)
+                        .await?
+                );
+            }
+        }
+    // payments
+    } else if let Some(Command::Payment { command }) = args.command {
+        match command {
+            PaymentCommand::Show { table_number } => {
+                print_bill_payments(&client.bill_payments(table_number).await?)
+            }
+            PaymentCommand::Pay {
+                table_number,
+                method,
+                amount,
+            } => print_payment(
+                &client
+                    .pay(table_number, &NewPayment { method, amount })
+                    .await?,
+            ),
+            PaymentCommand::List { session } => {
+                for payment in client.payments(session).await? {
+                    print_payment(&payment);
+                }
+            }
+            PaymentCommand::Refund {
+                payment_id,
+                amount,
+                reason,
+            } => print_payment(
+                &client
+                    .refund(payment_id, &NewRefund { amount, reason })
+                    .await?,
+            ),
+        }
+    // seat
+    } else if let Some(Command::Seat {
+        table_number,
+        item_id,
+        seat,
+    }) = args.command
+    {
+        print_item(&client.set_seat(table_number, item_id, seat).await?);
+    // move
+    } else if let Some(Command::Move { from, to, item_ids }) = args.command {
+        let moved = if item_ids.is_empty() {
+            client.merge_tables(from, to).await?
+        } else {
+            client.transfer_items(from, to, &item_ids).await?
+        };
+        println!("--------Moved to table {}----------", to);
+        for menu_item in moved.iter() {
+            print_item(menu_item);
+        }
+    // sessions
+    } else if let Some(Command::Session { command }) = args.command {
+        match command {
+            SessionCommand::Show { table_number } => match client.session(table_number).await? {
+                Some(session) => print_session_info(&session),
+                None => println!("Nobody is seated at table {}", table_number),
+            },
+            SessionCommand::Open {
+                table_number,
+                guests,
+                staff,
+            } => print_session_info(
+                &client
+                    .open_session(table_number, &SessionDetails { guests, staff })
+                    .await?,
+            ),
+            SessionCommand::Update {
+                table_number,
+                guests,
+                staff,
+            } => print_session_info(
+                &client
+                    .update_session(table_number, &SessionDetails { guests, staff })
+                    .await?,
+            ),
+            SessionCommand::Close { table_number } => {
+                print_session(&client.close_session(table_number).await?)
+            }
+            SessionCommand::History { table_number } => {
+                for session in client.sessions(table_number).await? {
+                    print_session(&session);
+                }
+            }
+        }
+    // floor plan
+    } else if let Some(Command::Tables { command }) = args.command {
+        match command {
+            TablesCommand::List => {
+                for table in client.floor_plan().await? {
+                    print_table_info(&table);
+                }
+            }
+            TablesCommand::Add {
+                name,
+                section,
+                capacity,
+            } => print_table_info(
+                &client
+                    .add_table(&NewTable {
+                        name,
+                        section,
+                        capacity,
+                    })
+                    .await?,
+            ),
+            TablesCommand::Update {
+                table_number,
+                name,
+                section,
+                capacity,
+                deactivate,
+                activate,
+            } => {
+                let mut table = client
+                    .floor_plan()
+                    .await?
+                    .into_iter()
+                    .find(|table| table.table_number == table_number)
+                    .with_context(|| format!("There is no table {}", table_number))?;
+                table.name = name.or(table.name);
+                table.section = section.or(table.section);
+                table.capacity = capacity.or(table.capacity);
+                table.active = (table.active || activate) && !deactivate;
+                print_table_info(&client.update_table(&table).await?);
+            }
+            TablesCommand::Remove { table_number } => {
+                println!("Removed: {}", client.remove_table(table_number).await?);
+            }
+        }
+    // kitchen
+    } else if let Some(Command::Kitchen { station, group }) = args.command {
+        if group {
+            for group in client.kitchen_queue_grouped(station.as_deref()).await? {
+                print_queue_group(&group);
+            }
+        } else {
+            for entry in client.kitchen_queue(station.as_deref()).await? {
+                print_queue_entry(&entry);
+            }
+        }
     // add
-    if let Some(mut add_vec) = args.add {
-        let menu_items = add_vec.split_off(1);
-        let cl = reqwest::blocking::Client::new();
-        cl.post(format!(
-            "http://127.0.0.1:3000/{}/?key={}",
-            add_vec.first().unwrap(),
-            API_KEY
-        ))
-        .json(&menu_items)
-        .send()?;
+    } else if let Some(mut add_vec) = args.add {
+        let menu_items = add_vec
+            .split_off(1)
+            .into_iter()
+            .map(|i| i as u64)
+            .collect::<Vec<u64>>();
+        let ids = match args.seat {
+            Some(seat) => {
+                client
+                    .add_items_for_seat(add_vec[0], seat, &menu_items)
+                    .await?
+            }
+            None => client.add_items(add_vec[0], &menu_items).await?,
+        };
+        println!("Added items with ids {:?}", ids);
     // delete
     } else if let Some(mut del_vec) = args.delete {
-        let menu_items = del_vec.split_off(1);
-        let cl = reqwest::blocking::Client::new();
-        for _ in menu_items {
-            cl.delete(format!(
-                "http://127.0.0.1:3000/{}/?key={}",
-                del_vec.first().unwrap(),
-                API_KEY
-            ))
-            .send()?;
+        let item_ids = del_vec.split_off(1);
+        for id in item_ids {
+            client.remove_item(del_vec[0], id as u64).await?;
         }
     // all
     } else if args.all {
-        let query_string = if let Some(l) = args.limit {
-            format!("http://127.0.0.1:3000/?key={}&limit={}", API_KEY, l)
-        } else {
-            format!("http://127.0.0.1:3000/?key={}", API_KEY,)
-        };
-        let tables = reqwest::blocking::get(query_string)?.json::<Vec<Table>>()?;
+        let tables = client.all_tables(options).await?;
         for i in tables {
             println!(
                 "--------Showing Items for table {}----------",
                 i.table_number
             );
-            for (index, menu_item) in i.items.iter().enumerate() {
-                println!(
-                    "{} | Item#: {} Time: {}",
-                    index, menu_item.item_number, menu_item.duration_in_minutes
-                );
+            for menu_item in i.items.iter() {
+                print_item(menu_item);
             }
         }
     // get
     } else if let Some(i) = args.get_table {
-        let query_string = if let Some(l) = args.limit {
-            format!("http://127.0.0.1:3000/{}/?key={}&limit={}", i, API_KEY, l)
-        } else {
-            format!("http://127.0.0.1:3000/{}/?key={}", i, API_KEY,)
-        };
-        let menu_items = reqwest::blocking::get(query_string)?.json::<Vec<MenuItem>>()?;
+        let menu_items = client.table(i, options).await?;
 
         println!("--------Showing Items for table {}----------", i);
-        for (index, menu_item) in menu_items.iter().enumerate() {
-            println!(
-                "{} | Item#: {} Time: {}",
-                index, menu_item.item_number, menu_item.duration_in_minutes
-            );
+        for menu_item in menu_items.iter() {
+            print_item(menu_item);
         }
-    } else if let Some(mut i) = args.get_item {
-        let menu_item = i.split_off(1);
-        let menu_item_number = menu_item.first().unwrap();
-        let table = i.first().unwrap();
-
-        let items = reqwest::blocking::get(format!(
-            "http://127.0.0.1:3000/{}/{}/?key={}",
-            table, menu_item_number, API_KEY,
-        ))?
-        .json::<Vec<MenuItem>>()?;
-        if let Some(item) = items.first() {
-            println!(
-                "| Item#: {} Time: {}",
-                item.item_number, item.duration_in_minutes
-            );
+    // status
+    } else if let Some(s) = args.set_status {
+        let item = client
+            .set_status(
+                s[0].parse()?,
+                s[1].parse()?,
+                s[2].parse().map_err(anyhow::Error::msg)?,
+            )
+            .await?;
+        print_item(&item);
+    } else if let Some(i) = args.get_item {
+        if let Some(item) = client.item(i[0], i[1] as u64).await? {
+            print_item(&item);
+        }
+    // menu
+    } else if args.menu {
+        for entry in client.menu().await? {
+            print_menu_entry(&entry);
         }
     }
 
     Ok(())
 }
+
+/// print a single item as one line
+fn print_item(menu_item: &MenuItem) {
+    println!(
+        "{} | Item#: {} Time: {} Status: {} Remaining: {} Ready at: {}{}",
+        menu_item.id,
+        menu_item.item_number,
+        menu_item.duration_in_minutes,
+        menu_item.status,
+        menu_item.remaining_minutes,
+        menu_item.ready_at.format("%H:%M:%S"),
+        menu_item
+            .seat
+            .map_or_else(String::new, |seat| format!(" Seat: {}", seat))
+    );
+}
+
+/// print a menu entry as one line
+fn print_menu_entry(entry: &MenuEntry) {
+    println!(
+        "{} | {} ({}) {} yen, {}-{} min{}",
+        entry.item_number,
+        entry.name,
+        entry.category,
+        entry.price,
+        entry.min_cook_minutes,
+        entry.max_cook_minutes,
+        if entry.available { "" } else { ", sold out" }
+    );
+}
+
+/// print an item of the kitchen queue as one line
+fn print_queue_entry(entry: &QueueEntry) {
+    println!(
+        "{} | Table: {} {} ({}) Status: {} Remaining: {}",
+        entry.item.id,
+        entry.table_number,
+        entry.name,
+        entry.station.as_deref().unwrap_or("-"),
+        entry.item.status,
+        entry.item.remaining_minutes
+    );
+}
+
+/// print a dish of the grouped kitchen queue as one line
+fn print_queue_group(group: &QueueGroup) {
+    println!(
+        "{} × {} ({}) waiting since {}",
+        group.count,
+        group.name,
+        group.station.as_deref().unwrap_or("-"),
+        group.oldest_ordered_at.format("%H:%M:%S")
+    );
+}
+
+/// print a session as one line
+fn print_session_info(session: &SessionInfo) {
+    println!(
+        "Session {} | {} guests, staff: {}, since {}",
+        session.id,
+        session
+            .guests
+            .map_or_else(|| "?".to_string(), |g| g.to_string()),
+        session.staff.as_deref().unwrap_or("-"),
+        session.opened_at.format("%H:%M:%S")
+    );
+}
+
+/// print a closed session followed by its items
+fn print_session(session: &Session) {
+    print_session_info(&session.info);
+    println!(
+        "--------Table {} closed at {}----------",
+        session.table_number,
+        session.closed_at.format("%H:%M:%S")
+    );
+    for menu_item in session.items.iter() {
+        print_item(menu_item);
+    }
+    if let Some(bill) = &session.bill {
+        print_bill(bill);
+    }
+}
+
+/// print a split bill, one line per share
+fn print_split(split: &SplitBill) {
+    println!(
+        "--------Session {} split {} ways, total ¥{}----------",
+        split.session_id,
+        split.shares.len(),
+        split.total
+    );
+    for share in split.shares.iter() {
+        println!(
+            "{} ¥{} items {:?}",
+            share
+                .seat
+                .map_or_else(|| "-".to_string(), |seat| format!("Seat {}", seat)),
+            share.amount,
+            share.item_ids
+        );
+    }
+    println!("Shared items {:?}", split.shared_item_ids);
+}
+
+/// print a discount or comp as one line
+fn print_adjustment(adjustment: &Adjustment) {
+    let what = match &adjustment.kind {
+        AdjustmentKind::PercentOff { percent } => format!("{}% off", percent),
+        AdjustmentKind::AmountOff { amount } => format!("¥{} off", amount),
+        AdjustmentKind::Comp { item_id } => format!("item {} on the house", item_id),
+    };
+    println!("{} | {} ({})", adjustment.id, what, adjustment.reason);
+}
+
+/// print a bill, one line per item followed by the adjustments and the totals
+fn print_bill(bill: &Bill) {
+    println!(
+        "--------Bill of table {}, session {}{}----------",
+        bill.table_number,
+        bill.session_id,
+        if bill.closed { ", final" } else { "" }
+    );
+    for line in bill.lines.iter() {
+        println!(
+            "{} | {} ¥{}{}",
+            line.item_id,
+            line.name,
+            line.price,
+            line.comped
+                .as_deref()
+                .map_or_else(String::new, |reason| format!(" comped: {}", reason))
+        );
+    }
+    for adjustment in bill.adjustments.iter() {
+        print_adjustment(adjustment);
+    }
+    println!("Subtotal       ¥{}", bill.subtotal);
+    println!("Discount      -¥{}", bill.discount);
+    println!("Service charge ¥{}", bill.service_charge);
+    println!("Tax            ¥{}", bill.tax);
+    println!("Total          ¥{}", bill.total);
+}
+
+/// print a payment or refund as one line
+fn print_payment(payment: &Payment) {
+    println!(
+        "{} | {} ¥{} {} {}{}{}",
+        payment.id,
+        payment
+            .refund_of
+            .map_or_else(|| "Payment".to_string(), |id| format!("Refund of {}", id)),
+        payment.amount,
+        payment.method,
+        payment.status,
+        payment
+            .error
+            .as_deref()
+            .map_or_else(String::new, |error| format!(": {}", error)),
+        payment
+            .reason
+            .as_deref()
+            .map_or_else(String::new, |reason| format!(" ({})", reason))
+    );
+}
+
+/// print what was paid of a bill and the payments
+fn print_bill_payments(payments: &BillPayments) {
+    println!(
+        "--------Payments of session {}----------",
+        payments.session_id
+    );
+    for payment in payments.payments.iter() {
+        print_payment(payment);
+    }
+    println!("Total   ¥{}", payments.total);
+    println!("Paid    ¥{}", payments.paid);
+    println!("Pending ¥{}", payments.pending);
+    println!("Open    ¥{}", payments.balance);
+}
+
+/// print a table of the floor plan as one line
+fn print_table_info(table: &TableInfo) {
+    println!(
+        "{} | {} ({}) seats {}{}",
+        table.table_number,
+        table.name.as_deref().unwrap_or("-"),
+        table.section.as_deref().unwrap_or("-"),
+        table
+            .capacity
+            .map_or_else(|| "-".to_string(), |c| c.to_string()),
+        if table.active { "" } else { ", inactive" }
+    );
+}
+
+/// print an api key as one line
+fn print_key(key: &KeyInfo) {
+    println!(
+        "{} | {} ({}) created {}",
+        key.id,
+        key.name,
+        key.role,
+        key.created_at.format("%Y-%m-%d %H:%M")
+    );
+}
+
+/// print a new secret, which cannot be looked up again
+fn print_issued_key(key: &IssuedKey) {
+    print_key(&key.info);
+    println!("Secret: {}", key.key);
+}
+
+/// read a certificate or key file
+fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))
+}
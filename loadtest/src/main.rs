@@ -1,25 +1,25 @@
 use goose::prelude::*;
+use restaurant_types::{routes, API_KEY};
 
-async fn loadtest_index(user: &mut GooseUser) -> TransactionResult {
-    let _goose_metrics = user.get("/?key=QXlj").await?;
-
-    Ok(())
+/// the path with our api key attached
+fn with_key(path: String) -> String {
+    format!("{}?key={}", path, API_KEY)
 }
 
 async fn loadtest_all(user: &mut GooseUser) -> TransactionResult {
-    let _goose_metrics = user.get("/?key=QXlj").await?;
+    let _goose_metrics = user.get(&with_key(routes::all_tables())).await?;
     Ok(())
 }
 
 async fn loadtest_query(user: &mut GooseUser) -> TransactionResult {
-    let _goose_metrics = user.get("/1/?key=QXlj").await?;
+    let _goose_metrics = user.get(&with_key(routes::table(1))).await?;
     Ok(())
 }
 
 async fn loadtest_fill(user: &mut GooseUser) -> TransactionResult {
     let json = &serde_json::json!(vec![1, 2, 3, 4, 7, 8, 9, 10]);
     for i in 1..50 {
-        let _goose_metrics = user.post_json(&format!("/{}/?key=QXlj", i), &json).await?;
+        let _goose_metrics = user.post_json(&with_key(routes::table(i)), &json).await?;
     }
     Ok(())
 }
@@ -29,7 +29,6 @@ async fn main() -> Result<(), GooseError> {
     GooseAttack::initialize()?
         .register_scenario(
             scenario!("LoadtestTransactions")
-                //  .register_transaction(transaction!(loadtest_index))
                 .register_transaction(transaction!(loadtest_fill).set_on_start())
                 .register_transaction(transaction!(loadtest_query))
                 .register_transaction(transaction!(loadtest_all)),
@@ -0,0 +1,940 @@
+//! A typed async client for the restaurant api.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), restaurant_sdk::Error> {
+//! let client = restaurant_sdk::RestaurantClient::builder()
+//!     .base_url("http://127.0.0.1:3000")
+//!     .build()?;
+//! let ids = client.add_items(4, &[1, 2, 3]).await?;
+//! client.remove_item(4, ids[0]).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The api key is sent in the `Authorization` header. With [`ClientBuilder::sign_with`] every request is signed
+//! with it instead, so the key itself never goes over the wire.
+//!
+//! Every changing request carries a fresh `Idempotency-Key`, the same for all of its attempts, so the server
+//! answers a retry with the answer to the first attempt and nothing is ordered or removed twice.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use reqwest::{header::AUTHORIZATION, Method, StatusCode};
+use restaurant_types::{
+    routes,
+    signing::{self, SignatureHeader},
+    Adjustment, Bill, BillPayments, ErrorBody, IssuedKey, ItemStatus, ItemTransfer, KeyInfo,
+    KitchenQuery, MenuEntry, MenuItem, NewAdjustment, NewKey, NewPayment, NewRefund, NewTable,
+    OrderQuery, Page, Payment, PaymentQuery, QueryParam, QueueEntry, QueueGroup, Role, SeatUpdate,
+    Session, SessionDetails, SessionInfo, Snapshot, SortKey, SplitBill, SplitMode, StatusUpdate,
+    Table, TableInfo, API_KEY, IDEMPOTENCY_KEY,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+mod tests;
+
+/// where the server listens if nothing else is configured
+pub static DEFAULT_BASE_URL: &str = "http://127.0.0.1:3000";
+
+#[derive(Debug, thiserror::Error)]
+/// Why a request failed
+pub enum Error {
+    #[error("the api key was rejected")]
+    Unauthorized,
+    #[error("the api key cannot be sent in a header")]
+    InvalidKey,
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("server error {status}: {message}")]
+    Server { status: u16, message: String },
+    #[error("request timed out")]
+    Timeout,
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+impl Error {
+    /// if sending the same request again might succeed
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout | Error::Server { .. } => true,
+            Error::Http(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Narrows down what the listing calls return
+pub struct ListOptions {
+    /// at most this many entries
+    pub limit: Option<u64>,
+    /// only items with this status
+    pub status: Option<ItemStatus>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Which page the paged listing calls return. Their [`ListOptions::limit`] is the page size.
+pub struct PageOptions {
+    /// the `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// skip this many entries
+    pub offset: Option<u64>,
+    pub sort: Option<SortKey>,
+}
+
+#[derive(Debug, Clone)]
+/// Configures a [`RestaurantClient`]
+pub struct ClientBuilder {
+    base_url: String,
+    api_key: String,
+    key_id: Option<u64>,
+    /// PEM encoded certificates to trust besides the system's
+    ca_bundle: Option<Vec<u8>>,
+    /// PEM encoded certificate and key to present to the server
+    client_certificate: Option<(Vec<u8>, Vec<u8>)>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: API_KEY.to_string(),
+            key_id: None,
+            ca_bundle: None,
+            client_certificate: None,
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// the url of the server without a trailing slash, i.e., `http://127.0.0.1:3000`
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// the key we authenticate with
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = api_key.into();
+        self
+    }
+
+    /// sign every request with the api key instead of sending it, `key_id` is the id the server lists it under
+    pub fn sign_with(mut self, key_id: u64) -> Self {
+        self.key_id = Some(key_id);
+        self
+    }
+
+    /// also trust the certificates in this PEM bundle, i.e., the CA a self hosted server's certificate comes from
+    pub fn ca_bundle(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_bundle = Some(pem.into());
+        self
+    }
+
+    /// present this PEM encoded certificate with its PKCS#8 key, for servers only talking to enrolled tablets
+    pub fn client_certificate(mut self, cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        self.client_certificate = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// how long a single attempt may take
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// how often a request is repeated after a timeout, connection or server error
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// how long we wait before the first retry, doubled for every further one
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fails with [`Error::InvalidKey`] if the api key has characters a header cannot carry
+    /// and with [`Error::Http`] for certificates that cannot be parsed
+    pub fn build(self) -> Result<RestaurantClient, Error> {
+        if reqwest::header::HeaderValue::from_str(&self.api_key).is_err() {
+            return Err(Error::InvalidKey);
+        }
+        let mut http = reqwest::Client::builder().timeout(self.timeout);
+        if let Some(pem) = &self.ca_bundle {
+            for cert in reqwest::Certificate::from_pem_bundle(pem)? {
+                http = http.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_certificate {
+            http = http.identity(reqwest::Identity::from_pkcs8_pem(cert, key)?);
+        }
+        let http = http.build()?;
+        Ok(RestaurantClient {
+            http,
+            base_url: self.base_url,
+            key_id: self.key_id,
+            api_key: self.api_key,
+            retries: self.retries,
+            backoff: self.backoff,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A client for the restaurant api. Cloning is cheap and shares the connection pool.
+pub struct RestaurantClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    /// sign requests instead of sending the key
+    key_id: Option<u64>,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RestaurantClient {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Add items with the given menu numbers to a table and return the ids the server assigned.
+    /// Fails with [`Error::BadRequest`] if any of them is not on the menu or not available.
+    pub async fn add_items(
+        &self,
+        table_number: usize,
+        item_numbers: &[u64],
+    ) -> Result<Vec<u64>, Error> {
+        self.request(
+            Method::POST,
+            &routes::table(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(item_numbers),
+        )
+        .await
+    }
+
+    /// Like [`RestaurantClient::add_items`], all of them for the guest at `seat`, counting from 1
+    pub async fn add_items_for_seat(
+        &self,
+        table_number: usize,
+        seat: u32,
+        item_numbers: &[u64],
+    ) -> Result<Vec<u64>, Error> {
+        self.request(
+            Method::POST,
+            &routes::table(table_number),
+            &OrderQuery {
+                key: None,
+                seat: Some(seat),
+            },
+            Some(item_numbers),
+        )
+        .await
+    }
+
+    /// Move an item to the guest at `seat`, or to the whole table without one, and return the updated item
+    pub async fn set_seat(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        seat: Option<u32>,
+    ) -> Result<MenuItem, Error> {
+        self.request(
+            Method::PUT,
+            &routes::item_seat(table_number, item_id),
+            &self.list_query(ListOptions::default()),
+            Some(&SeatUpdate { seat }),
+        )
+        .await
+    }
+
+    /// Remove an item from a table. Returns if there was such an item.
+    pub async fn remove_item(&self, table_number: usize, item_id: u64) -> Result<bool, Error> {
+        self.request::<bool, ()>(
+            Method::DELETE,
+            &routes::item(table_number, item_id),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Move the items `item_ids` from one table to another, keeping their ids and cook times, and return them.
+    /// Fails with [`Error::NotFound`] without moving anything if one of them is not on the table.
+    pub async fn transfer_items(
+        &self,
+        from: usize,
+        to: usize,
+        item_ids: &[u64],
+    ) -> Result<Vec<MenuItem>, Error> {
+        self.transfer(from, to, Some(item_ids.to_vec())).await
+    }
+
+    /// Move all items from one table to another, i.e., when two parties join, and return them
+    pub async fn merge_tables(&self, from: usize, to: usize) -> Result<Vec<MenuItem>, Error> {
+        self.transfer(from, to, None).await
+    }
+
+    async fn transfer(
+        &self,
+        from: usize,
+        to: usize,
+        item_ids: Option<Vec<u64>>,
+    ) -> Result<Vec<MenuItem>, Error> {
+        self.request(
+            Method::POST,
+            &routes::table_transfer(from),
+            &self.list_query(ListOptions::default()),
+            Some(&ItemTransfer { to, item_ids }),
+        )
+        .await
+    }
+
+    /// Move an item to a new status and return the updated item.
+    /// Fails with [`Error::Conflict`] if the item cannot go there from its current status.
+    pub async fn set_status(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        status: ItemStatus,
+    ) -> Result<MenuItem, Error> {
+        self.request(
+            Method::PATCH,
+            &routes::item(table_number, item_id),
+            &self.list_query(ListOptions::default()),
+            Some(&StatusUpdate { status }),
+        )
+        .await
+    }
+
+    /// The items of a table
+    pub async fn table(
+        &self,
+        table_number: usize,
+        options: ListOptions,
+    ) -> Result<Vec<MenuItem>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::table(table_number),
+            &self.list_query(options),
+            None,
+        )
+        .await
+    }
+
+    /// A single item of a table, if it exists
+    pub async fn item(&self, table_number: usize, item_id: u64) -> Result<Option<MenuItem>, Error> {
+        let items: Vec<MenuItem> = self
+            .request::<_, ()>(
+                Method::GET,
+                &routes::item(table_number, item_id),
+                &self.list_query(ListOptions::default()),
+                None,
+            )
+            .await?;
+        Ok(items.into_iter().next())
+    }
+
+    /// All tables that have (matching) items. The `limit` applies to the tables returned, empty ones do not count.
+    pub async fn all_tables(&self, options: ListOptions) -> Result<Vec<Table>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::all_tables(),
+            &self.list_query(options),
+            None,
+        )
+        .await
+    }
+
+    /// A page of the tables that have (matching) items
+    pub async fn all_tables_page(
+        &self,
+        options: ListOptions,
+        page: PageOptions,
+    ) -> Result<Page<Table>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::all_tables(),
+            &self.page_query(options, page),
+            None,
+        )
+        .await
+    }
+
+    /// A page of the (matching) items of a table
+    pub async fn table_page(
+        &self,
+        table_number: usize,
+        options: ListOptions,
+        page: PageOptions,
+    ) -> Result<Page<MenuItem>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::table(table_number),
+            &self.page_query(options, page),
+            None,
+        )
+        .await
+    }
+
+    /// Like [`RestaurantClient::all_tables`], but read at a single point in time and tagged with its version.
+    /// Two snapshots with the same version and options are identical, so their countdown is not updated.
+    pub async fn snapshot(&self, options: ListOptions) -> Result<Snapshot, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::all_tables(),
+            &QueryParam {
+                snapshot: true,
+                ..self.list_query(options)
+            },
+            None,
+        )
+        .await
+    }
+
+    /// The whole menu, including entries that are currently not available
+    pub async fn menu(&self) -> Result<Vec<MenuEntry>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::menu(),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// The menu entry for `item_number`, if there is one
+    pub async fn menu_entry(&self, item_number: u64) -> Result<Option<MenuEntry>, Error> {
+        let result = self
+            .request::<_, ()>(
+                Method::GET,
+                &routes::menu_entry(item_number),
+                &self.list_query(ListOptions::default()),
+                None,
+            )
+            .await;
+        match result {
+            Ok(entry) => Ok(Some(entry)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Put a new entry on the menu.
+    /// Fails with [`Error::Conflict`] if there already is an entry with its item number.
+    pub async fn add_menu_entry(&self, entry: &MenuEntry) -> Result<MenuEntry, Error> {
+        self.request(
+            Method::POST,
+            &routes::menu(),
+            &self.list_query(ListOptions::default()),
+            Some(entry),
+        )
+        .await
+    }
+
+    /// Replace the menu entry with the same item number, i.e., to mark it as sold out
+    pub async fn replace_menu_entry(&self, entry: &MenuEntry) -> Result<MenuEntry, Error> {
+        self.request(
+            Method::PUT,
+            &routes::menu_entry(entry.item_number),
+            &self.list_query(ListOptions::default()),
+            Some(entry),
+        )
+        .await
+    }
+
+    /// Take an entry off the menu. Returns if there was such an entry.
+    pub async fn remove_menu_entry(&self, item_number: u64) -> Result<bool, Error> {
+        self.request::<bool, ()>(
+            Method::DELETE,
+            &routes::menu_entry(item_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// The session of the party seated at a table, `None` if nobody is seated
+    pub async fn session(&self, table_number: usize) -> Result<Option<SessionInfo>, Error> {
+        let result = self
+            .request::<_, ()>(
+                Method::GET,
+                &routes::session(table_number),
+                &self.list_query(ListOptions::default()),
+                None,
+            )
+            .await;
+        match result {
+            Ok(session) => Ok(Some(session)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Seat a party at a table. Adding items to a table nobody is seated at does so as well.
+    /// Fails with [`Error::Conflict`] if somebody is already seated there.
+    pub async fn open_session(
+        &self,
+        table_number: usize,
+        details: &SessionDetails,
+    ) -> Result<SessionInfo, Error> {
+        self.request(
+            Method::POST,
+            &routes::session(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(details),
+        )
+        .await
+    }
+
+    /// Change the guest count or the staff of the session at a table, the details left out stay as they are
+    pub async fn update_session(
+        &self,
+        table_number: usize,
+        details: &SessionDetails,
+    ) -> Result<SessionInfo, Error> {
+        self.request(
+            Method::PATCH,
+            &routes::session(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(details),
+        )
+        .await
+    }
+
+    /// Close the session at a table and return it with its items, the table is empty afterwards.
+    /// Fails with [`Error::Conflict`] while an item is neither served nor cancelled.
+    pub async fn close_session(&self, table_number: usize) -> Result<Session, Error> {
+        self.request::<_, ()>(
+            Method::DELETE,
+            &routes::session(table_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// The closed sessions of a table, oldest first
+    pub async fn sessions(&self, table_number: usize) -> Result<Vec<Session>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::sessions(table_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// The bill of the party seated at a table as it would be if they left now.
+    /// Fails with [`Error::NotFound`] if nobody is seated there, final bills come with [`RestaurantClient::sessions`].
+    pub async fn bill(&self, table_number: usize) -> Result<Bill, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::bill(table_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Split the bill of a table by seat, by item or evenly. The shares add up to the bill's total.
+    pub async fn split_bill(
+        &self,
+        table_number: usize,
+        mode: &SplitMode,
+    ) -> Result<SplitBill, Error> {
+        self.request(
+            Method::POST,
+            &routes::bill_split(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(mode),
+        )
+        .await
+    }
+
+    /// Put a discount or comp on the bill of a table. Needs a manager key.
+    /// Not retried, as sending it twice would discount twice.
+    pub async fn adjust_bill(
+        &self,
+        table_number: usize,
+        adjustment: &NewAdjustment,
+    ) -> Result<Adjustment, Error> {
+        self.request(
+            Method::POST,
+            &routes::bill_adjustments(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(adjustment),
+        )
+        .await
+    }
+
+    /// Take a discount or comp off the bill of a table again. Returns if there was such an adjustment.
+    pub async fn remove_adjustment(
+        &self,
+        table_number: usize,
+        adjustment_id: u64,
+    ) -> Result<bool, Error> {
+        self.request::<bool, ()>(
+            Method::DELETE,
+            &routes::bill_adjustment(table_number, adjustment_id),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// What the party at a table paid so far and what is still open
+    pub async fn bill_payments(&self, table_number: usize) -> Result<BillPayments, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::bill_payments(table_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Pay towards the bill of a table. A card or deferred payment the provider has not decided on yet
+    /// comes back pending. Not retried, as sending it twice would pay twice.
+    pub async fn pay(&self, table_number: usize, payment: &NewPayment) -> Result<Payment, Error> {
+        self.request(
+            Method::POST,
+            &routes::bill_payments(table_number),
+            &self.list_query(ListOptions::default()),
+            Some(payment),
+        )
+        .await
+    }
+
+    /// All payments and refunds, or those of one session
+    pub async fn payments(&self, session_id: Option<u64>) -> Result<Vec<Payment>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::payments(),
+            &PaymentQuery {
+                key: None,
+                session_id,
+            },
+            None,
+        )
+        .await
+    }
+
+    /// A single payment or refund by its id
+    pub async fn payment(&self, payment_id: u64) -> Result<Payment, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::payment(payment_id),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Pay back a payment, fully or in part, and return the refund. Needs a manager key.
+    /// Not retried, as sending it twice would refund twice.
+    pub async fn refund(&self, payment_id: u64, refund: &NewRefund) -> Result<Payment, Error> {
+        self.request(
+            Method::POST,
+            &routes::payment_refund(payment_id),
+            &self.list_query(ListOptions::default()),
+            Some(refund),
+        )
+        .await
+    }
+
+    /// The floor plan, all tables including inactive ones
+    pub async fn floor_plan(&self) -> Result<Vec<TableInfo>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::tables(),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Add an empty table, the server picks its number. Needs a manager key.
+    /// Not retried, as sending it twice would add two tables.
+    pub async fn add_table(&self, table: &NewTable) -> Result<TableInfo, Error> {
+        self.request(
+            Method::POST,
+            &routes::tables(),
+            &self.list_query(ListOptions::default()),
+            Some(table),
+        )
+        .await
+    }
+
+    /// Replace the floor plan entry of the table with the same number, i.e., to rename or deactivate it
+    pub async fn update_table(&self, table: &TableInfo) -> Result<TableInfo, Error> {
+        self.request(
+            Method::PUT,
+            &routes::table_info(table.table_number),
+            &self.list_query(ListOptions::default()),
+            Some(table),
+        )
+        .await
+    }
+
+    /// Take a table off the floor plan. Returns if there was such a table.
+    /// Fails with [`Error::Conflict`] while it still has items.
+    pub async fn remove_table(&self, table_number: usize) -> Result<bool, Error> {
+        self.request::<bool, ()>(
+            Method::DELETE,
+            &routes::table_info(table_number),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// All api keys, without their secrets. Needs an admin key.
+    pub async fn keys(&self) -> Result<Vec<KeyInfo>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::keys(),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Hand out a new api key. The returned secret cannot be looked up again later.
+    /// Not retried, as sending it twice would create two keys.
+    pub async fn create_key(&self, name: &str, role: Role) -> Result<IssuedKey, Error> {
+        self.request(
+            Method::POST,
+            &routes::keys(),
+            &self.list_query(ListOptions::default()),
+            Some(&NewKey {
+                name: name.to_string(),
+                role,
+            }),
+        )
+        .await
+    }
+
+    /// Revoke an api key. Returns if there was such a key.
+    /// Fails with [`Error::Conflict`] for the last admin key.
+    pub async fn revoke_key(&self, key_id: u64) -> Result<bool, Error> {
+        self.request::<bool, ()>(
+            Method::DELETE,
+            &routes::key(key_id),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// Give an api key a new secret, the old one stops working at once.
+    /// Not retried, a second rotation would replace the secret we never got to see.
+    pub async fn rotate_key(&self, key_id: u64) -> Result<IssuedKey, Error> {
+        self.request::<_, ()>(
+            Method::POST,
+            &routes::key_rotate(key_id),
+            &self.list_query(ListOptions::default()),
+            None,
+        )
+        .await
+    }
+
+    /// What the kitchen still has to cook, the next item first. With a `station` only the items cooked there.
+    pub async fn kitchen_queue(&self, station: Option<&str>) -> Result<Vec<QueueEntry>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::kitchen_queue(),
+            &self.kitchen_query(station, false),
+            None,
+        )
+        .await
+    }
+
+    /// Like [`RestaurantClient::kitchen_queue`] with one entry per dish, i.e., "7 × Karaage"
+    pub async fn kitchen_queue_grouped(
+        &self,
+        station: Option<&str>,
+    ) -> Result<Vec<QueueGroup>, Error> {
+        self.request::<_, ()>(
+            Method::GET,
+            &routes::kitchen_queue(),
+            &self.kitchen_query(station, true),
+            None,
+        )
+        .await
+    }
+
+    fn list_query(&self, options: ListOptions) -> QueryParam {
+        QueryParam {
+            key: None,
+            limit: options.limit,
+            status: options.status,
+            snapshot: false,
+            paged: false,
+            cursor: None,
+            offset: None,
+            sort: None,
+        }
+    }
+
+    fn page_query(&self, options: ListOptions, page: PageOptions) -> QueryParam {
+        QueryParam {
+            paged: true,
+            cursor: page.cursor,
+            offset: page.offset,
+            sort: page.sort,
+            ..self.list_query(options)
+        }
+    }
+
+    fn kitchen_query(&self, station: Option<&str>, group: bool) -> KitchenQuery {
+        KitchenQuery {
+            key: None,
+            station: station.map(str::to_string),
+            group,
+        }
+    }
+
+    /// Send a request, retrying transient failures. Changing requests get an idempotency key for all attempts.
+    async fn request<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &impl Serialize,
+        body: Option<&B>,
+    ) -> Result<T, Error> {
+        let idempotency_key = (method != Method::GET).then(hex_nonce);
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match self
+                .send(
+                    method.clone(),
+                    path,
+                    query,
+                    body,
+                    idempotency_key.as_deref(),
+                )
+                .await
+            {
+                Err(e) if attempt < self.retries && e.is_transient() => {
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The value of the `Authorization` header for `request`
+    fn authorization(&self, request: &reqwest::Request) -> String {
+        let Some(key_id) = self.key_id else {
+            return format!("{} {}", signing::BEARER_SCHEME, self.api_key);
+        };
+        let url = request.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap_or(&[]);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        // every attempt gets a fresh nonce, the server refuses one it has seen already
+        let nonce = hex_nonce();
+        let canonical =
+            signing::canonical_request(request.method().as_str(), &path, body, timestamp, &nonce);
+        SignatureHeader {
+            key_id,
+            timestamp,
+            signature: signing::sign(&signing::signing_key(&self.api_key), &canonical),
+            nonce,
+        }
+        .to_string()
+    }
+
+    /// Send a request once and decode the answer
+    async fn send<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &impl Serialize,
+        body: Option<&B>,
+        idempotency_key: Option<&str>,
+    ) -> Result<T, Error> {
+        let mut request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path))
+            .query(query);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        if let Some(key) = idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY, key);
+        }
+        let mut request = request.build()?;
+        let authorization = self
+            .authorization(&request)
+            .parse()
+            .map_err(|_| Error::InvalidKey)?;
+        request.headers_mut().insert(AUTHORIZATION, authorization);
+        let response = self.http.execute(request).await.map_err(from_reqwest)?;
+        let status = response.status();
+        if status.is_success() {
+            return response.json().await.map_err(from_reqwest);
+        }
+
+        let text = response.text().await.map_err(from_reqwest)?;
+        let message = serde_json::from_str::<ErrorBody>(&text)
+            .map(|body| body.message)
+            .unwrap_or(text);
+        Err(match status {
+            StatusCode::UNAUTHORIZED => Error::Unauthorized,
+            StatusCode::FORBIDDEN => Error::Forbidden(message),
+            StatusCode::NOT_FOUND => Error::NotFound(message),
+            StatusCode::CONFLICT => Error::Conflict(message),
+            s if s.is_client_error() => Error::BadRequest(message),
+            s => Error::Server {
+                status: s.as_u16(),
+                message,
+            },
+        })
+    }
+}
+
+/// distinguish timeouts from other transport errors
+fn from_reqwest(e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        Error::Timeout
+    } else {
+        Error::Http(e)
+    }
+}
+
+/// 16 random bytes, hex encoded
+fn hex_nonce() -> String {
+    rand::thread_rng()
+        .gen::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
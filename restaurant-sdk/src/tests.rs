@@ -0,0 +1,429 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::Query,
+        http::{header::AUTHORIZATION, HeaderMap, Method, StatusCode, Uri},
+        routing::{any, get},
+        Json, Router,
+    };
+    use restaurant_types::{
+        signing::{self, SignatureHeader},
+        ErrorBody, ErrorKind, ItemStatus, MenuItem, Page, QueryParam, IDEMPOTENCY_KEY,
+    };
+
+    use crate::{ClientBuilder, Error, ListOptions, PageOptions, RestaurantClient};
+
+    /// helper function that serves `router` on a free port and returns a client for it
+    async fn serve(router: Router) -> RestaurantClient {
+        serve_with(router, RestaurantClient::builder()).await
+    }
+
+    /// helper function that serves `router` on a free port and returns a client for it built by `builder`
+    async fn serve_with(router: Router, builder: ClientBuilder) -> RestaurantClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        builder
+            .base_url(format!("http://{}", address))
+            .backoff(Duration::from_millis(1))
+            .build()
+            .unwrap()
+    }
+
+    /// helper function that answers every request with the `Authorization` header and what was signed
+    async fn echo_authorization(
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Json<Vec<String>> {
+        let authorization = headers
+            .get(AUTHORIZATION)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        Json(vec![
+            authorization,
+            method.to_string(),
+            uri.to_string(),
+            String::from_utf8(body.to_vec()).unwrap(),
+        ])
+    }
+
+    /// helper function that answers like the server does on failure
+    fn error(status: StatusCode, kind: ErrorKind) -> (StatusCode, Json<ErrorBody>) {
+        (
+            status,
+            Json(ErrorBody {
+                error: kind,
+                message: "went wrong".to_string(),
+            }),
+        )
+    }
+
+    #[tokio::test]
+    /// test that a successful answer is decoded
+    async fn decodes_items() {
+        let item = MenuItem {
+            id: 7,
+            item_number: 3,
+            duration_in_minutes: 5,
+            ordered_at: Default::default(),
+            ready_at: Default::default(),
+            remaining_minutes: 2,
+            status: ItemStatus::Cooking,
+            status_history: vec![],
+            session_id: None,
+            price: None,
+            seat: None,
+        };
+        let answer = item.clone();
+        let client = serve(Router::new().route(
+            "/1/",
+            get(move || async move { Json(vec![answer.clone()]) }),
+        ))
+        .await;
+        assert_eq!(
+            client.table(1, ListOptions::default()).await.unwrap(),
+            vec![item]
+        );
+    }
+
+    #[tokio::test]
+    /// test that the key is sent as bearer token and never in the query
+    async fn sends_bearer_key() {
+        let client = serve_with(
+            Router::new().route("/1/", any(echo_authorization)),
+            RestaurantClient::builder().api_key("secret"),
+        )
+        .await;
+        let echo = client
+            .request::<Vec<String>, ()>(
+                Method::GET,
+                "/1/",
+                &client.list_query(ListOptions::default()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(echo[0], "Bearer secret");
+        assert!(!echo[2].contains("key"));
+    }
+
+    #[tokio::test]
+    /// test that signed requests carry a valid signature over what was sent and a fresh nonce each
+    async fn signs_requests() {
+        let client = serve_with(
+            Router::new().route("/1/", any(echo_authorization)),
+            RestaurantClient::builder().api_key("secret").sign_with(7),
+        )
+        .await;
+        let options = ListOptions {
+            limit: Some(2),
+            status: None,
+        };
+        let mut nonces = vec![];
+        for _ in 0..2 {
+            let echo = client
+                .request::<Vec<String>, _>(
+                    Method::PATCH,
+                    "/1/",
+                    &client.list_query(options),
+                    Some(&[1, 2]),
+                )
+                .await
+                .unwrap();
+            let header = echo[0].parse::<SignatureHeader>().unwrap();
+            assert_eq!(header.key_id, 7);
+            assert_eq!(echo[2], "/1/?limit=2&snapshot=false&paged=false");
+            let canonical = signing::canonical_request(
+                &echo[1],
+                &echo[2],
+                echo[3].as_bytes(),
+                header.timestamp,
+                &header.nonce,
+            );
+            assert!(signing::verify(
+                &signing::signing_key("secret"),
+                &canonical,
+                &header.signature
+            ));
+            assert!(!echo[0].contains("secret"));
+            nonces.push(header.nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+    }
+
+    #[tokio::test]
+    /// test that status codes become typed errors
+    async fn maps_status_codes() {
+        let client = serve(
+            Router::new()
+                .route(
+                    "/1/",
+                    get(|| async { error(StatusCode::UNAUTHORIZED, ErrorKind::Unauthorized) }),
+                )
+                .route(
+                    "/2/",
+                    get(|| async { error(StatusCode::NOT_FOUND, ErrorKind::NotFound) }),
+                )
+                .route(
+                    "/3/",
+                    get(|| async { error(StatusCode::FORBIDDEN, ErrorKind::Forbidden) }),
+                ),
+        )
+        .await;
+        assert!(matches!(
+            client.table(1, ListOptions::default()).await,
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            client.table(2, ListOptions::default()).await,
+            Err(Error::NotFound(message)) if message == "went wrong"
+        ));
+        assert!(matches!(
+            client.table(3, ListOptions::default()).await,
+            Err(Error::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    /// test that an illegal status change becomes a conflict
+    async fn maps_conflict() {
+        let client = serve(Router::new().route(
+            "/1/items/3",
+            axum::routing::patch(|| async { error(StatusCode::CONFLICT, ErrorKind::Conflict) }),
+        ))
+        .await;
+        assert!(matches!(
+            client.set_status(1, 3, ItemStatus::Cooking).await,
+            Err(Error::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    /// test that a missing menu entry is not an error
+    async fn missing_menu_entry() {
+        let client = serve(Router::new().route(
+            "/menu/9",
+            get(|| async { error(StatusCode::NOT_FOUND, ErrorKind::NotFound) }),
+        ))
+        .await;
+        assert!(matches!(client.menu_entry(9).await, Ok(None)));
+    }
+
+    #[tokio::test]
+    /// test that a paged listing asks for a page and passes the cursor on
+    async fn pages_with_cursor() {
+        let client = serve(Router::new().route(
+            "/1/",
+            get(|Query(query): Query<QueryParam>| async move {
+                Json(Page::<MenuItem> {
+                    items: vec![],
+                    next_cursor: query.paged.then(|| format!("after-{:?}", query.cursor)),
+                    total: query.limit.unwrap_or(0),
+                })
+            }),
+        ))
+        .await;
+        let page = client
+            .table_page(
+                1,
+                ListOptions {
+                    limit: Some(3),
+                    status: None,
+                },
+                PageOptions {
+                    cursor: Some("abc".to_string()),
+                    ..PageOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.next_cursor.as_deref(), Some("after-Some(\"abc\")"));
+        assert_eq!(page.total, 3);
+    }
+
+    #[tokio::test]
+    /// test that idempotent requests are retried after a server error
+    async fn retries_get() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let client = serve(Router::new().route(
+            "/1/",
+            get(move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorKind::Internal,
+                    ))
+                } else {
+                    Ok(Json(Vec::<MenuItem>::new()))
+                }
+            }),
+        ))
+        .await;
+        assert!(client
+            .table(1, ListOptions::default())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    /// test that adding items is retried with the same idempotency key, and a new order gets a new one
+    async fn retries_post_with_the_same_key() {
+        let keys = Arc::new(Mutex::new(vec![]));
+        let seen = keys.clone();
+        let client = serve(Router::new().route(
+            "/1/",
+            axum::routing::post(move |headers: HeaderMap| async move {
+                let mut seen = seen.lock().unwrap();
+                seen.push(headers[IDEMPOTENCY_KEY].to_str().unwrap().to_string());
+                if seen.len() == 1 {
+                    Err(error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorKind::Internal,
+                    ))
+                } else {
+                    Ok(Json(vec![seen.len() as u64]))
+                }
+            }),
+        ))
+        .await;
+        assert_eq!(client.add_items(1, &[1]).await.unwrap(), vec![2]);
+        assert_eq!(client.add_items(1, &[1]).await.unwrap(), vec![3]);
+        let keys = keys.lock().unwrap();
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+    }
+
+    #[tokio::test]
+    /// test that a slow server results in a timeout
+    async fn times_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = Router::new().route(
+            "/1/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Json(Vec::<MenuItem>::new())
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let client = RestaurantClient::builder()
+            .base_url(format!("http://{}", address))
+            .timeout(Duration::from_millis(50))
+            .retries(0)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            client.table(1, ListOptions::default()).await,
+            Err(Error::Timeout)
+        ));
+    }
+
+    /// helper function that issues a certificate for `127.0.0.1` with `usage` signed by `ca`, as PEM
+    fn issue(
+        ca: &rcgen::Certificate,
+        ca_key: &rcgen::KeyPair,
+        usage: rcgen::ExtendedKeyUsagePurpose,
+    ) -> (String, String) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        // openssl takes a certificate named like its issuer for self signed
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "127.0.0.1");
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    #[tokio::test]
+    /// test talking to a server with a self signed CA that only lets clients with a certificate in
+    async fn mutual_tls() {
+        use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+        use rustls::{
+            crypto::ring,
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+            server::WebPkiClientVerifier,
+            RootCertStore, ServerConfig,
+        };
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let (server_cert, server_key) = issue(&ca, &ca_key, ExtendedKeyUsagePurpose::ServerAuth);
+        let (client_cert, client_key) = issue(&ca, &ca_key, ExtendedKeyUsagePurpose::ClientAuth);
+
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![CertificateDer::from_pem_slice(server_cert.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(server_key.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        let handle = axum_server::Handle::new();
+        let router = Router::new().route("/1/", get(|| async { Json(Vec::<MenuItem>::new()) }));
+        tokio::spawn(
+            axum_server::bind_rustls(
+                "127.0.0.1:0".parse().unwrap(),
+                axum_server::tls_rustls::RustlsConfig::from_config(Arc::new(config)),
+            )
+            .handle(handle.clone())
+            .serve(router.into_make_service()),
+        );
+        let base_url = format!("https://{}", handle.listening().await.unwrap());
+
+        let builder = RestaurantClient::builder()
+            .base_url(&base_url)
+            .retries(0)
+            .ca_bundle(ca.pem());
+        let enrolled = builder
+            .clone()
+            .client_certificate(client_cert, client_key)
+            .build()
+            .unwrap();
+        assert!(enrolled
+            .table(1, ListOptions::default())
+            .await
+            .unwrap()
+            .is_empty());
+        let stranger = builder.build().unwrap();
+        assert!(matches!(
+            stranger.table(1, ListOptions::default()).await,
+            Err(Error::Http(_))
+        ));
+        let untrusting = RestaurantClient::builder()
+            .base_url(&base_url)
+            .retries(0)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            untrusting.table(1, ListOptions::default()).await,
+            Err(Error::Http(_))
+        ));
+    }
+}
@@ -0,0 +1,843 @@
+//! The types the restaurant server, the client and the loadtest exchange over the wire.
+//! Changing anything here breaks compilation of every binary using it, instead of failing at runtime.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod routes;
+pub mod signing;
+
+/// the development key. A server without keys of its own starts with it as its admin key, rotate it in production.
+pub static API_KEY: &str = "QXlj";
+
+/// the header a client sends the same key in with every attempt of a changing request,
+/// so the server answers a retry with the first answer instead of doing it again
+pub static IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// set to `true` on answers the server kept from an earlier attempt
+pub static IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+/// the longest idempotency key the server accepts
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Where an item is in the kitchen.
+/// An item starts as ordered and moves along `ordered -> cooking -> ready -> served`,
+/// it can be cancelled as long as it has not been served.
+pub enum ItemStatus {
+    #[default]
+    Ordered,
+    Cooking,
+    Ready,
+    Served,
+    Cancelled,
+}
+
+impl ItemStatus {
+    /// if an item may move from this status to `next`
+    pub fn can_transition_to(self, next: ItemStatus) -> bool {
+        use ItemStatus::*;
+        matches!(
+            (self, next),
+            (Ordered, Cooking)
+                | (Cooking, Ready)
+                | (Ready, Served)
+                | (Ordered, Cancelled)
+                | (Cooking, Cancelled)
+                | (Ready, Cancelled)
+        )
+    }
+
+    /// if nothing more happens to an item with this status
+    pub fn is_final(self) -> bool {
+        matches!(self, ItemStatus::Served | ItemStatus::Cancelled)
+    }
+}
+
+impl fmt::Display for ItemStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ItemStatus::Ordered => "ordered",
+            ItemStatus::Cooking => "cooking",
+            ItemStatus::Ready => "ready",
+            ItemStatus::Served => "served",
+            ItemStatus::Cancelled => "cancelled",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ItemStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ordered" => Ok(ItemStatus::Ordered),
+            "cooking" => Ok(ItemStatus::Cooking),
+            "ready" => Ok(ItemStatus::Ready),
+            "served" => Ok(ItemStatus::Served),
+            "cancelled" => Ok(ItemStatus::Cancelled),
+            _ => Err(format!("unknown status '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An item entered `status` at `at`
+pub struct StatusChange {
+    pub status: ItemStatus,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// an item on the menu
+pub struct MenuItem {
+    /// the id assigned by the server. It is unique across all tables and never reused, so it stays valid
+    /// even when other items on the same table are deleted.
+    pub id: u64,
+    /// the number of the menu, i.e., 1 for Potato Fries, 2 for Karaage, etc.
+    pub item_number: u64,
+    /// the duration the menu item needs to cook in minutes. We do not need finer granularity.
+    pub duration_in_minutes: u64,
+    /// when the item was ordered
+    #[serde(default)]
+    pub ordered_at: DateTime<Utc>,
+    /// when the item is expected to be ready, `duration_in_minutes` after it was ordered
+    #[serde(default)]
+    pub ready_at: DateTime<Utc>,
+    /// minutes left until `ready_at` at the time of the answer, rounded up. Zero once the kitchen is done with it.
+    #[serde(default)]
+    pub remaining_minutes: u64,
+    /// where the item currently is in the kitchen
+    #[serde(default)]
+    pub status: ItemStatus,
+    /// every status the item went through with the time it got there, oldest first
+    #[serde(default)]
+    pub status_history: Vec<StatusChange>,
+    /// the seating it was ordered for, missing for items from before there were sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<u64>,
+    /// what it cost when it was ordered, missing for items from before the menu had prices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<u64>,
+    /// the seat of the guest who ordered it, counting from 1, if the waiter noted it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seat: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+/// How long the kitchen takes for a dish. Whatever a model comes up with is kept within the entry's cook time range.
+pub enum CookTimeModel {
+    /// always the same number of minutes
+    Fixed { minutes: u64 },
+    /// any whole number of minutes in the range, all equally likely
+    #[default]
+    Uniform,
+    /// normally distributed around `mean` minutes
+    Normal { mean: f64, std_dev: f64 },
+    /// the average of how long the kitchen actually took, the middle of the range until it cooked the dish once.
+    /// Updated by the server every time an item of the dish gets ready.
+    Learned {
+        /// how many cook times went into `mean`. The server stops counting at some point, so older ones fade out.
+        #[serde(default)]
+        samples: u64,
+        /// in minutes
+        #[serde(default)]
+        mean: f64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A dish on the menu card, what an item's `item_number` refers to
+pub struct MenuEntry {
+    pub item_number: u64,
+    pub name: String,
+    /// i.e., Drinks, Sides or Mains
+    pub category: String,
+    /// in the smallest unit of the currency, i.e., yen
+    pub price: u64,
+    /// the fastest the kitchen usually cooks it, in minutes
+    pub min_cook_minutes: u64,
+    /// the slowest the kitchen usually cooks it, in minutes
+    pub max_cook_minutes: u64,
+    /// how the cook time of a new item is estimated
+    #[serde(default)]
+    pub cook_time: CookTimeModel,
+    /// the part of the kitchen that cooks it, i.e., fryer or grill
+    #[serde(default)]
+    pub station: Option<String>,
+    /// the kitchen queue puts dishes with a higher priority first, i.e., drinks before mains
+    #[serde(default)]
+    pub priority: u8,
+    /// if it can be ordered right now. Sold out dishes stay on the menu but are not available.
+    #[serde(default = "available_by_default")]
+    pub available: bool,
+}
+
+fn available_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A table in the restaurant having various menuitems
+pub struct Table {
+    /// stays the same for as long as the table exists, numbers of removed tables are not handed out again
+    pub table_number: usize,
+    /// i.e., "Terrace 3", tables without one are only known by their number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// the part of the restaurant it is in, i.e., Terrace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// how many guests it seats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u32>,
+    /// inactive tables keep their items but take no new orders
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+    /// the party seated right now, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionInfo>,
+    pub items: Vec<MenuItem>,
+    /// counts the changes made to the table and only ever goes up. The server sends it as `ETag` as well.
+    #[serde(default)]
+    pub version: u64,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+impl Table {
+    /// An empty table as described by `info`
+    pub fn new(info: TableInfo) -> Self {
+        Self {
+            table_number: info.table_number,
+            name: info.name,
+            section: info.section,
+            capacity: info.capacity,
+            active: info.active,
+            session: None,
+            items: vec![],
+            version: 0,
+        }
+    }
+
+    /// What the floor plan says about this table
+    pub fn info(&self) -> TableInfo {
+        TableInfo {
+            table_number: self.table_number,
+            name: self.name.clone(),
+            section: self.section.clone(),
+            capacity: self.capacity,
+            active: self.active,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A table on the floor plan, without its items. The body to change a table as well.
+pub struct TableInfo {
+    pub table_number: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+impl TableInfo {
+    /// A table with nothing but its number, the way tables used to be
+    pub fn numbered(table_number: usize) -> Self {
+        Self {
+            table_number,
+            name: None,
+            section: None,
+            capacity: None,
+            active: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A party seated at a table, from when it sat down until the table is cleared
+pub struct SessionInfo {
+    /// unique across all tables and never reused
+    pub id: u64,
+    /// how many guests there are, if somebody counted
+    #[serde(default)]
+    pub guests: Option<u32>,
+    /// who looks after the party, i.e., the name of a waiter
+    #[serde(default)]
+    pub staff: Option<String>,
+    pub opened_at: DateTime<Utc>,
+    /// the discounts and comps on its bill, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<Adjustment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A closed session with everything that was ordered during it
+pub struct Session {
+    #[serde(flatten)]
+    pub info: SessionInfo,
+    pub table_number: usize,
+    pub closed_at: DateTime<Utc>,
+    pub items: Vec<MenuItem>,
+    /// the final bill, missing for sessions from before there were bills
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bill: Option<Bill>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to open a session, and to change it, in which case only the given fields change
+pub struct SessionDetails {
+    #[serde(default)]
+    pub guests: Option<u32>,
+    #[serde(default)]
+    pub staff: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// What is taken off a bill
+pub enum AdjustmentKind {
+    /// a share of everything that is not comped, in percent
+    PercentOff { percent: u32 },
+    /// a fixed amount, in yen
+    AmountOff { amount: u64 },
+    /// the item is on the house
+    Comp { item_id: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to adjust a bill
+pub struct NewAdjustment {
+    #[serde(flatten)]
+    pub kind: AdjustmentKind,
+    /// why, i.e., "birthday" or "dish sent back", there always has to be one
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A discount or comp on the bill of a session
+pub struct Adjustment {
+    /// unique within its session
+    pub id: u64,
+    #[serde(flatten)]
+    pub kind: AdjustmentKind,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An item on a bill
+pub struct BillLine {
+    pub item_id: u64,
+    pub item_number: u64,
+    /// the name on the menu, empty if the dish is no longer on it
+    pub name: String,
+    pub price: u64,
+    /// why the item is on the house, if it is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comped: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seat: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// What a session costs, all amounts in yen.
+/// `total` is `subtotal - discount + service_charge + tax`, where the tax is charged on the service charge as well.
+pub struct Bill {
+    pub session_id: u64,
+    pub table_number: usize,
+    /// every item that was not cancelled, in the order it was ordered
+    pub lines: Vec<BillLine>,
+    pub adjustments: Vec<Adjustment>,
+    /// what the items cost together
+    pub subtotal: u64,
+    /// the comps and discounts together, never more than the subtotal
+    pub discount: u64,
+    pub service_charge: u64,
+    pub tax: u64,
+    pub total: u64,
+    /// when the bill was made, when the session was closed for a final bill
+    pub issued_at: DateTime<Utc>,
+    /// if the session is closed, so the bill does not change anymore
+    #[serde(default)]
+    pub closed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+/// the body to split a bill. Items no share asks for are split evenly among all shares.
+pub enum SplitMode {
+    /// one share per seat, in seat order
+    BySeat,
+    /// one share per list of item ids
+    ByItem { shares: Vec<Vec<u64>> },
+    /// `ways` equal shares
+    Even { ways: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// What one guest or group pays of a split bill
+pub struct BillShare {
+    /// the seat paying it, for splits by seat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seat: Option<u32>,
+    /// the items only this share pays for
+    pub item_ids: Vec<u64>,
+    /// its part of the bill's total, in yen
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A bill split into shares. The amounts of the shares always add up to `total`.
+/// Discounts, service charge and tax are split in proportion to what each share ordered,
+/// leftover yen go to the shares that lost the most to rounding, the first of them on a tie.
+pub struct SplitBill {
+    pub session_id: u64,
+    /// the total of the whole bill
+    pub total: u64,
+    pub shares: Vec<BillShare>,
+    /// the items split evenly among all shares
+    pub shared_item_ids: Vec<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How a guest pays
+pub enum PaymentMethod {
+    /// taken at the table, nothing to ask a payment provider
+    Cash,
+    Card,
+    /// buy now, pay later. The provider may take a while to approve it.
+    Deferred,
+}
+
+impl fmt::Display for PaymentMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PaymentMethod::Cash => "cash",
+            PaymentMethod::Card => "card",
+            PaymentMethod::Deferred => "deferred",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for PaymentMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cash" => Ok(PaymentMethod::Cash),
+            "card" => Ok(PaymentMethod::Card),
+            "deferred" => Ok(PaymentMethod::Deferred),
+            _ => Err(format!(
+                "unknown payment method '{}', expected cash, card or deferred",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Where a payment or refund is. Pending ones are asked about again until the provider decides.
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl fmt::Display for PaymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Succeeded => "succeeded",
+            PaymentStatus::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// Money paid towards the bill of a session, or paid back if it is a refund
+pub struct Payment {
+    /// unique over all sessions
+    pub id: u64,
+    pub session_id: u64,
+    pub table_number: usize,
+    pub method: PaymentMethod,
+    /// in yen, paid back for a refund
+    pub amount: u64,
+    pub status: PaymentStatus,
+    /// the payment this refunds, if it is a refund
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund_of: Option<u64>,
+    /// why it was refunded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// what the payment provider calls it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// why it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// when the status last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to pay towards a bill
+pub struct NewPayment {
+    pub method: PaymentMethod,
+    /// in yen, everything still open without one
+    #[serde(default)]
+    pub amount: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to refund a payment
+pub struct NewRefund {
+    /// in yen, everything not refunded yet without one
+    #[serde(default)]
+    pub amount: Option<u64>,
+    /// there always has to be one
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// What has been paid of the bill of a session, all amounts in yen
+pub struct BillPayments {
+    pub session_id: u64,
+    /// the total of the bill
+    pub total: u64,
+    /// what succeeded, less what was refunded
+    pub paid: u64,
+    /// what the provider has not decided on yet
+    pub pending: u64,
+    /// what is still open, neither paid nor pending
+    pub balance: u64,
+    /// the payments and refunds, oldest first
+    pub payments: Vec<Payment>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// the query parameter of listing payments
+pub struct PaymentQuery {
+    /// Deprecated, send the key in the `Authorization` header instead, see [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// only the payments of this session
+    pub session_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to change the seat of an item, `None` if it is for the whole table
+pub struct SeatUpdate {
+    pub seat: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// the query parameter of adding items
+pub struct OrderQuery {
+    /// Deprecated, send the key in the `Authorization` header instead, see [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The seat ordering all of the items
+    pub seat: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to add a table, the server picks its number
+pub struct NewTable {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub capacity: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// All tables at one point in time. Every change to any table increases the `version`,
+/// so two snapshots with the same version (and the same query) are identical.
+pub struct Snapshot {
+    pub version: u64,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What paged listings are ordered by. Ties are broken by table number for tables and by item id for items.
+/// Tables are ordered by their first item, except when sorting by table number.
+pub enum SortKey {
+    #[default]
+    TableNumber,
+    /// the longest waiting first
+    OrderedAt,
+    /// the soonest ready first
+    Remaining,
+    ItemNumber,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// One page of a listing
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// pass as `cursor` to get the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+    /// how many entries there are on all pages together
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// the query parameter, having a optional limit and a optional status filter
+pub struct QueryParam {
+    /// Deprecated, send the key in the `Authorization` header instead, see [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The limit if we want
+    pub limit: Option<u64>,
+    /// Only return items with this status
+    pub status: Option<ItemStatus>,
+    /// Answer all tables with a [`Snapshot`] taken at a single point in time
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Answer with a [`Page`], the `limit` then counts the returned entries
+    #[serde(default)]
+    pub paged: bool,
+    /// Continue after the page that returned this cursor. Only with `paged`.
+    pub cursor: Option<String>,
+    /// Skip this many entries. Only with `paged`.
+    pub offset: Option<u64>,
+    /// Only with `paged`
+    pub sort: Option<SortKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// the query parameter of the kitchen queue
+pub struct KitchenQuery {
+    /// Deprecated, send the key in the `Authorization` header instead, see [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Only return items cooked at this station
+    pub station: Option<String>,
+    /// Return one [`QueueGroup`] per dish instead of one [`QueueEntry`] per item
+    #[serde(default)]
+    pub group: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An item the kitchen still has to finish
+pub struct QueueEntry {
+    pub table_number: usize,
+    /// the name on the menu, empty if the dish is no longer on it
+    pub name: String,
+    pub station: Option<String>,
+    pub priority: u8,
+    pub item: MenuItem,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// All open items of one dish, i.e., "7 × Karaage"
+pub struct QueueGroup {
+    pub item_number: u64,
+    /// the name on the menu, empty if the dish is no longer on it
+    pub name: String,
+    pub station: Option<String>,
+    pub count: usize,
+    /// the grouped items, the next to cook first
+    pub item_ids: Vec<u64>,
+    /// when the longest waiting of them was ordered
+    pub oldest_ordered_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// What happened in the restaurant
+pub enum EventKind {
+    ItemAdded {
+        item: MenuItem,
+    },
+    ItemRemoved {
+        item_id: u64,
+        item_number: u64,
+    },
+    StatusChanged {
+        item_id: u64,
+        item_number: u64,
+        status: ItemStatus,
+    },
+    /// the item is for the guest at `seat` now, or for the whole table without one
+    SeatChanged {
+        item_id: u64,
+        item_number: u64,
+        seat: Option<u32>,
+    },
+    /// the item was moved here from the table `from`, unchanged.
+    /// Subscribers of either table get this event.
+    ItemMoved {
+        item: MenuItem,
+        from: usize,
+    },
+    /// the table has nothing left that is not served or cancelled
+    TableClosed,
+    /// the table was added to the floor plan or its entry changed
+    TableChanged {
+        table: TableInfo,
+    },
+    /// the table was taken off the floor plan
+    TableRemoved,
+    /// a party was seated at the table, or the details of its session changed
+    SessionChanged {
+        session: SessionInfo,
+    },
+    /// the party left and its session was archived, the table is empty now
+    SessionClosed {
+        session_id: u64,
+    },
+    /// a payment or refund for the session at the table was made or its status changed
+    PaymentChanged {
+        payment: Payment,
+    },
+    /// events were lost, because the consumer was too slow or resumed from an event we no longer have.
+    /// The consumer should reload what it shows and carry on from this event's id.
+    Resync,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An entry of the event stream
+pub struct Event {
+    /// increases with every event, pass the last one seen to resume after a reconnect
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    /// the table it happened at, only missing for [`EventKind::Resync`]
+    pub table_number: Option<usize>,
+    /// the station cooking the item it is about, if any
+    pub station: Option<String>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// the query parameter of the event stream
+pub struct EventQuery {
+    /// Deprecated, send the key in the `Authorization` header instead, see [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Only events of this table
+    pub table: Option<usize>,
+    /// Only events of items cooked at this station
+    pub station: Option<String>,
+    /// Resume after this event. For server-sent events the `Last-Event-ID` header works as well.
+    pub last_event_id: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What the holder of a key may do. Everybody may read the orders, the menu and the events.
+/// Waiters take orders and payments and move orders along, the kitchen only moves them along, managers also edit
+/// the menu and the floor plan, discount bills and refund payments, and admins also manage the keys.
+pub enum Role {
+    Waiter,
+    Kitchen,
+    Manager,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Waiter => "waiter",
+            Role::Kitchen => "kitchen",
+            Role::Manager => "manager",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waiter" => Ok(Role::Waiter),
+            "kitchen" => Ok(Role::Kitchen),
+            "manager" => Ok(Role::Manager),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An api key as the server shows it, without the secret
+pub struct KeyInfo {
+    pub id: u64,
+    /// the device or person the key was handed to
+    pub name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to create a key
+pub struct NewKey {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A freshly created or rotated key. The secret is only ever shown here, the server keeps a hash of it.
+pub struct IssuedKey {
+    #[serde(flatten)]
+    pub info: KeyInfo,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to move items to another table. Without `item_ids` all items move, which merges the tables.
+pub struct ItemTransfer {
+    pub to: usize,
+    #[serde(default)]
+    pub item_ids: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// the body to move an item to a new status
+pub struct StatusUpdate {
+    pub status: ItemStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What went wrong, so clients do not have to parse the message
+pub enum ErrorKind {
+    Unauthorized,
+    /// the key is valid, but its role does not allow the request
+    Forbidden,
+    NotFound,
+    BadRequest,
+    /// the request does not fit the current state, i.e., an illegal status transition
+    Conflict,
+    /// the table changed since the version named in `If-Match`
+    PreconditionFailed,
+    Internal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// The body the server answers with when a request fails
+pub struct ErrorBody {
+    pub error: ErrorKind,
+    /// human readable explanation
+    pub message: String,
+}
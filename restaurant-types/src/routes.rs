@@ -0,0 +1,179 @@
+//! The paths of the api. The templates are what the server routes on, the functions build concrete paths for clients.
+
+/// all non-empty tables
+pub static ALL_TABLES: &str = "/";
+/// the items of one table, `GET` to query and `POST` to add, `?seat=` notes who ordered them
+pub static TABLE: &str = "/:table_number/";
+/// a single item by its id, `GET` to query and `DELETE` to remove
+pub static ITEM: &str = "/:table_number/items/:item_id";
+/// the seat of a single item, `PUT` to change it
+pub static ITEM_SEAT: &str = "/:table_number/items/:item_id/seat";
+/// `POST` to move items of the table to another one, keeping their ids and cook times
+pub static TABLE_TRANSFER: &str = "/:table_number/transfer";
+/// the party seated at the table, `GET` to query, `POST` to open, `PATCH` to change and `DELETE` to close it
+pub static SESSION: &str = "/:table_number/session";
+/// the closed sessions of the table, oldest first, `GET` only
+pub static SESSIONS: &str = "/:table_number/sessions";
+/// a single item by its position on the table.
+/// Deprecated: positions shift when other items are deleted, use [`ITEM`] instead.
+pub static ITEM_POSITION: &str = "/:table_number/:item_position/";
+/// the menu card, `GET` to list and `POST` to add an entry
+pub static MENU: &str = "/menu/";
+/// a single menu entry by its item number, `GET` to query, `PUT` to replace and `DELETE` to remove
+pub static MENU_ENTRY: &str = "/menu/:item_number";
+/// the open items of all tables in the order the kitchen should cook them, `GET` only
+pub static KITCHEN_QUEUE: &str = "/kitchen/queue";
+/// the event stream as server-sent events
+pub static EVENTS: &str = "/events";
+/// the event stream over a websocket
+pub static EVENTS_WS: &str = "/ws";
+/// the floor plan, `GET` to list all tables, including inactive ones, and `POST` to add one
+pub static TABLES: &str = "/tables/";
+/// a single table of the floor plan, `PUT` to change and `DELETE` to remove it
+pub static TABLE_INFO: &str = "/tables/:table_number";
+/// the bill of the party seated at the table so far, `GET` only. The final one is kept with the closed session.
+pub static BILL: &str = "/tables/:table_number/bill";
+/// `POST` to split the bill into shares, by seat, by item or evenly. Nothing is changed.
+pub static BILL_SPLIT: &str = "/tables/:table_number/bill/split";
+/// the discounts and comps on the bill, `POST` to add one
+pub static BILL_ADJUSTMENTS: &str = "/tables/:table_number/bill/adjustments";
+/// a single discount or comp by its id, `DELETE` to take it back
+pub static BILL_ADJUSTMENT: &str = "/tables/:table_number/bill/adjustments/:adjustment_id";
+/// what was paid of the bill, `GET` to query and `POST` to pay
+pub static BILL_PAYMENTS: &str = "/tables/:table_number/bill/payments";
+/// all payments and refunds, `GET` only, `?session_id=` narrows them to one session
+pub static PAYMENTS: &str = "/payments/";
+/// a single payment or refund by its id, `GET` only
+pub static PAYMENT: &str = "/payments/:payment_id";
+/// `POST` to pay back a payment, fully or in part
+pub static PAYMENT_REFUND: &str = "/payments/:payment_id/refund";
+/// the api keys, `GET` to list and `POST` to create one
+pub static KEYS: &str = "/keys/";
+/// a single api key by its id, `DELETE` to revoke
+pub static KEY: &str = "/keys/:key_id";
+/// `POST` to replace the secret of an api key, the old one stops working at once
+pub static KEY_ROTATE: &str = "/keys/:key_id/rotate";
+
+/// path to all non-empty tables
+pub fn all_tables() -> String {
+    ALL_TABLES.to_string()
+}
+
+/// path to the items of `table_number`
+pub fn table(table_number: usize) -> String {
+    format!("/{}/", table_number)
+}
+
+/// path to the item `item_id` on `table_number`
+pub fn item(table_number: usize, item_id: u64) -> String {
+    format!("/{}/items/{}", table_number, item_id)
+}
+
+/// path to the seat of the item `item_id` on `table_number`
+pub fn item_seat(table_number: usize, item_id: u64) -> String {
+    format!("/{}/items/{}/seat", table_number, item_id)
+}
+
+/// path to move items away from `table_number`
+pub fn table_transfer(table_number: usize) -> String {
+    format!("/{}/transfer", table_number)
+}
+
+/// path to the session of `table_number`
+pub fn session(table_number: usize) -> String {
+    format!("/{}/session", table_number)
+}
+
+/// path to the closed sessions of `table_number`
+pub fn sessions(table_number: usize) -> String {
+    format!("/{}/sessions", table_number)
+}
+
+/// path to the item at `item_position` on `table_number`.
+/// Deprecated: positions shift when other items are deleted, use [`item`] instead.
+pub fn item_position(table_number: usize, item_position: usize) -> String {
+    format!("/{}/{}/", table_number, item_position)
+}
+
+/// path to the menu card
+pub fn menu() -> String {
+    MENU.to_string()
+}
+
+/// path to the menu entry of `item_number`
+pub fn menu_entry(item_number: u64) -> String {
+    format!("/menu/{}", item_number)
+}
+
+/// path to the kitchen queue
+pub fn kitchen_queue() -> String {
+    KITCHEN_QUEUE.to_string()
+}
+
+/// path to the floor plan
+pub fn tables() -> String {
+    TABLES.to_string()
+}
+
+/// path to the floor plan entry of `table_number`
+pub fn table_info(table_number: usize) -> String {
+    format!("/tables/{}", table_number)
+}
+
+/// path to the bill of `table_number`
+pub fn bill(table_number: usize) -> String {
+    format!("/tables/{}/bill", table_number)
+}
+
+/// path to split the bill of `table_number`
+pub fn bill_split(table_number: usize) -> String {
+    format!("/tables/{}/bill/split", table_number)
+}
+
+/// path to the discounts and comps on the bill of `table_number`
+pub fn bill_adjustments(table_number: usize) -> String {
+    format!("/tables/{}/bill/adjustments", table_number)
+}
+
+/// path to the discount or comp `adjustment_id` on the bill of `table_number`
+pub fn bill_adjustment(table_number: usize, adjustment_id: u64) -> String {
+    format!(
+        "/tables/{}/bill/adjustments/{}",
+        table_number, adjustment_id
+    )
+}
+
+/// path to the payments towards the bill of `table_number`
+pub fn bill_payments(table_number: usize) -> String {
+    format!("/tables/{}/bill/payments", table_number)
+}
+
+/// path to all payments
+pub fn payments() -> String {
+    PAYMENTS.to_string()
+}
+
+/// path to the payment `payment_id`
+pub fn payment(payment_id: u64) -> String {
+    format!("/payments/{}", payment_id)
+}
+
+/// path to refund the payment `payment_id`
+pub fn payment_refund(payment_id: u64) -> String {
+    format!("/payments/{}/refund", payment_id)
+}
+
+/// path to the api keys
+pub fn keys() -> String {
+    KEYS.to_string()
+}
+
+/// path to the api key `key_id`
+pub fn key(key_id: u64) -> String {
+    format!("/keys/{}", key_id)
+}
+
+/// path to rotate the api key `key_id`
+pub fn key_rotate(key_id: u64) -> String {
+    format!("/keys/{}/rotate", key_id)
+}
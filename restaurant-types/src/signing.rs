@@ -0,0 +1,126 @@
+//! Signed requests, an alternative to sending the api key itself.
+//!
+//! The client signs the method, the path with the query, a hash of the body, a timestamp and a nonce with
+//! HMAC-SHA256 and sends the result in the `Authorization` header:
+//!
+//! ```text
+//! Authorization: Signature key_id=3,timestamp=1719835200,nonce=5f2b...,signature=9a0c...
+//! ```
+//!
+//! The HMAC key is derived from the api key with [`signing_key`]. It differs from the sha256 the server checks
+//! sent api keys against, but the server has to keep it as well, so whoever reads the server's keys file can
+//! sign requests. That file has to be guarded like the api keys themselves.
+//! The server refuses timestamps further than [`MAX_SKEW_SECONDS`] from its clock and nonces it has seen
+//! within that window, so a captured request cannot be sent again.
+use std::{fmt, str::FromStr};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// the scheme of the `Authorization` header for signed requests
+pub static SIGNATURE_SCHEME: &str = "Signature";
+/// the scheme of the `Authorization` header sending the api key itself
+pub static BEARER_SCHEME: &str = "Bearer";
+/// how far the timestamp of a signed request may be from the server's clock, in either direction
+pub const MAX_SKEW_SECONDS: i64 = 300;
+
+/// what the api key is mixed with to get its HMAC key
+const SIGNING_KEY_LABEL: &[u8] = b"restaurant signed requests v1";
+
+/// The HMAC key belonging to the api key `secret`
+pub fn signing_key(secret: &str) -> [u8; 32] {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(SIGNING_KEY_LABEL);
+    mac.finalize().into_bytes().into()
+}
+
+/// What gets signed. `path` includes the query, if there is one.
+pub fn canonical_request(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    timestamp: i64,
+    nonce: &str,
+) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path,
+        hex::encode(Sha256::digest(body)),
+        timestamp,
+        nonce
+    )
+}
+
+fn mac(signing_key: &[u8], canonical_request: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(signing_key).expect("hmac takes keys of any length");
+    mac.update(canonical_request.as_bytes());
+    mac
+}
+
+/// The hex encoded signature of `canonical_request`
+pub fn sign(signing_key: &[u8], canonical_request: &str) -> String {
+    hex::encode(mac(signing_key, canonical_request).finalize().into_bytes())
+}
+
+/// if `signature` is the signature of `canonical_request`, compared in constant time
+pub fn verify(signing_key: &[u8], canonical_request: &str, signature: &str) -> bool {
+    hex::decode(signature).is_ok_and(|signature| {
+        mac(signing_key, canonical_request)
+            .verify_slice(&signature)
+            .is_ok()
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The value of the `Authorization` header of a signed request
+pub struct SignatureHeader {
+    pub key_id: u64,
+    /// seconds since the unix epoch
+    pub timestamp: i64,
+    /// anything unique per request
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl fmt::Display for SignatureHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} key_id={},timestamp={},nonce={},signature={}",
+            SIGNATURE_SCHEME, self.key_id, self.timestamp, self.nonce, self.signature
+        )
+    }
+}
+
+impl FromStr for SignatureHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let params = s
+            .strip_prefix(SIGNATURE_SCHEME)
+            .and_then(|rest| rest.strip_prefix(' '))
+            .ok_or_else(|| format!("expected the {} scheme", SIGNATURE_SCHEME))?;
+        let (mut key_id, mut timestamp, mut nonce, mut signature) = (None, None, None, None);
+        for param in params.split(',') {
+            match param.trim().split_once('=') {
+                Some(("key_id", value)) => key_id = value.parse().ok(),
+                Some(("timestamp", value)) => timestamp = value.parse().ok(),
+                Some(("nonce", value)) if !value.is_empty() => nonce = Some(value.to_string()),
+                Some(("signature", value)) => signature = Some(value.to_string()),
+                _ => return Err(format!("unexpected parameter '{}'", param)),
+            }
+        }
+        match (key_id, timestamp, nonce, signature) {
+            (Some(key_id), Some(timestamp), Some(nonce), Some(signature)) => Ok(Self {
+                key_id,
+                timestamp,
+                nonce,
+                signature,
+            }),
+            _ => Err("needs key_id, timestamp, nonce and signature".to_string()),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Who may do what. Every request carries an api key and every route performs one [`Operation`],
+//! which the role of the key has to allow. Routes missing from [`operation`] are refused.
+//!
+//! The key goes into the `Authorization` header, either as `Bearer <key>` or as a signature of the request,
+//! see [`restaurant_types::signing`]. The `key` query parameter still works for clients that cannot set headers,
+//! like a browser's `EventSource`, but ends up in logs and browser histories.
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Query, Request, State},
+    http::{header::AUTHORIZATION, Method, Uri},
+    middleware::Next,
+    response::Response,
+};
+use restaurant_types::{
+    routes,
+    signing::{self, SignatureHeader, BEARER_SCHEME, MAX_SKEW_SECONDS, SIGNATURE_SCHEME},
+    ErrorKind,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::AuthConfig,
+    error::ApiError,
+    store::RestaurantStore,
+    types::{KeyInfo, Role},
+    AppState,
+};
+
+/// What [`authorize`] needs to know
+pub(crate) struct AuthState<S> {
+    store: AppState<S>,
+    /// if the deprecated `key` query parameter is still accepted
+    allow_query_key: bool,
+}
+
+impl<S> AuthState<S> {
+    pub(crate) fn new(store: AppState<S>, config: &AuthConfig) -> Self {
+        Self {
+            store,
+            allow_query_key: config.allow_query_key,
+        }
+    }
+}
+
+impl<S> Clone for AuthState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            allow_query_key: self.allow_query_key,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What a request does, as far as permissions are concerned
+pub(crate) enum Operation {
+    /// read tables, items, bills, the kitchen queue and the events
+    ReadOrders,
+    /// add, remove and move items, seat parties and take payments
+    TakeOrders,
+    /// move items to another status
+    UpdateStatus,
+    ReadMenu,
+    EditMenu,
+    /// add, change and remove tables
+    EditFloorPlan,
+    /// discount and comp bills, and refund payments
+    AdjustBills,
+    ManageKeys,
+}
+
+impl Operation {
+    /// if a key with `role` may perform this operation
+    pub(crate) fn allowed_for(self, role: Role) -> bool {
+        match self {
+            Operation::ReadOrders | Operation::UpdateStatus | Operation::ReadMenu => true,
+            Operation::TakeOrders => role != Role::Kitchen,
+            Operation::EditMenu | Operation::EditFloorPlan | Operation::AdjustBills => {
+                matches!(role, Role::Manager | Role::Admin)
+            }
+            Operation::ManageKeys => role == Role::Admin,
+        }
+    }
+}
+
+/// The operation performed by a `method` request on the route template `route`
+fn operation(method: &Method, route: &str) -> Option<Operation> {
+    use Operation::*;
+    let permissions = [
+        (Method::GET, routes::ALL_TABLES, ReadOrders),
+        (Method::GET, routes::TABLE, ReadOrders),
+        (Method::POST, routes::TABLE, TakeOrders),
+        (Method::POST, routes::TABLE_TRANSFER, TakeOrders),
+        (Method::GET, routes::SESSION, ReadOrders),
+        (Method::POST, routes::SESSION, TakeOrders),
+        (Method::PATCH, routes::SESSION, TakeOrders),
+        (Method::DELETE, routes::SESSION, TakeOrders),
+        (Method::GET, routes::SESSIONS, ReadOrders),
+        (Method::GET, routes::ITEM, ReadOrders),
+        (Method::DELETE, routes::ITEM, TakeOrders),
+        (Method::PATCH, routes::ITEM, UpdateStatus),
+        (Method::PUT, routes::ITEM_SEAT, TakeOrders),
+        (Method::GET, routes::ITEM_POSITION, ReadOrders),
+        (Method::DELETE, routes::ITEM_POSITION, TakeOrders),
+        (Method::GET, routes::MENU, ReadMenu),
+        (Method::POST, routes::MENU, EditMenu),
+        (Method::GET, routes::MENU_ENTRY, ReadMenu),
+        (Method::PUT, routes::MENU_ENTRY, EditMenu),
+        (Method::DELETE, routes::MENU_ENTRY, EditMenu),
+        (Method::GET, routes::KITCHEN_QUEUE, ReadOrders),
+        (Method::GET, routes::EVENTS, ReadOrders),
+        (Method::GET, routes::EVENTS_WS, ReadOrders),
+        (Method::GET, routes::TABLES, ReadOrders),
+        (Method::POST, routes::TABLES, EditFloorPlan),
+        (Method::PUT, routes::TABLE_INFO, EditFloorPlan),
+        (Method::DELETE, routes::TABLE_INFO, EditFloorPlan),
+        (Method::GET, routes::BILL, ReadOrders),
+        (Method::POST, routes::BILL_SPLIT, ReadOrders),
+        (Method::POST, routes::BILL_ADJUSTMENTS, AdjustBills),
+        (Method::DELETE, routes::BILL_ADJUSTMENT, AdjustBills),
+        (Method::GET, routes::BILL_PAYMENTS, ReadOrders),
+        (Method::POST, routes::BILL_PAYMENTS, TakeOrders),
+        (Method::GET, routes::PAYMENTS, ReadOrders),
+        (Method::GET, routes::PAYMENT, ReadOrders),
+        (Method::POST, routes::PAYMENT_REFUND, AdjustBills),
+        (Method::GET, routes::KEYS, ManageKeys),
+        (Method::POST, routes::KEYS, ManageKeys),
+        (Method::DELETE, routes::KEY, ManageKeys),
+        (Method::POST, routes::KEY_ROTATE, ManageKeys),
+    ];
+    permissions
+        .into_iter()
+        .find(|(m, r, _)| m == method && *r == route)
+        .map(|(_, _, operation)| operation)
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyParam {
+    key: Option<String>,
+}
+
+/// Middleware letting a request through if its key exists and may perform the operation of the route.
+/// Answers 401 for a missing or unknown key or a bad signature and 403 if the role of the key does not allow
+/// the operation. The [`KeyInfo`] of the key is added to the extensions of the request.
+pub(crate) async fn authorize<S: RestaurantStore>(
+    State(AuthState {
+        store: state,
+        allow_query_key,
+    }): State<AuthState<S>>,
+    route: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .map(|value| value.to_str().map(str::to_string))
+        .transpose()
+        .map_err(|_| ApiError::unauthorized())?;
+    let (holder, mut request) = match authorization {
+        Some(value) if value.starts_with(SIGNATURE_SCHEME) => {
+            verify_signature(&state, &value, request).await?
+        }
+        Some(value) => {
+            let key = value
+                .strip_prefix(BEARER_SCHEME)
+                .and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(ApiError::unauthorized)?;
+            (authenticate(&state, key)?, request)
+        }
+        None if allow_query_key => {
+            let key = Query::<KeyParam>::try_from_uri(request.uri())
+                .ok()
+                .and_then(|Query(param)| param.key)
+                .ok_or_else(ApiError::unauthorized)?;
+            (authenticate(&state, &key)?, request)
+        }
+        None => return Err(ApiError::unauthorized()),
+    };
+    match operation(request.method(), route.as_str()) {
+        Some(operation) if operation.allowed_for(holder.role) => {
+            request.extensions_mut().insert(holder);
+            Ok(next.run(request).await)
+        }
+        _ => Err(ApiError::forbidden(holder.role)),
+    }
+}
+
+fn authenticate<S: RestaurantStore>(state: &AppState<S>, key: &str) -> Result<KeyInfo, ApiError> {
+    state.authenticate(key).ok_or_else(ApiError::unauthorized)
+}
+
+/// Check the signature in the `Authorization` header `value`. As the body is part of what is signed, it is read
+/// here and put back into the request handed on.
+async fn verify_signature<S: RestaurantStore>(
+    state: &AppState<S>,
+    value: &str,
+    request: Request,
+) -> Result<(KeyInfo, Request), ApiError> {
+    let rejected = |message: &str| ApiError::new(ErrorKind::Unauthorized, message);
+    let header = value
+        .parse::<SignatureHeader>()
+        .map_err(|e| rejected(&format!("invalid signature header: {}", e)))?;
+    let now = state.now().timestamp();
+    // the timestamp is not authenticated yet, any i64 may arrive here
+    if now.abs_diff(header.timestamp) > MAX_SKEW_SECONDS as u64 {
+        return Err(rejected(
+            "the signature timestamp is too far from the server time",
+        ));
+    }
+    let (holder, signing_key) = state
+        .signing_key(header.key_id)
+        .ok_or_else(ApiError::unauthorized)?;
+
+    let (parts, body) = request.into_parts();
+    // the body limit of the router applies here as well
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| ApiError::new(ErrorKind::BadRequest, "the body is too large"))?;
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |path| path.as_str());
+    let canonical = signing::canonical_request(
+        parts.method.as_str(),
+        path,
+        &body,
+        header.timestamp,
+        &header.nonce,
+    );
+    if !signing::verify(&signing_key, &canonical, &header.signature) {
+        return Err(rejected("invalid signature"));
+    }
+    // only now, so forged requests cannot use up the nonces of real ones
+    if !state.use_nonce(
+        header.key_id,
+        &header.nonce,
+        header.timestamp.saturating_add(MAX_SKEW_SECONDS),
+    ) {
+        return Err(rejected("the request was replayed"));
+    }
+    Ok((holder, Request::from_parts(parts, Body::from(body))))
+}
+
+/// `uri` with the value of a `key` query parameter hidden, for logging
+pub(crate) fn redact(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some(("key", _)) => "key=redacted",
+            _ => pair,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", uri.path(), query)
+}
@@ -0,0 +1,305 @@
+//! What a party pays. The bill is worked out from the items of a session, the prices they were ordered at
+//! and the discounts and comps on the session, following the rules in the `billing` section of the config.
+//!
+//! Comps come off first, then the discounts, then the service charge is added and the consumption tax is charged
+//! on top of both. Every percentage is rounded to whole yen on its own, the way the config says.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::BillingConfig,
+    types::{
+        AdjustmentKind, Bill, BillLine, BillShare, ItemStatus, MenuEntry, MenuItem, SessionInfo,
+        SplitBill, SplitMode,
+    },
+};
+
+/// the most shares a bill is split into evenly, more than any table seats
+const MAX_SPLIT_WAYS: u32 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// What happens to fractions of a yen
+pub(crate) enum Rounding {
+    /// cut them off, the usual way for consumption tax
+    #[default]
+    Down,
+    Up,
+    /// half a yen and more rounds up
+    Nearest,
+}
+
+impl FromStr for Rounding {
+    type Err = anyhow::Error;
+
+    /// Parses `down`, `up` or `nearest`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            "nearest" => Ok(Rounding::Nearest),
+            _ => Err(anyhow::anyhow!(
+                "Unknown rounding '{}', expected down, up or nearest",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How a bill is worked out
+pub(crate) struct BillingRules {
+    /// consumption tax in percent, added on top of the menu prices
+    pub(crate) tax_percent: u32,
+    pub(crate) service_charge_percent: u32,
+    /// only parties of at least this many guests pay the service charge. Parties nobody counted do not.
+    pub(crate) service_charge_min_guests: Option<u32>,
+    pub(crate) rounding: Rounding,
+}
+
+impl Default for BillingRules {
+    fn default() -> Self {
+        Self {
+            tax_percent: 10,
+            service_charge_percent: 0,
+            service_charge_min_guests: None,
+            rounding: Rounding::Down,
+        }
+    }
+}
+
+impl BillingRules {
+    /// The rules set in the `billing` section of the config
+    pub(crate) fn from_config(config: &BillingConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            tax_percent: config.tax_percent,
+            service_charge_percent: config.service_charge_percent,
+            service_charge_min_guests: config.service_charge_min_guests,
+            rounding: config.rounding.parse()?,
+        })
+    }
+
+    /// `percent` of `amount`, rounded, at most `u64::MAX`
+    fn percent_of(&self, amount: u64, percent: u32) -> u64 {
+        let hundredths = amount as u128 * percent as u128;
+        let rounded = match self.rounding {
+            Rounding::Down => hundredths / 100,
+            Rounding::Up => hundredths.div_ceil(100),
+            Rounding::Nearest => (hundredths + 50) / 100,
+        };
+        u64::try_from(rounded).unwrap_or(u64::MAX)
+    }
+
+    /// if the party of `session` pays the service charge
+    fn charges_service(&self, session: &SessionInfo) -> bool {
+        match self.service_charge_min_guests {
+            None => true,
+            Some(min) => session.guests.is_some_and(|guests| guests >= min),
+        }
+    }
+}
+
+/// The bill of `session` at `table_number` with its `items` as of `now`.
+/// Items from before the menu had prices cost what the `menu` says today.
+pub(crate) fn bill(
+    session: &SessionInfo,
+    table_number: usize,
+    items: &[MenuItem],
+    menu: &[MenuEntry],
+    rules: &BillingRules,
+    now: DateTime<Utc>,
+) -> Bill {
+    let menu = menu
+        .iter()
+        .map(|entry| (entry.item_number, entry))
+        .collect::<HashMap<u64, &MenuEntry>>();
+    let comps = session
+        .adjustments
+        .iter()
+        .filter_map(|adjustment| match adjustment.kind {
+            AdjustmentKind::Comp { item_id } => Some((item_id, adjustment.reason.clone())),
+            _ => None,
+        })
+        .collect::<HashMap<u64, String>>();
+    let lines = items
+        .iter()
+        .filter(|item| item.status != ItemStatus::Cancelled)
+        .map(|item| {
+            let entry = menu.get(&item.item_number);
+            BillLine {
+                item_id: item.id,
+                item_number: item.item_number,
+                name: entry.map(|e| e.name.clone()).unwrap_or_default(),
+                price: item.price.or(entry.map(|e| e.price)).unwrap_or_default(),
+                comped: comps.get(&item.id).cloned(),
+                seat: item.seat,
+            }
+        })
+        .collect::<Vec<BillLine>>();
+
+    // amounts saturate rather than wrap, whatever was entered
+    let subtotal = lines
+        .iter()
+        .fold(0u64, |sum, line| sum.saturating_add(line.price));
+    let comped = lines
+        .iter()
+        .filter(|line| line.comped.is_some())
+        .fold(0u64, |sum, line| sum.saturating_add(line.price));
+    let discounted = session
+        .adjustments
+        .iter()
+        .map(|adjustment| match adjustment.kind {
+            AdjustmentKind::PercentOff { percent } => rules.percent_of(subtotal - comped, percent),
+            AdjustmentKind::AmountOff { amount } => amount,
+            AdjustmentKind::Comp { .. } => 0,
+        })
+        .fold(0u64, u64::saturating_add);
+    let discount = comped.saturating_add(discounted).min(subtotal);
+    let net = subtotal - discount;
+    let service_charge = if rules.charges_service(session) {
+        rules.percent_of(net, rules.service_charge_percent)
+    } else {
+        0
+    };
+    let tax = rules.percent_of(net.saturating_add(service_charge), rules.tax_percent);
+    Bill {
+        session_id: session.id,
+        table_number,
+        lines,
+        adjustments: session.adjustments.clone(),
+        subtotal,
+        discount,
+        service_charge,
+        tax,
+        total: net.saturating_add(service_charge).saturating_add(tax),
+        issued_at: now,
+        closed: false,
+    }
+}
+
+/// the seat of a share, if it is one, and the lines it pays for itself
+type Share<'a> = (Option<u32>, Vec<&'a BillLine>);
+
+/// Split `bill` into shares whose amounts add up to its total.
+/// A share weighs what its own items cost plus an even part of the items no share asked for,
+/// comped items count as free. The total is then divided in proportion to the weights, see [`allocate`].
+pub(crate) fn split(bill: &Bill, mode: &SplitMode) -> Result<SplitBill, String> {
+    let (shares, shared): (Vec<Share>, Vec<&BillLine>) = match mode {
+        SplitMode::BySeat => {
+            let mut seats = BTreeMap::<u32, Vec<&BillLine>>::new();
+            let mut shared = vec![];
+            for line in &bill.lines {
+                match line.seat {
+                    Some(seat) => seats.entry(seat).or_default().push(line),
+                    None => shared.push(line),
+                }
+            }
+            if seats.is_empty() {
+                return Err("no item on the bill has a seat".to_string());
+            }
+            (
+                seats
+                    .into_iter()
+                    .map(|(seat, lines)| (Some(seat), lines))
+                    .collect(),
+                shared,
+            )
+        }
+        SplitMode::ByItem { shares } => {
+            if shares.is_empty() {
+                return Err("a split needs at least one share".to_string());
+            }
+            let lines = bill
+                .lines
+                .iter()
+                .map(|line| (line.item_id, line))
+                .collect::<HashMap<u64, &BillLine>>();
+            let mut taken = HashSet::new();
+            let mut split = Vec::with_capacity(shares.len());
+            for item_ids in shares {
+                let mut own = Vec::with_capacity(item_ids.len());
+                for item_id in item_ids {
+                    let line = lines
+                        .get(item_id)
+                        .ok_or_else(|| format!("item {} is not on the bill", item_id))?;
+                    if !taken.insert(*item_id) {
+                        return Err(format!("item {} is in more than one share", item_id));
+                    }
+                    own.push(*line);
+                }
+                split.push((None, own));
+            }
+            let shared = bill
+                .lines
+                .iter()
+                .filter(|line| !taken.contains(&line.item_id))
+                .collect();
+            (split, shared)
+        }
+        SplitMode::Even { ways } => {
+            if *ways == 0 || *ways > MAX_SPLIT_WAYS {
+                return Err(format!(
+                    "a bill is split between 1 and {} ways",
+                    MAX_SPLIT_WAYS
+                ));
+            }
+            (
+                (0..*ways).map(|_| (None, vec![])).collect(),
+                bill.lines.iter().collect(),
+            )
+        }
+    };
+
+    let cost = |line: &&BillLine| if line.comped.is_some() { 0 } else { line.price };
+    let count = shares.len() as u64;
+    let common = shared.iter().map(cost).sum::<u64>();
+    // scaled by the number of shares, so the even parts of the shared items need no rounding
+    let weights = shares
+        .iter()
+        .map(|(_, own)| own.iter().map(cost).sum::<u64>() * count + common)
+        .collect::<Vec<u64>>();
+    let amounts = allocate(bill.total, &weights);
+    Ok(SplitBill {
+        session_id: bill.session_id,
+        total: bill.total,
+        shares: shares
+            .into_iter()
+            .zip(amounts)
+            .map(|((seat, own), amount)| BillShare {
+                seat,
+                item_ids: own.iter().map(|line| line.item_id).collect(),
+                amount,
+            })
+            .collect(),
+        shared_item_ids: shared.iter().map(|line| line.item_id).collect(),
+    })
+}
+
+/// Divide `total` in proportion to `weights` so the parts add up to it exactly, equally without any weight.
+/// Every part is rounded down, then the yen left over go one each to the parts that lost the most,
+/// the earlier part first on a tie, so the same bill is always split the same way.
+fn allocate(total: u64, weights: &[u64]) -> Vec<u64> {
+    let weights = if weights.iter().all(|&weight| weight == 0) {
+        vec![1; weights.len()]
+    } else {
+        weights.to_vec()
+    };
+    let sum = weights.iter().map(|&weight| weight as u128).sum::<u128>();
+    let mut parts = weights
+        .iter()
+        .map(|&weight| {
+            let exact = total as u128 * weight as u128;
+            ((exact / sum) as u64, exact % sum)
+        })
+        .collect::<Vec<(u64, u128)>>();
+    let left = total - parts.iter().map(|(part, _)| part).sum::<u64>();
+    let mut order = (0..parts.len()).collect::<Vec<usize>>();
+    order.sort_by(|&a, &b| parts[b].1.cmp(&parts[a].1).then(a.cmp(&b)));
+    for i in order.into_iter().take(left as usize) {
+        parts[i].0 += 1;
+    }
+    parts.into_iter().map(|(part, _)| part).collect()
+}
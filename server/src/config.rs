@@ -0,0 +1,576 @@
+//! How the server is set up. Every setting has a default, which a TOML file, then environment variables and
+//! finally command line flags override, in that order. The result is validated once at startup.
+//!
+//! `restaurant.toml` in the working directory is read if it exists, `--config` names another file.
+//! `--print-config` shows the merged result without starting the server.
+use std::{
+    ffi::{OsStr, OsString},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{AMOUNT_OF_TABLES, API_KEY};
+
+/// the file read when `--config` is not given, if it exists
+static DEFAULT_CONFIG_FILE: &str = "restaurant.toml";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// The effective configuration of the server
+pub(crate) struct Config {
+    /// address and port we listen on
+    pub(crate) listen: SocketAddr,
+    /// how many tables a fresh restaurant starts with, numbered from 0. Afterwards tables are managed at `/tables/`.
+    pub(crate) tables: usize,
+    /// the menu file
+    pub(crate) menu: PathBuf,
+    /// seed of the cook time generator, for reproducible cook times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) seed: Option<u64>,
+    pub(crate) log: LogConfig,
+    pub(crate) storage: StorageConfig,
+    pub(crate) auth: AuthConfig,
+    pub(crate) limits: LimitsConfig,
+    pub(crate) tls: TlsConfig,
+    pub(crate) billing: BillingConfig,
+    pub(crate) payments: PaymentsConfig,
+    pub(crate) idempotency: IdempotencyConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LogConfig {
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub(crate) level: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Only used when built with the `durable` feature
+pub(crate) struct StorageConfig {
+    /// directory holding the log and the snapshot
+    pub(crate) dir: PathBuf,
+    /// `always`, `never` or `every:<n>`
+    pub(crate) durability: String,
+    pub(crate) snapshot_interval_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    /// the keys file
+    pub(crate) keys: PathBuf,
+    /// the secret of the admin key a missing keys file is created with
+    pub(crate) bootstrap_key: String,
+    /// accept the api key in the deprecated `key` query parameter
+    pub(crate) allow_query_key: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LimitsConfig {
+    /// larger request bodies are refused with 413
+    pub(crate) max_body_bytes: usize,
+    /// requests taking longer are answered with 408. Streams only need to start in time.
+    pub(crate) request_timeout_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// With a certificate and key we serve https, see [`crate::tls`]
+pub(crate) struct TlsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<PathBuf>,
+    /// require client certificates issued by the CAs in this file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) client_ca: Option<PathBuf>,
+    /// how often the files are checked for changes
+    pub(crate) reload_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// How bills are worked out, see [`crate::billing`]
+pub(crate) struct BillingConfig {
+    /// consumption tax in percent, added on top of the menu prices
+    pub(crate) tax_percent: u32,
+    pub(crate) service_charge_percent: u32,
+    /// only parties of at least this many guests pay the service charge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) service_charge_min_guests: Option<u32>,
+    /// `down`, `up` or `nearest`, what happens to fractions of a yen
+    pub(crate) rounding: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Who takes card and deferred payments, see [`crate::payments`]. Without a provider only cash is taken.
+pub(crate) struct PaymentsConfig {
+    /// base url of the provider's http api
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) provider_url: Option<String>,
+    /// sent to the provider as bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) provider_key: Option<String>,
+    /// how long a single request to the provider may take
+    pub(crate) timeout_secs: u64,
+    /// how often a request the provider did not answer is tried again
+    pub(crate) retries: u32,
+    /// the wait before the first retry, doubled for every further one
+    pub(crate) retry_backoff_ms: u64,
+    /// how often payments the provider has not decided on yet are asked about again
+    pub(crate) reconcile_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// How long answers are kept for retries, see [`crate::idempotency`]
+pub(crate) struct IdempotencyConfig {
+    /// seconds a retry with the same idempotency key gets the first answer
+    pub(crate) window_secs: u64,
+    /// answers kept at most, the oldest are forgotten first
+    pub(crate) max_keys: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            tables: AMOUNT_OF_TABLES,
+            menu: PathBuf::from("menu.toml"),
+            seed: None,
+            log: LogConfig::default(),
+            storage: StorageConfig::default(),
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+            tls: TlsConfig::default(),
+            billing: BillingConfig::default(),
+            payments: PaymentsConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("data"),
+            durability: "always".to_string(),
+            snapshot_interval_secs: 60,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            keys: PathBuf::from("keys.toml"),
+            bootstrap_key: API_KEY.to_string(),
+            allow_query_key: true,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 2 * 1024 * 1024,
+            request_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert: None,
+            key: None,
+            client_ca: None,
+            reload_secs: 30,
+        }
+    }
+}
+
+impl Default for BillingConfig {
+    fn default() -> Self {
+        Self {
+            tax_percent: 10,
+            service_charge_percent: 0,
+            service_charge_min_guests: None,
+            rounding: "down".to_string(),
+        }
+    }
+}
+
+impl Default for PaymentsConfig {
+    fn default() -> Self {
+        Self {
+            provider_url: None,
+            provider_key: None,
+            timeout_secs: 8,
+            retries: 2,
+            retry_backoff_ms: 200,
+            reconcile_secs: 60,
+        }
+    }
+}
+
+impl PaymentsConfig {
+    /// The longest a payment can take to settle, with every attempt timing out
+    pub(crate) fn longest_settle(&self) -> Duration {
+        let attempts = u64::from(self.retries) + 1;
+        let backoff = (0..self.retries).fold(0u64, |sum, retry| {
+            let wait = 1u64.checked_shl(retry).map_or(u64::MAX, |factor| {
+                self.retry_backoff_ms.saturating_mul(factor)
+            });
+            sum.saturating_add(wait)
+        });
+        Duration::from_secs(self.timeout_secs.saturating_mul(attempts))
+            .saturating_add(Duration::from_millis(backoff))
+    }
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 60 * 60,
+            max_keys: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Parser)]
+#[clap(author, version, about)]
+/// The restaurant server. Every flag can also be set by its environment variable or in the config file.
+pub(crate) struct Cli {
+    /// The config file, `restaurant.toml` if it exists otherwise
+    #[clap(long, env = "RESTAURANT_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the effective configuration and exit
+    #[clap(long)]
+    pub(crate) print_config: bool,
+
+    /// Address and port to listen on, `listen`
+    #[clap(long, env = "RESTAURANT_LISTEN")]
+    listen: Option<SocketAddr>,
+    /// Number of tables to start a fresh data directory with, `tables`
+    #[clap(long, env = "RESTAURANT_TABLES")]
+    tables: Option<usize>,
+    /// The menu file, `menu`
+    #[clap(long, env = "RESTAURANT_MENU")]
+    menu: Option<PathBuf>,
+    /// Seed for reproducible cook times, `seed`
+    #[clap(long, env = "RESTAURANT_SEED")]
+    seed: Option<u64>,
+    /// error, warn, info, debug or trace, `log.level`
+    #[clap(long, env = "RESTAURANT_LOG")]
+    log_level: Option<String>,
+
+    /// Directory of the log and snapshot, `storage.dir`
+    #[clap(long, env = "RESTAURANT_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// When the log is fsynced: `always`, `never` or `every:<n>`, `storage.durability`
+    #[clap(long, env = "RESTAURANT_DURABILITY")]
+    durability: Option<String>,
+    /// Seconds between snapshots, `storage.snapshot_interval_secs`
+    #[clap(long, env = "RESTAURANT_SNAPSHOT_INTERVAL_SECS")]
+    snapshot_interval_secs: Option<u64>,
+
+    /// The api keys file, `auth.keys`
+    #[clap(long, env = "RESTAURANT_KEYS")]
+    keys: Option<PathBuf>,
+    /// Secret of the admin key a missing keys file starts with, `auth.bootstrap_key`
+    #[clap(long, env = "RESTAURANT_BOOTSTRAP_KEY", hide_env_values = true)]
+    bootstrap_key: Option<String>,
+    /// Accept the deprecated `key` query parameter, `auth.allow_query_key`
+    #[clap(long, env = "RESTAURANT_ALLOW_QUERY_KEY")]
+    allow_query_key: Option<bool>,
+
+    /// Largest accepted request body, `limits.max_body_bytes`
+    #[clap(long, env = "RESTAURANT_MAX_BODY_BYTES")]
+    max_body_bytes: Option<usize>,
+    /// Seconds a request may take, `limits.request_timeout_secs`
+    #[clap(long, env = "RESTAURANT_REQUEST_TIMEOUT_SECS")]
+    request_timeout_secs: Option<u64>,
+
+    /// PEM certificate chain to serve https with, `tls.cert`
+    #[clap(long, env = "RESTAURANT_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM key of the certificate, `tls.key`
+    #[clap(long, env = "RESTAURANT_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Only accept clients with a certificate from these CAs, `tls.client_ca`
+    #[clap(long, env = "RESTAURANT_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+    /// Seconds between checks for new certificates, `tls.reload_secs`
+    #[clap(long, env = "RESTAURANT_TLS_RELOAD_SECS")]
+    tls_reload_secs: Option<u64>,
+
+    /// Consumption tax in percent, `billing.tax_percent`
+    #[clap(long, env = "RESTAURANT_TAX_PERCENT")]
+    tax_percent: Option<u32>,
+    /// Service charge in percent, `billing.service_charge_percent`
+    #[clap(long, env = "RESTAURANT_SERVICE_CHARGE_PERCENT")]
+    service_charge_percent: Option<u32>,
+    /// Smallest party paying the service charge, `billing.service_charge_min_guests`
+    #[clap(long, env = "RESTAURANT_SERVICE_CHARGE_MIN_GUESTS")]
+    service_charge_min_guests: Option<u32>,
+    /// down, up or nearest, `billing.rounding`
+    #[clap(long, env = "RESTAURANT_ROUNDING")]
+    rounding: Option<String>,
+
+    /// Base url of the payment provider, `payments.provider_url`
+    #[clap(long, env = "RESTAURANT_PAYMENT_PROVIDER_URL")]
+    payment_provider_url: Option<String>,
+    /// Bearer token for the payment provider, `payments.provider_key`
+    #[clap(long, env = "RESTAURANT_PAYMENT_PROVIDER_KEY", hide_env_values = true)]
+    payment_provider_key: Option<String>,
+    /// Seconds a request to the provider may take, `payments.timeout_secs`
+    #[clap(long, env = "RESTAURANT_PAYMENT_TIMEOUT_SECS")]
+    payment_timeout_secs: Option<u64>,
+    /// Retries of a request the provider did not answer, `payments.retries`
+    #[clap(long, env = "RESTAURANT_PAYMENT_RETRIES")]
+    payment_retries: Option<u32>,
+    /// Milliseconds before the first retry, `payments.retry_backoff_ms`
+    #[clap(long, env = "RESTAURANT_PAYMENT_RETRY_BACKOFF_MS")]
+    payment_retry_backoff_ms: Option<u64>,
+    /// Seconds between asking about pending payments, `payments.reconcile_secs`
+    #[clap(long, env = "RESTAURANT_PAYMENT_RECONCILE_SECS")]
+    payment_reconcile_secs: Option<u64>,
+
+    /// Seconds answers are kept for retries with the same idempotency key, `idempotency.window_secs`
+    #[clap(long, env = "RESTAURANT_IDEMPOTENCY_WINDOW_SECS")]
+    idempotency_window_secs: Option<u64>,
+    /// Most answers kept for retries, `idempotency.max_keys`
+    #[clap(long, env = "RESTAURANT_IDEMPOTENCY_MAX_KEYS")]
+    idempotency_max_keys: Option<usize>,
+}
+
+impl Cli {
+    /// Parse `args`, taking the environment variables from `env` instead of the process environment.
+    /// The environment is handed to clap as flags ahead of `args`, so a flag given as well wins.
+    pub(crate) fn try_parse_with_env<I, T>(
+        args: I,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let command = Self::command();
+        let from_env = command.get_arguments().filter_map(|arg| {
+            let value = env(arg.get_env()?.to_str()?)?;
+            Some(OsString::from(format!("--{}={}", arg.get_long()?, value)))
+        });
+        let mut args = args.into_iter().map(Into::into);
+        let args: Vec<OsString> = args
+            .next()
+            .into_iter()
+            .chain(from_env)
+            .chain(args)
+            .collect();
+        // clap would read the process environment itself, the variables are only named in the help
+        let matches = command
+            .mut_args(|arg| match arg.get_env().and_then(OsStr::to_str) {
+                Some(name) => {
+                    let help = format!("{} [env: {}]", arg.get_help().unwrap_or_default(), name);
+                    arg.env(None).help(help)
+                }
+                None => arg,
+            })
+            .args_override_self(true)
+            .try_get_matches_from(args)?;
+        Self::from_arg_matches(&matches)
+    }
+}
+
+/// `target = value` for every value that is set
+macro_rules! set {
+    ($($target:expr => $value:expr),* $(,)?) => {
+        $(if let Some(value) = $value {
+            $target = value;
+        })*
+    };
+}
+
+impl Config {
+    /// Merge the defaults, the config file, the environment and the flags in `cli`, then validate the result
+    pub(crate) fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply(cli.clone());
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The defaults overridden by the file at `path`
+    pub(crate) fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Override everything set in `cli`, be it by flag or environment variable
+    fn apply(&mut self, cli: Cli) {
+        set! {
+            self.listen => cli.listen,
+            self.tables => cli.tables,
+            self.menu => cli.menu,
+            self.seed => cli.seed.map(Some),
+            self.log.level => cli.log_level,
+            self.storage.dir => cli.data_dir,
+            self.storage.durability => cli.durability,
+            self.storage.snapshot_interval_secs => cli.snapshot_interval_secs,
+            self.auth.keys => cli.keys,
+            self.auth.bootstrap_key => cli.bootstrap_key,
+            self.auth.allow_query_key => cli.allow_query_key,
+            self.limits.max_body_bytes => cli.max_body_bytes,
+            self.limits.request_timeout_secs => cli.request_timeout_secs,
+            self.tls.cert => cli.tls_cert.map(Some),
+            self.tls.key => cli.tls_key.map(Some),
+            self.tls.client_ca => cli.tls_client_ca.map(Some),
+            self.tls.reload_secs => cli.tls_reload_secs,
+            self.billing.tax_percent => cli.tax_percent,
+            self.billing.service_charge_percent => cli.service_charge_percent,
+            self.billing.service_charge_min_guests => cli.service_charge_min_guests.map(Some),
+            self.billing.rounding => cli.rounding,
+            self.payments.provider_url => cli.payment_provider_url.map(Some),
+            self.payments.provider_key => cli.payment_provider_key.map(Some),
+            self.payments.timeout_secs => cli.payment_timeout_secs,
+            self.payments.retries => cli.payment_retries,
+            self.payments.retry_backoff_ms => cli.payment_retry_backoff_ms,
+            self.payments.reconcile_secs => cli.payment_reconcile_secs,
+            self.idempotency.window_secs => cli.idempotency_window_secs,
+            self.idempotency.max_keys => cli.idempotency_max_keys,
+        }
+    }
+
+    /// Check everything at once, so all mistakes are reported together
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+        if self.tables == 0 {
+            problems.push("tables has to be at least 1".to_string());
+        }
+        if self.log.level.parse::<tracing::Level>().is_err() {
+            problems.push(format!(
+                "log.level '{}' is not one of error, warn, info, debug or trace",
+                self.log.level
+            ));
+        }
+        #[cfg(feature = "durable")]
+        if let Err(e) = self
+            .storage
+            .durability
+            .parse::<crate::storage::Durability>()
+        {
+            problems.push(format!("storage.durability: {}", e));
+        }
+        if self.storage.snapshot_interval_secs == 0 {
+            problems.push("storage.snapshot_interval_secs has to be at least 1".to_string());
+        }
+        if self.auth.bootstrap_key.is_empty() {
+            problems.push("auth.bootstrap_key cannot be empty".to_string());
+        }
+        if self.limits.max_body_bytes == 0 {
+            problems.push("limits.max_body_bytes has to be at least 1".to_string());
+        }
+        if self.limits.request_timeout_secs == 0 {
+            problems.push("limits.request_timeout_secs has to be at least 1".to_string());
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            problems.push("tls.cert and tls.key need to be set together".to_string());
+        }
+        if self.tls.client_ca.is_some() && self.tls.cert.is_none() {
+            problems.push("tls.client_ca needs tls.cert and tls.key".to_string());
+        }
+        if self.tls.reload_secs == 0 {
+            problems.push("tls.reload_secs has to be at least 1".to_string());
+        }
+        if self.billing.tax_percent > 100 {
+            problems.push("billing.tax_percent cannot be more than 100".to_string());
+        }
+        if self.billing.service_charge_percent > 100 {
+            problems.push("billing.service_charge_percent cannot be more than 100".to_string());
+        }
+        if let Err(e) = self.billing.rounding.parse::<crate::billing::Rounding>() {
+            problems.push(format!("billing.rounding: {}", e));
+        }
+        if let Some(url) = &self.payments.provider_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                problems.push(format!(
+                    "payments.provider_url '{}' is not an http or https url",
+                    url
+                ));
+            }
+        }
+        if self.payments.provider_key.is_some() && self.payments.provider_url.is_none() {
+            problems.push("payments.provider_key needs payments.provider_url".to_string());
+        }
+        if self.payments.timeout_secs == 0 {
+            problems.push("payments.timeout_secs has to be at least 1".to_string());
+        }
+        // a request timing out frees its idempotency key while the payment may still go through
+        if self.payments.provider_url.is_some()
+            && self.payments.longest_settle() >= self.request_timeout()
+        {
+            problems.push(format!(
+                "payments.timeout_secs, retries and retry_backoff_ms let a payment take up to {:?}, \
+                 which has to stay below limits.request_timeout_secs",
+                self.payments.longest_settle()
+            ));
+        }
+        if self.payments.reconcile_secs == 0 {
+            problems.push("payments.reconcile_secs has to be at least 1".to_string());
+        }
+        if self.idempotency.window_secs == 0 {
+            problems.push("idempotency.window_secs has to be at least 1".to_string());
+        }
+        if self.idempotency.max_keys == 0 {
+            problems.push("idempotency.max_keys has to be at least 1".to_string());
+        }
+        anyhow::ensure!(
+            problems.is_empty(),
+            "Invalid configuration:\n  {}",
+            problems.join("\n  ")
+        );
+        Ok(())
+    }
+
+    /// The configuration as TOML, without secrets
+    pub(crate) fn to_toml(&self) -> String {
+        let mut shown = self.clone();
+        shown.auth.bootstrap_key = "<redacted>".to_string();
+        if shown.payments.provider_key.is_some() {
+            shown.payments.provider_key = Some("<redacted>".to_string());
+        }
+        toml::to_string_pretty(&shown).expect("the config is always valid TOML")
+    }
+
+    pub(crate) fn log_level(&self) -> tracing::Level {
+        self.log.level.parse().unwrap_or(tracing::Level::INFO)
+    }
+
+    pub(crate) fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.limits.request_timeout_secs)
+    }
+}
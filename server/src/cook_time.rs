@@ -0,0 +1,68 @@
+//! Estimating how long the kitchen needs for an item of a dish, following the dish's [`CookTimeModel`].
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::types::{CookTimeModel, MenuEntry};
+
+/// the most cook times a learned model weighs, so it keeps following a kitchen that gets faster or slower
+const LEARNED_WINDOW: u64 = 20;
+
+/// Check that the cook time model of `entry` fits its range
+pub(crate) fn validate(entry: &MenuEntry) -> Result<(), String> {
+    match entry.cook_time {
+        CookTimeModel::Fixed { minutes }
+            if minutes < entry.min_cook_minutes || minutes > entry.max_cook_minutes =>
+        {
+            Err(format!(
+                "menu entry {} has a fixed cook time of {} minutes outside of its range",
+                entry.item_number, minutes
+            ))
+        }
+        CookTimeModel::Normal { mean, std_dev }
+            if !mean.is_finite() || !std_dev.is_finite() || std_dev < 0.0 =>
+        {
+            Err(format!(
+                "menu entry {} needs a finite mean and a finite, non-negative standard deviation",
+                entry.item_number
+            ))
+        }
+        CookTimeModel::Learned { mean, .. } if !mean.is_finite() || mean < 0.0 => Err(format!(
+            "menu entry {} has a learned mean that is not a duration",
+            entry.item_number
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Draw the cook time in minutes for a new item of `entry`
+pub(crate) fn sample(entry: &MenuEntry, rng: &mut impl Rng) -> u64 {
+    let (min, max) = (entry.min_cook_minutes, entry.max_cook_minutes);
+    let minutes = match entry.cook_time {
+        CookTimeModel::Fixed { minutes } => minutes,
+        CookTimeModel::Uniform => rng.gen_range(min..=max),
+        CookTimeModel::Normal { mean, std_dev } => whole_minutes(
+            Normal::new(mean, std_dev)
+                .map(|normal| normal.sample(rng))
+                .unwrap_or(mean),
+        ),
+        CookTimeModel::Learned { samples: 0, .. } => (min + max) / 2,
+        CookTimeModel::Learned { mean, .. } => whole_minutes(mean),
+    };
+    minutes.clamp(min, max)
+}
+
+/// Teach `model` that the kitchen took `minutes` for an item. Returns if the model changed, only learned models do.
+pub(crate) fn observe(model: &mut CookTimeModel, minutes: f64) -> bool {
+    match model {
+        CookTimeModel::Learned { samples, mean } => {
+            *samples = (*samples + 1).min(LEARNED_WINDOW);
+            *mean += (minutes - *mean) / *samples as f64;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn whole_minutes(minutes: f64) -> u64 {
+    minutes.round().max(0.0) as u64
+}
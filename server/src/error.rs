@@ -0,0 +1,153 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use restaurant_types::{ErrorBody, ErrorKind, Role};
+
+use crate::store::StoreError;
+
+#[derive(Debug)]
+/// A failed request, answered with the matching status and an [`ErrorBody`]
+pub(crate) struct ApiError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl ApiError {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// the api key is missing or wrong
+    pub(crate) fn unauthorized() -> Self {
+        Self::new(ErrorKind::Unauthorized, "invalid api key")
+    }
+
+    /// the key is fine, but may not do this
+    pub(crate) fn forbidden(role: Role) -> Self {
+        Self::new(
+            ErrorKind::Forbidden,
+            format!("a {} key may not do this", role),
+        )
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::UnknownTable => Self::new(ErrorKind::NotFound, "no such table"),
+            StoreError::InactiveTable => {
+                Self::new(ErrorKind::Conflict, "the table takes no orders right now")
+            }
+            StoreError::TableNotEmpty => Self::new(
+                ErrorKind::Conflict,
+                "the table still has items or a party seated, deactivate it instead",
+            ),
+            StoreError::InvalidTable(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::NoSession => {
+                Self::new(ErrorKind::NotFound, "nobody is seated at the table")
+            }
+            StoreError::SessionOpen => Self::new(
+                ErrorKind::Conflict,
+                "a party is already seated at the table",
+            ),
+            StoreError::OpenItems => Self::new(
+                ErrorKind::Conflict,
+                "the session still has items that are neither served nor cancelled",
+            ),
+            StoreError::InvalidSession(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::InvalidAdjustment(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::UnknownItem => Self::new(ErrorKind::NotFound, "no such item"),
+            StoreError::InvalidSeat => Self::new(ErrorKind::BadRequest, "seats are counted from 1"),
+            StoreError::InvalidSplit(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::UnknownPayment => Self::new(ErrorKind::NotFound, "no such payment"),
+            StoreError::InvalidPayment(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::NoPaymentProvider => Self::new(
+                ErrorKind::BadRequest,
+                "only cash is taken, there is no payment provider",
+            ),
+            StoreError::VersionMismatch { current } => Self::new(
+                ErrorKind::PreconditionFailed,
+                format!("the table changed, it is at version {} now", current),
+            ),
+            StoreError::SameTable => {
+                Self::new(ErrorKind::BadRequest, "the items are already on that table")
+            }
+            StoreError::IllegalTransition { from, to } => Self::new(
+                ErrorKind::Conflict,
+                format!("an item cannot go from {} to {}", from, to),
+            ),
+            StoreError::NotOrderable {
+                unknown,
+                unavailable,
+            } => {
+                let mut problems = vec![];
+                if !unknown.is_empty() {
+                    problems.push(format!("not on the menu: {}", join(&unknown)));
+                }
+                if !unavailable.is_empty() {
+                    problems.push(format!("not available: {}", join(&unavailable)));
+                }
+                Self::new(
+                    ErrorKind::BadRequest,
+                    format!("cannot order items, {}", problems.join("; ")),
+                )
+            }
+            StoreError::UnknownMenuEntry => Self::new(ErrorKind::NotFound, "no such menu entry"),
+            StoreError::DuplicateMenuEntry => Self::new(
+                ErrorKind::Conflict,
+                "there already is a menu entry with this item number",
+            ),
+            StoreError::InvalidMenuEntry(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::UnknownKey => Self::new(ErrorKind::NotFound, "no such key"),
+            StoreError::LastAdminKey => Self::new(
+                ErrorKind::Conflict,
+                "cannot revoke the last admin key, create another one first",
+            ),
+            StoreError::InvalidKey(reason) => Self::new(ErrorKind::BadRequest, reason),
+            StoreError::Storage(e) => {
+                tracing::error!("Could not persist change: {}", e);
+                Self::new(ErrorKind::Internal, "could not persist change")
+            }
+        }
+    }
+}
+
+/// `1, 2, 3`
+fn join(numbers: &[u64]) -> String {
+    numbers
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        (
+            status,
+            Json(ErrorBody {
+                error: self.kind,
+                message: self.message,
+            }),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,182 @@
+//! Pushing what happens in the restaurant to tablets and the kitchen, instead of them polling all tables.
+//!
+//! Every event is broadcast to all subscribers and kept in a bounded history, so a subscriber that reconnects
+//! can resume after the last event it saw. Each subscriber has a bounded buffer. A subscriber that falls so far
+//! behind that its buffer overflows, or that resumes from an event no longer in the history, gets a
+//! [`EventKind::Resync`] instead of the lost events and carries on with the newest ones.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use restaurant_types::{Event, EventKind};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// how many events a subscriber may fall behind and how many we keep for resuming
+const CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Which events a subscriber wants
+pub(crate) struct EventFilter {
+    pub(crate) table_number: Option<usize>,
+    pub(crate) station: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        if event.kind == EventKind::Resync {
+            return true;
+        }
+        self.table_number.is_none_or(|table_number| {
+            event.table_number == Some(table_number)
+                || matches!(event.kind, EventKind::ItemMoved { from, .. } if from == table_number)
+        }) && self
+            .station
+            .as_ref()
+            .is_none_or(|station| event.station.as_ref() == Some(station))
+    }
+}
+
+#[derive(Debug)]
+struct History {
+    /// the id the next event gets
+    next_id: u64,
+    events: VecDeque<Event>,
+}
+
+#[derive(Debug)]
+/// Hands out ids to events and distributes them to the subscribers
+pub(crate) struct EventHub {
+    sender: broadcast::Sender<Event>,
+    /// shared with the subscriptions, which need the newest id when they fall behind
+    history: Arc<Mutex<History>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventHub {
+    /// Ids start at the current time in microseconds, so they keep increasing over restarts
+    /// and a subscriber resuming with an id from before a restart is told to resync.
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            history: Arc::new(Mutex::new(History {
+                next_id: Utc::now().timestamp_micros().max(1) as u64,
+                events: VecDeque::with_capacity(CAPACITY),
+            })),
+        }
+    }
+
+    /// Give `kind` the next id and send it to everybody subscribed
+    pub(crate) fn publish(
+        &self,
+        at: DateTime<Utc>,
+        table_number: usize,
+        station: Option<String>,
+        kind: EventKind,
+    ) {
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        let event = Event {
+            id: history.next_id,
+            at,
+            table_number: Some(table_number),
+            station,
+            kind,
+        };
+        history.next_id += 1;
+        if history.events.len() == CAPACITY {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+        // nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to the events matching `filter`.
+    /// With `last_event_id` the subscription starts with the events after it that are still in the history.
+    pub(crate) fn subscribe(
+        &self,
+        filter: EventFilter,
+        last_event_id: Option<u64>,
+    ) -> Subscription {
+        // holding the lock means no event is published between reading the history and subscribing
+        let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        let receiver = self.sender.subscribe();
+        let newest = history.next_id - 1;
+        let backlog = match last_event_id {
+            None => VecDeque::new(),
+            Some(last) if last == newest => VecDeque::new(),
+            Some(last)
+                if last < newest
+                    && history
+                        .events
+                        .front()
+                        .is_some_and(|oldest| oldest.id <= last + 1) =>
+            {
+                history
+                    .events
+                    .iter()
+                    .filter(|event| event.id > last)
+                    .cloned()
+                    .collect()
+            }
+            // too old or not from us
+            Some(_) => VecDeque::from([resync(newest)]),
+        };
+        Subscription {
+            backlog,
+            receiver,
+            filter,
+            history: self.history.clone(),
+        }
+    }
+}
+
+/// The event telling a subscriber it missed something, carrying the newest id there is
+fn resync(newest: u64) -> Event {
+    Event {
+        id: newest,
+        at: Utc::now(),
+        table_number: None,
+        station: None,
+        kind: EventKind::Resync,
+    }
+}
+
+/// The events a single subscriber gets
+pub(crate) struct Subscription {
+    /// events from before subscribing the subscriber asked for
+    backlog: VecDeque<Event>,
+    receiver: broadcast::Receiver<Event>,
+    filter: EventFilter,
+    history: Arc<Mutex<History>>,
+}
+
+impl Subscription {
+    /// The next matching event. `None` once the hub is gone.
+    pub(crate) async fn next(&mut self) -> Option<Event> {
+        while let Some(event) = self.backlog.pop_front() {
+            if self.filter.matches(&event) {
+                return Some(event);
+            }
+        }
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.filter.matches(&event) => return Some(event),
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => {
+                    // skip what is left in the buffer and go on with the events after the newest one
+                    let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+                    self.receiver = self.receiver.resubscribe();
+                    return Some(resync(history.next_id - 1));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
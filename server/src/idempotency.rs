@@ -0,0 +1,239 @@
+//! Safe retries of changing requests. A client sends the same `Idempotency-Key` header with every attempt of a
+//! `POST`, `PUT`, `PATCH` or `DELETE`. The answer to the first attempt is kept for `idempotency.window_secs`
+//! and every further attempt gets it again, marked with `Idempotent-Replayed: true`, without doing anything.
+//! A tablet that lost an answer can ask again without ordering twice or removing a second item.
+//!
+//! Keys belong to the api key sending them. Reusing one for a different request answers 409, so does an attempt
+//! arriving while the first one is still handled. Server errors are not kept, as nothing was changed by them.
+//! Requests without the header are handled like before. The kept answers live in memory only.
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use restaurant_types::{ErrorKind, IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED, MAX_IDEMPOTENCY_KEY_LEN};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::IdempotencyConfig, error::ApiError, store::RestaurantStore, types::KeyInfo, AppState,
+};
+
+/// the id of the api key and the idempotency key it sent
+type Key = (u64, String);
+
+#[derive(Clone, Debug)]
+/// The answer to the first attempt
+struct Answer {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// hash of the method, path and body, to tell a retry from a different request
+    fingerprint: [u8; 32],
+    /// none while the first attempt is handled
+    answer: Option<(Answer, DateTime<Utc>)>,
+}
+
+#[derive(Debug, Default)]
+/// The kept answers, the oldest answer is the next to expire
+struct Kept {
+    entries: HashMap<Key, Entry>,
+    /// (expires at, key) of every answered entry
+    by_expiry: BTreeSet<(DateTime<Utc>, Key)>,
+}
+
+impl Kept {
+    fn remove_oldest(&mut self) {
+        if let Some((_, key)) = self.by_expiry.pop_first() {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// What [`remember`] needs to know
+pub(crate) struct IdempotencyState<S> {
+    store: AppState<S>,
+    kept: Arc<Mutex<Kept>>,
+    window: chrono::Duration,
+    /// answers kept at most, the oldest are forgotten first
+    max_keys: usize,
+}
+
+impl<S> IdempotencyState<S> {
+    pub(crate) fn new(store: AppState<S>, config: &IdempotencyConfig) -> Self {
+        Self {
+            store,
+            kept: Arc::default(),
+            window: chrono::Duration::seconds(config.window_secs as i64),
+            max_keys: config.max_keys,
+        }
+    }
+}
+
+impl<S> Clone for IdempotencyState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            kept: self.kept.clone(),
+            window: self.window,
+            max_keys: self.max_keys,
+        }
+    }
+}
+
+/// Forgets the entry of an attempt that did not finish, i.e., timed out, so the next attempt is handled
+struct Pending {
+    kept: Arc<Mutex<Kept>>,
+    key: Key,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let mut kept = self.kept.lock().unwrap_or_else(PoisonError::into_inner);
+        if kept
+            .entries
+            .get(&self.key)
+            .is_some_and(|entry| entry.answer.is_none())
+        {
+            kept.entries.remove(&self.key);
+        }
+    }
+}
+
+/// Middleware answering repeated attempts of a changing request with the kept answer of the first one.
+/// Has to run after [`crate::auth::authorize`], which adds the [`KeyInfo`] the keys belong to.
+pub(crate) async fn remember<S: RestaurantStore>(
+    State(state): State<IdempotencyState<S>>,
+    Extension(holder): Extension<KeyInfo>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(next.run(request).await);
+    }
+    let Some(value) = request.headers().get(IDEMPOTENCY_KEY) else {
+        return Ok(next.run(request).await);
+    };
+    let idempotency_key = value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN)
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorKind::BadRequest,
+                format!(
+                    "an idempotency key has 1 to {} visible ascii characters",
+                    MAX_IDEMPOTENCY_KEY_LEN
+                ),
+            )
+        })?
+        .to_string();
+
+    let (parts, body) = request.into_parts();
+    // the body limit of the router applies here as well
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| ApiError::new(ErrorKind::BadRequest, "the body is too large"))?;
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |path| path.as_str());
+    let fingerprint = Sha256::new()
+        .chain_update(parts.method.as_str())
+        .chain_update([0])
+        .chain_update(path)
+        .chain_update([0])
+        .chain_update(&body)
+        .finalize()
+        .into();
+
+    let key = (holder.id, idempotency_key);
+    {
+        let mut kept = state.kept.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = state.store.now();
+        while kept.by_expiry.first().is_some_and(|(at, _)| *at <= now) {
+            kept.remove_oldest();
+        }
+        match kept.entries.get(&key) {
+            Some(entry) if entry.fingerprint != fingerprint => {
+                return Err(ApiError::new(
+                    ErrorKind::Conflict,
+                    "the idempotency key was already used for a different request",
+                ))
+            }
+            Some(Entry { answer: None, .. }) => {
+                return Err(ApiError::new(
+                    ErrorKind::Conflict,
+                    "a request with this idempotency key is still handled",
+                ))
+            }
+            Some(Entry {
+                answer: Some((answer, _)),
+                ..
+            }) => return Ok(replay(answer.clone())),
+            None => {
+                kept.entries.insert(
+                    key.clone(),
+                    Entry {
+                        fingerprint,
+                        answer: None,
+                    },
+                );
+            }
+        }
+    }
+    let pending = Pending {
+        kept: state.kept.clone(),
+        key,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status().is_server_error() {
+        return Ok(response);
+    }
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| ApiError::new(ErrorKind::Internal, "could not read the answer"))?;
+    let answer = Answer {
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    };
+    let mut kept = state.kept.lock().unwrap_or_else(PoisonError::into_inner);
+    let expires_at = state.store.now() + state.window;
+    if let Some(entry) = kept.entries.get_mut(&pending.key) {
+        entry.answer = Some((answer, expires_at));
+        kept.by_expiry.insert((expires_at, pending.key.clone()));
+    }
+    while kept.by_expiry.len() > state.max_keys {
+        kept.remove_oldest();
+    }
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// The kept `answer`, marked as replayed
+fn replay(answer: Answer) -> Response {
+    let mut response = Response::new(Body::from(answer.body));
+    *response.status_mut() = answer.status;
+    *response.headers_mut() = answer.headers;
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
@@ -0,0 +1,76 @@
+//! The kitchen's view of the restaurant: not tables, but the items that still have to be cooked.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use restaurant_types::{QueueEntry, QueueGroup};
+
+use crate::types::{with_countdown, ItemStatus, MenuEntry, Table};
+
+/// The open items of all `tables`, the dish with the highest priority first and otherwise the longest waiting.
+/// With a `station` only the items of dishes cooked there are kept.
+pub(crate) fn queue(
+    tables: Vec<Table>,
+    menu: &[MenuEntry],
+    station: Option<&str>,
+    now: DateTime<Utc>,
+) -> Vec<QueueEntry> {
+    let menu = menu
+        .iter()
+        .map(|entry| (entry.item_number, entry))
+        .collect::<HashMap<u64, &MenuEntry>>();
+    let mut queue = tables
+        .into_iter()
+        .flat_map(|table| {
+            let table_number = table.table_number;
+            table
+                .items
+                .into_iter()
+                .map(move |item| (table_number, item))
+        })
+        .filter(|(_, item)| matches!(item.status, ItemStatus::Ordered | ItemStatus::Cooking))
+        .map(|(table_number, item)| {
+            let entry = menu.get(&item.item_number);
+            QueueEntry {
+                table_number,
+                name: entry.map(|e| e.name.clone()).unwrap_or_default(),
+                station: entry.and_then(|e| e.station.clone()),
+                priority: entry.map_or(0, |e| e.priority),
+                item: with_countdown(item, now),
+            }
+        })
+        .filter(|entry| station.is_none_or(|station| entry.station.as_deref() == Some(station)))
+        .collect::<Vec<QueueEntry>>();
+    queue.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then(a.item.ordered_at.cmp(&b.item.ordered_at))
+            .then(a.item.id.cmp(&b.item.id))
+    });
+    queue
+}
+
+/// Group a `queue` by dish, keeping the order in which each dish first shows up in it
+pub(crate) fn group(queue: Vec<QueueEntry>) -> Vec<QueueGroup> {
+    let mut groups: Vec<QueueGroup> = vec![];
+    for entry in queue {
+        match groups
+            .iter_mut()
+            .find(|group| group.item_number == entry.item.item_number)
+        {
+            Some(group) => {
+                group.count += 1;
+                group.item_ids.push(entry.item.id);
+                group.oldest_ordered_at = group.oldest_ordered_at.min(entry.item.ordered_at);
+            }
+            None => groups.push(QueueGroup {
+                item_number: entry.item.item_number,
+                name: entry.name,
+                station: entry.station,
+                count: 1,
+                item_ids: vec![entry.item.id],
+                oldest_ordered_at: entry.item.ordered_at,
+            }),
+        }
+    }
+    groups
+}
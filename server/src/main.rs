@@ -1,153 +1,852 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    routing::{delete, get},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, Path, Query, State,
+    },
+    http::HeaderMap,
+    middleware,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use tower_http::trace::{self, TraceLayer};
+use config::{Cli, Config};
+use events::{EventFilter, Subscription};
+use futures_util::Stream;
+use std::{sync::Arc, time::Duration};
+
+use error::ApiError;
+use restaurant_types::{routes, ErrorKind, Event, EventQuery, KitchenQuery};
+use store::{checkpoint_periodically, Menu, RestaurantStore, StoreError};
+use tower_http::{
+    limit::RequestBodyLimitLayer,
+    timeout::TimeoutLayer,
+    trace::{self, TraceLayer},
+};
 use tracing::Level;
 use types::{
-    is_table_empty, new_app_state, AppState, MenuItem, QueryParam, Table, AMOUNT_OF_TABLES, API_KEY,
+    with_countdown, Adjustment, Bill, BillPayments, IssuedKey, ItemStatus, ItemTransfer, KeyInfo,
+    MenuEntry, MenuItem, NewAdjustment, NewKey, NewPayment, NewRefund, NewTable, OrderQuery,
+    Payment, PaymentQuery, QueryParam, SeatUpdate, Session, SessionDetails, SessionInfo, Snapshot,
+    SplitBill, SplitMode, StatusUpdate, Table, TableInfo,
 };
 
+mod auth;
+mod billing;
+mod clock;
+mod config;
+mod cook_time;
+mod error;
+mod events;
+mod idempotency;
+mod kitchen;
+mod paging;
+mod payments;
+#[cfg(feature = "durable")]
+mod storage;
+mod store;
 mod tests;
+mod tls;
 mod types;
+mod versions;
+
+/// The backend selected by the cargo features
+#[cfg(feature = "durable")]
+type Backend = store::DurableStore;
+/// The backend selected by the cargo features
+#[cfg(not(feature = "durable"))]
+type Backend = store::MemoryStore;
+
+/// The whole state of the app
+pub(crate) type AppState<S> = Arc<S>;
+
+/// if `item` passes the optional status filter
+fn matches_status(item: &MenuItem, status: Option<ItemStatus>) -> bool {
+    status.is_none_or(|status| item.status == status)
+}
 
 /// Returns all items for all tables, if supplied the limit applies to the number of tables, not the number of menuitems
-/// We do not return tables that do not have menuitems
-async fn get_all_items(
+/// We do not return tables that do not have menuitems (matching the status filter, if given), nor count them
+/// With `snapshot` we answer with a [`Snapshot`] carrying the version the tables were read at.
+/// The countdown depends on when we are asked, so snapshots do not update it to keep equal versions identical.
+/// With `paged` we answer with a [`Page`](restaurant_types::Page) of the non-empty tables, see [`paging`].
+async fn get_all_items<S: RestaurantStore>(
     Query(query): Query<QueryParam>,
-    State(state): State<AppState>,
-) -> Result<Json<Vec<Table>>, StatusCode> {
-    if query.key != API_KEY {
-        Err(StatusCode::UNAUTHORIZED)
+    State(state): State<AppState<S>>,
+) -> Result<Response, ApiError> {
+    if query.paged && query.snapshot {
+        Err(ApiError::new(
+            ErrorKind::BadRequest,
+            "snapshot and paged cannot be combined",
+        ))
+    } else if query.paged {
+        let now = state.now();
+        let sort = query.sort.unwrap_or_default();
+        let tables = state
+            .snapshot_all()
+            .await
+            .tables
+            .into_iter()
+            .map(|mut t| {
+                t.items = t
+                    .items
+                    .into_iter()
+                    .filter(|item| matches_status(item, query.status))
+                    .map(|item| with_countdown(item, now))
+                    .collect();
+                t.items
+                    .sort_by_key(|item| paging::item_position(item, sort));
+                t
+            })
+            .filter(|t| !t.items.is_empty())
+            .collect();
+        let page = paging::page(tables, |t| paging::table_position(t, sort), &query)?;
+        Ok(Json(page).into_response())
     } else {
-        let mut non_empty_tables = vec![];
-        // filter does not work in async yet
-        for t in state
-            .iter()
-            .take(query.limit.unwrap_or(AMOUNT_OF_TABLES as u64) as usize)
-        {
-            if !is_table_empty(t).await {
-                non_empty_tables.push(t.read().await.to_owned());
-            }
+        paging::check_unpaged(&query)?;
+        let now = state.now();
+        let snapshot = state.snapshot_all().await;
+        let non_empty_tables: Vec<Table> = snapshot
+            .tables
+            .into_iter()
+            .map(|mut t| {
+                t.items = t
+                    .items
+                    .into_iter()
+                    .filter(|item| matches_status(item, query.status))
+                    .map(|item| {
+                        if query.snapshot {
+                            item
+                        } else {
+                            with_countdown(item, now)
+                        }
+                    })
+                    .collect();
+                t
+            })
+            .filter(|t| !t.items.is_empty())
+            .take(query.limit.map_or(usize::MAX, |limit| limit as usize))
+            .collect();
+        if query.snapshot {
+            Ok(Json(Snapshot {
+                version: snapshot.version,
+                tables: non_empty_tables,
+            })
+            .into_response())
+        } else {
+            Ok(Json(non_empty_tables).into_response())
         }
-        Ok(Json(non_empty_tables))
     }
 }
 
-/// returns the items for a given `table_id`, table_id start at zero.
-async fn get_items_for_table(
+/// returns the items for a given `table_id`, table_id start at zero. Can be filtered by status.
+/// Every returned item carries the minutes remaining until it is expected to be ready.
+/// With `paged` we answer with a [`Page`](restaurant_types::Page) of the items, see [`paging`].
+async fn get_items_for_table<S: RestaurantStore>(
     Path(table_number): Path<usize>,
     Query(query): Query<QueryParam>,
-    State(state): State<AppState>,
-) -> Result<Json<Vec<MenuItem>>, StatusCode> {
-    if query.key != API_KEY {
-        Err(StatusCode::UNAUTHORIZED)
-    } else if let Some(table_lock) = state.get(table_number).map(|table| table.read()) {
-        let table_lock = table_lock.await;
-        let limit = query.limit.unwrap_or(table_lock.items.len() as u64);
-        let new_items = table_lock
+    State(state): State<AppState<S>>,
+) -> Result<Response, ApiError> {
+    if query.paged {
+        let now = state.now();
+        let sort = query.sort.unwrap_or_default();
+        let items = state
+            .table(table_number)
+            .await?
+            .items
+            .into_iter()
+            .filter(|item| matches_status(item, query.status))
+            .map(|item| with_countdown(item, now))
+            .collect();
+        let page = paging::page(items, |item| paging::item_position(item, sort), &query)?;
+        Ok(Json(page).into_response())
+    } else {
+        paging::check_unpaged(&query)?;
+        let table = state.table(table_number).await.map_err(ApiError::from)?;
+        let limit = query.limit.unwrap_or(table.items.len() as u64);
+        let new_items = table
             .items
-            .iter()
-            .cloned()
+            .into_iter()
+            .filter(|item| matches_status(item, query.status))
             .take(limit as usize)
+            .map(|item| with_countdown(item, state.now()))
             .collect::<Vec<MenuItem>>();
-        Ok(Json(new_items))
+        Ok(Json(new_items).into_response())
+    }
+}
+
+/// returns a specific item by its position on the table.
+/// Deprecated: positions shift when other items are deleted, use `get_item_by_id` instead.
+async fn get_item<S: RestaurantStore>(
+    Path((table_number, item_position)): Path<(usize, usize)>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<Vec<MenuItem>>, ApiError> {
+    if let Ok(table) = state.table(table_number).await {
+        Ok(Json(
+            table
+                .items
+                .get(item_position)
+                .into_iter()
+                .map(|item| with_countdown(item.clone(), state.now()))
+                .collect(),
+        ))
     } else {
-        Err(StatusCode::NOT_FOUND)
+        Ok(Json(vec![]))
     }
 }
 
-/// returns a specific item
-async fn get_item(
-    Path((table_number, item_number)): Path<(usize, usize)>,
-    Query(query): Query<QueryParam>,
-    State(state): State<AppState>,
-) -> Result<Json<Vec<MenuItem>>, StatusCode> {
-    if query.key != API_KEY {
-        Err(StatusCode::UNAUTHORIZED)
-    } else if let Some(table_lock) = state.get(table_number) {
-        let table_items = &table_lock.read().await.items;
-        if let Some(item) = table_items.get(item_number) {
-            Ok(Json(vec![*item]))
-        } else {
-            Ok(Json(vec![]))
-        }
+/// returns a specific item given by its `item_id` on the table `table_id`
+async fn get_item_by_id<S: RestaurantStore>(
+    Path((table_number, item_id)): Path<(usize, u64)>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<Vec<MenuItem>>, ApiError> {
+    if let Ok(item) = state.item(table_number, item_id).await {
+        Ok(Json(
+            item.into_iter()
+                .map(|item| with_countdown(item, state.now()))
+                .collect(),
+        ))
     } else {
         Ok(Json(vec![]))
     }
 }
 
-/// adds items to a table given by `table_id` (starting at zero) with the body a json. Returns if we successfully added the items.
-/// Notice that this does not add items to the table if we are out of tables.
-async fn add_item_to_table(
+/// moves the item `item_id` on table `table_id` to the status given in the body and returns the updated item.
+/// Answers 409 if the item cannot go to that status from where it is, i.e., from served back to cooking.
+async fn update_item_status<S: RestaurantStore>(
+    Path((table_number, item_id)): Path<(usize, u64)>,
+    State(state): State<AppState<S>>,
+    Json(update): Json<StatusUpdate>,
+) -> Result<Json<MenuItem>, ApiError> {
+    let item = state
+        .set_status(table_number, item_id, update.status)
+        .await?;
+    Ok(Json(with_countdown(item, state.now())))
+}
+
+/// moves the item `item_id` on table `table_id` to the seat given in the body and returns the updated item.
+/// Without a seat the item is for the whole table and split evenly.
+async fn update_item_seat<S: RestaurantStore>(
+    Path((table_number, item_id)): Path<(usize, u64)>,
+    State(state): State<AppState<S>>,
+    Json(update): Json<SeatUpdate>,
+) -> Result<Json<MenuItem>, ApiError> {
+    let item = state.set_seat(table_number, item_id, update.seat).await?;
+    Ok(Json(with_countdown(item, state.now())))
+}
+
+/// adds items to a table given by `table_id` (starting at zero) with the body a json. Returns the ids of the created items.
+/// Notice that this does not add items to the table if we are out of tables, in which case the returned list is empty.
+/// If any item number is not on the menu or not available nothing is added and we answer 400 listing them.
+/// Inactive tables answer 409. With `seat` all items are for the guest on that seat.
+async fn add_item_to_table<S: RestaurantStore>(
     Path(table_number): Path<usize>,
-    Query(query): Query<QueryParam>,
-    State(state): State<AppState>,
+    Query(query): Query<OrderQuery>,
+    State(state): State<AppState<S>>,
     Json(vec_items): Json<Vec<u64>>,
-) -> Result<Json<bool>, StatusCode> {
-    if query.key != API_KEY {
-        Err(StatusCode::UNAUTHORIZED)
-    } else if let Some(table) = state.get(table_number) {
-        let mut table_mut = table.write().await;
-        for i in vec_items {
-            table_mut.items.push(MenuItem::new(i));
-        }
-        Ok(Json(true))
-    } else {
-        Ok(Json(false))
+) -> Result<Json<Vec<u64>>, ApiError> {
+    match state.add_items(table_number, vec_items, query.seat).await {
+        Ok(items) => Ok(Json(items.iter().map(|item| item.id).collect())),
+        Err(StoreError::UnknownTable) => Ok(Json(vec![])),
+        Err(e) => Err(e.into()),
     }
 }
 
-/// deletes an item from a given `table_id` (starting at zero) and a given `item_position``. Returns if we successfully deleted the item.
-async fn delete_item(
+/// moves items from `table_number` to another table and returns them, all of them without `item_ids`.
+/// Ids, times and cook times stay as they are. Answers 404 without moving anything if an item is not on the table
+/// and 409 if the other table is inactive.
+async fn transfer_items<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(transfer): Json<ItemTransfer>,
+) -> Result<Json<Vec<MenuItem>>, ApiError> {
+    let now = state.now();
+    let items = state
+        .transfer_items(table_number, transfer.to, transfer.item_ids)
+        .await?;
+    Ok(Json(
+        items
+            .into_iter()
+            .map(|item| with_countdown(item, now))
+            .collect(),
+    ))
+}
+
+/// returns the session of the party seated at `table_number`, 404 if nobody is seated
+async fn get_session<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<SessionInfo>, ApiError> {
+    let table = state.table(table_number).await?;
+    Ok(Json(table.session.ok_or(StoreError::NoSession)?))
+}
+
+/// seats a party at `table_number`. Answers 409 if somebody is already seated there.
+async fn open_session<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(details): Json<SessionDetails>,
+) -> Result<Json<SessionInfo>, ApiError> {
+    Ok(Json(state.open_session(table_number, details).await?))
+}
+
+/// changes the guest count or the staff of the session at `table_number`, leaving out what is not given
+async fn update_session<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(details): Json<SessionDetails>,
+) -> Result<Json<SessionInfo>, ApiError> {
+    Ok(Json(state.update_session(table_number, details).await?))
+}
+
+/// closes the session at `table_number` and returns it with its items, the table is empty afterwards.
+/// Answers 409 while an item is neither served nor cancelled.
+async fn close_session<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<Session>, ApiError> {
+    Ok(Json(state.close_session(table_number).await?))
+}
+
+/// returns the closed sessions of `table_number`, oldest first.
+/// They are kept after the table is removed, so they can still be looked up.
+async fn get_sessions<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Json<Vec<Session>> {
+    Json(state.sessions(table_number).await)
+}
+
+/// returns the bill of the party seated at `table_number` as it would be if they left now, 404 if nobody is seated.
+/// The final bill is made when the session is closed and kept with it.
+async fn get_bill<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<Bill>, ApiError> {
+    Ok(Json(state.bill(table_number).await?))
+}
+
+/// splits the bill of `table_number` by seat, by item or evenly, without changing anything.
+/// The shares always add up to the total of the bill.
+async fn split_bill<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(mode): Json<SplitMode>,
+) -> Result<Json<SplitBill>, ApiError> {
+    Ok(Json(state.split_bill(table_number, mode).await?))
+}
+
+/// puts a discount or comp on the bill of `table_number` and returns it with its id
+async fn adjust_bill<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(adjustment): Json<NewAdjustment>,
+) -> Result<Json<Adjustment>, ApiError> {
+    Ok(Json(state.adjust_bill(table_number, adjustment).await?))
+}
+
+/// takes the discount or comp `adjustment_id` off the bill of `table_number`. Returns if there was such an adjustment.
+async fn remove_adjustment<S: RestaurantStore>(
+    Path((table_number, adjustment_id)): Path<(usize, u64)>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    Ok(Json(
+        state.remove_adjustment(table_number, adjustment_id).await?,
+    ))
+}
+
+/// returns what the party seated at `table_number` paid so far and what is still open
+async fn get_bill_payments<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<BillPayments>, ApiError> {
+    Ok(Json(state.bill_payments(table_number).await?))
+}
+
+/// pays towards the bill of `table_number`. Card and deferred payments are answered once the provider decided,
+/// or as pending if it could not be reached, in which case it is asked again later.
+async fn pay_bill<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(payment): Json<NewPayment>,
+) -> Result<Json<Payment>, ApiError> {
+    Ok(Json(state.pay(table_number, payment).await?))
+}
+
+/// lists all payments and refunds, or those of one session
+async fn list_payments<S: RestaurantStore>(
+    Query(query): Query<PaymentQuery>,
+    State(state): State<AppState<S>>,
+) -> Json<Vec<Payment>> {
+    Json(state.payments(query.session_id).await)
+}
+
+/// returns the payment or refund `payment_id`
+async fn get_payment<S: RestaurantStore>(
+    Path(payment_id): Path<u64>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<Payment>, ApiError> {
+    state
+        .payment(payment_id)
+        .await
+        .map(Json)
+        .ok_or_else(|| StoreError::UnknownPayment.into())
+}
+
+/// pays back the payment `payment_id` and returns the refund, even after the party left
+async fn refund_payment<S: RestaurantStore>(
+    Path(payment_id): Path<u64>,
+    State(state): State<AppState<S>>,
+    Json(refund): Json<NewRefund>,
+) -> Result<Json<Payment>, ApiError> {
+    Ok(Json(state.refund(payment_id, refund).await?))
+}
+
+/// deletes an item from a given `table_id` (starting at zero) and a given `item_position`. Returns if we successfully deleted the item.
+/// Deprecated: two tablets deleting from the same table can remove the wrong item, use `delete_item_by_id` instead.
+async fn delete_item<S: RestaurantStore>(
     Path((table_number, item_position)): Path<(usize, usize)>,
-    Query(query): Query<QueryParam>,
-    State(state): State<AppState>,
-) -> Result<Json<bool>, StatusCode> {
-    if query.key != API_KEY {
-        Err(StatusCode::UNAUTHORIZED)
-    } else if let Some(table) = state.get(table_number) {
-        let mut table_mut = table.write().await;
-        table_mut.items.remove(item_position);
-        Ok(Json(true))
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    let item_id = state
+        .table(table_number)
+        .await
+        .ok()
+        .and_then(|table| table.items.get(item_position).map(|item| item.id));
+    match item_id {
+        Some(item_id) => delete_by_id(state.as_ref(), table_number, item_id).await,
+        None => Ok(Json(false)),
+    }
+}
+
+/// deletes the item with `item_id` from the table `table_id`. Returns if we successfully deleted the item.
+async fn delete_item_by_id<S: RestaurantStore>(
+    Path((table_number, item_id)): Path<(usize, u64)>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    delete_by_id(state.as_ref(), table_number, item_id).await
+}
+
+/// removes an item, answering false if the table does not exist
+async fn delete_by_id<S: RestaurantStore>(
+    state: &S,
+    table_number: usize,
+    item_id: u64,
+) -> Result<Json<bool>, ApiError> {
+    match state.remove_item(table_number, item_id).await {
+        Ok(removed) => Ok(Json(removed)),
+        Err(StoreError::UnknownTable) => Ok(Json(false)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// lists the whole menu, including entries that are currently not available
+async fn get_menu<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+) -> Result<Json<Vec<MenuEntry>>, ApiError> {
+    Ok(Json(state.menu().await))
+}
+
+/// returns the menu entry for `item_number`
+async fn get_menu_entry<S: RestaurantStore>(
+    Path(item_number): Path<u64>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<MenuEntry>, ApiError> {
+    state
+        .menu_entry(item_number)
+        .await
+        .map(Json)
+        .ok_or_else(|| StoreError::UnknownMenuEntry.into())
+}
+
+/// puts a new entry on the menu. Answers 409 if its item number is already taken.
+async fn add_menu_entry<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+    Json(entry): Json<MenuEntry>,
+) -> Result<Json<MenuEntry>, ApiError> {
+    Ok(Json(state.add_menu_entry(entry).await?))
+}
+
+/// replaces the menu entry for `item_number`, i.e., to mark it as sold out
+async fn replace_menu_entry<S: RestaurantStore>(
+    Path(item_number): Path<u64>,
+    State(state): State<AppState<S>>,
+    Json(entry): Json<MenuEntry>,
+) -> Result<Json<MenuEntry>, ApiError> {
+    if entry.item_number != item_number {
+        Err(ApiError::new(
+            ErrorKind::BadRequest,
+            "the item number of the entry does not match the path",
+        ))
     } else {
-        Ok(Json(false))
+        Ok(Json(state.replace_menu_entry(entry).await?))
+    }
+}
+
+/// takes the entry for `item_number` off the menu. Returns if there was such an entry.
+async fn remove_menu_entry<S: RestaurantStore>(
+    Path(item_number): Path<u64>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    Ok(Json(state.remove_menu_entry(item_number).await?))
+}
+
+/// returns what the kitchen still has to cook across all tables, the next item to cook first.
+/// With `group` we answer with one entry per dish instead of one per item.
+async fn get_kitchen_queue<S: RestaurantStore>(
+    Query(query): Query<KitchenQuery>,
+    State(state): State<AppState<S>>,
+) -> Result<Response, ApiError> {
+    let queue = kitchen::queue(
+        state.snapshot_all().await.tables,
+        &state.menu().await,
+        query.station.as_deref(),
+        state.now(),
+    );
+    if query.group {
+        Ok(Json(kitchen::group(queue)).into_response())
+    } else {
+        Ok(Json(queue).into_response())
+    }
+}
+
+/// lists the floor plan, including inactive tables
+async fn get_floor_plan<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+) -> Json<Vec<TableInfo>> {
+    Json(state.floor_plan().await)
+}
+
+/// adds an empty table, numbered after every table there ever was
+async fn add_table<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+    Json(table): Json<NewTable>,
+) -> Result<Json<TableInfo>, ApiError> {
+    Ok(Json(state.add_table(table).await?))
+}
+
+/// replaces the floor plan entry of `table_number`, i.e., to rename or deactivate it. Its items stay.
+async fn update_table<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+    Json(table): Json<TableInfo>,
+) -> Result<Json<TableInfo>, ApiError> {
+    if table.table_number != table_number {
+        Err(ApiError::new(
+            ErrorKind::BadRequest,
+            "the table number of the entry does not match the path",
+        ))
+    } else {
+        Ok(Json(state.update_table(table).await?))
+    }
+}
+
+/// takes `table_number` off the floor plan. Returns if there was such a table.
+/// Answers 409 while the table has items, so no order goes missing.
+async fn remove_table<S: RestaurantStore>(
+    Path(table_number): Path<usize>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    Ok(Json(state.remove_table(table_number).await?))
+}
+
+/// lists the api keys, without their secrets
+async fn list_keys<S: RestaurantStore>(State(state): State<AppState<S>>) -> Json<Vec<KeyInfo>> {
+    Json(state.keys().await)
+}
+
+/// hands out a new api key. The answer is the only place its secret is ever shown.
+async fn create_key<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+    Json(key): Json<NewKey>,
+) -> Result<Json<IssuedKey>, ApiError> {
+    Ok(Json(state.create_key(key).await?))
+}
+
+/// revokes the api key `key_id`. Returns if there was such a key.
+/// Answers 409 for the last admin key, so keys can always be managed.
+async fn revoke_key<S: RestaurantStore>(
+    Path(key_id): Path<u64>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<bool>, ApiError> {
+    Ok(Json(state.revoke_key(key_id).await?))
+}
+
+/// gives the api key `key_id` a new secret and returns it, the old secret stops working at once
+async fn rotate_key<S: RestaurantStore>(
+    Path(key_id): Path<u64>,
+    State(state): State<AppState<S>>,
+) -> Result<Json<IssuedKey>, ApiError> {
+    Ok(Json(state.rotate_key(key_id).await?))
+}
+
+/// the subscription an event stream request asks for, resuming after `last_event_id` if given
+fn subscribe<S: RestaurantStore>(
+    state: &S,
+    query: EventQuery,
+    last_event_id: Option<u64>,
+) -> Subscription {
+    let filter = EventFilter {
+        table_number: query.table,
+        station: query.station,
+    };
+    state.subscribe(filter, query.last_event_id.or(last_event_id))
+}
+
+/// streams the events matching the query as server-sent events.
+/// A reconnecting client resumes after the id in the `Last-Event-ID` header or the `last_event_id` parameter.
+async fn sse_events<S: RestaurantStore>(
+    Query(query): Query<EventQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState<S>>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, axum::Error>>>, ApiError> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let subscription = subscribe(state.as_ref(), query, last_event_id);
+    let stream = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.next().await?;
+        let sse_event = sse::Event::default()
+            .id(event.id.to_string())
+            .json_data(&event);
+        Some((sse_event, subscription))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// streams the events matching the query over a websocket, one json text message per event
+async fn ws_events<S: RestaurantStore>(
+    ws: WebSocketUpgrade,
+    Query(query): Query<EventQuery>,
+    State(state): State<AppState<S>>,
+) -> Result<Response, ApiError> {
+    let subscription = subscribe(state.as_ref(), query, None);
+    Ok(ws.on_upgrade(move |socket| forward_events(socket, subscription)))
+}
+
+/// send the events of `subscription` until either side goes away.
+/// We wait for every send, so a slow client falls behind and gets a resync instead of piling up memory.
+async fn forward_events(mut socket: WebSocket, mut subscription: Subscription) {
+    loop {
+        tokio::select! {
+            event = subscription.next() => {
+                let Some(event) = event else { break };
+                let text = serde_json::to_string::<Event>(&event).expect("events serialize");
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                // pings are answered by axum, nothing else is expected from the client
+                Some(Ok(_)) => {}
+            }
+        }
     }
 }
 
-/// Setup the router with the app state
+/// Setup the router with a fresh in-memory app state, the test menu and the test keys
+#[cfg(test)]
 fn router() -> Router {
-    let state: AppState = new_app_state();
+    router_with_state(Arc::new(
+        store::MemoryStore::new()
+            .with_menu(Menu::for_tests())
+            .with_credentials(store::Credentials::for_tests()),
+    ))
+}
 
+/// Setup the router with the given app state and the default config
+#[cfg(test)]
+fn router_with_state<S: RestaurantStore>(state: AppState<S>) -> Router {
+    router_with_config(state, &Config::default())
+}
+
+/// Setup the router with the given app state, applying the auth, limits and idempotency sections of `config`
+fn router_with_config<S: RestaurantStore>(state: AppState<S>, config: &Config) -> Router {
     Router::new()
-        .route("/", get(get_all_items))
+        .route(routes::ALL_TABLES, get(get_all_items::<S>))
         .route(
-            "/:table_number/",
-            get(get_items_for_table).post(add_item_to_table),
+            routes::TABLE,
+            get(get_items_for_table::<S>).post(add_item_to_table::<S>),
         )
         .route(
-            "/:table_number/:item_number/",
-            delete(delete_item).get(get_item),
+            routes::ITEM,
+            delete(delete_item_by_id::<S>)
+                .get(get_item_by_id::<S>)
+                .patch(update_item_status::<S>),
         )
+        .route(routes::ITEM_SEAT, put(update_item_seat::<S>))
+        .route(routes::TABLE_TRANSFER, post(transfer_items::<S>))
+        .route(
+            routes::SESSION,
+            get(get_session::<S>)
+                .post(open_session::<S>)
+                .patch(update_session::<S>)
+                .delete(close_session::<S>),
+        )
+        .route(routes::SESSIONS, get(get_sessions::<S>))
+        .route(routes::MENU, get(get_menu::<S>).post(add_menu_entry::<S>))
+        .route(
+            routes::MENU_ENTRY,
+            get(get_menu_entry::<S>)
+                .put(replace_menu_entry::<S>)
+                .delete(remove_menu_entry::<S>),
+        )
+        .route(routes::KITCHEN_QUEUE, get(get_kitchen_queue::<S>))
+        .route(routes::EVENTS, get(sse_events::<S>))
+        .route(routes::EVENTS_WS, get(ws_events::<S>))
+        .route(
+            routes::TABLES,
+            get(get_floor_plan::<S>).post(add_table::<S>),
+        )
+        .route(
+            routes::TABLE_INFO,
+            put(update_table::<S>).delete(remove_table::<S>),
+        )
+        .route(routes::BILL, get(get_bill::<S>))
+        .route(routes::BILL_SPLIT, post(split_bill::<S>))
+        .route(routes::BILL_ADJUSTMENTS, post(adjust_bill::<S>))
+        .route(routes::BILL_ADJUSTMENT, delete(remove_adjustment::<S>))
+        .route(
+            routes::BILL_PAYMENTS,
+            get(get_bill_payments::<S>).post(pay_bill::<S>),
+        )
+        .route(routes::PAYMENTS, get(list_payments::<S>))
+        .route(routes::PAYMENT, get(get_payment::<S>))
+        .route(routes::PAYMENT_REFUND, post(refund_payment::<S>))
+        .route(routes::KEYS, get(list_keys::<S>).post(create_key::<S>))
+        .route(routes::KEY, delete(revoke_key::<S>))
+        .route(routes::KEY_ROTATE, post(rotate_key::<S>))
+        // deprecated positional aliases
+        .route(
+            routes::ITEM_POSITION,
+            delete(delete_item::<S>).get(get_item::<S>),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            versions::conditional::<S>,
+        ))
+        // runs after the auth middleware, which tells it who sent the request
+        .route_layer(middleware::from_fn_with_state(
+            idempotency::IdempotencyState::new(state.clone(), &config.idempotency),
+            idempotency::remember::<S>,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            auth::AuthState::new(state.clone(), &config.auth),
+            auth::authorize::<S>,
+        ))
         .with_state(state)
+        // the limit has to hold for the auth middleware reading signed bodies as well, not only for extractors
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.limits.max_body_bytes))
+        .layer(TimeoutLayer::new(config.request_timeout()))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                // like the default span, without the api key
+                .make_span_with(|request: &axum::http::Request<_>| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        uri = %auth::redact(request.uri()),
+                        version = ?request.version(),
+                    )
+                })
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
         )
 }
 
+/// Open the backend selected by the cargo features, together with how often it wants to be checkpointed
+#[cfg(feature = "durable")]
+fn open_backend(config: &Config) -> anyhow::Result<(Backend, Option<Duration>)> {
+    let options = storage::StorageOptions::from_config(&config.storage)?;
+    Ok((
+        Backend::open(&options, config.tables)?,
+        Some(options.snapshot_interval),
+    ))
+}
+
+/// Open the backend selected by the cargo features, together with how often it wants to be checkpointed
+#[cfg(not(feature = "durable"))]
+fn open_backend(config: &Config) -> anyhow::Result<(Backend, Option<Duration>)> {
+    Ok((Backend::with_table_count(config.tables), None))
+}
+
 #[tokio::main]
 async fn main() {
-    let app = router();
+    let cli = Cli::try_parse_with_env(std::env::args_os(), |name| std::env::var(name).ok())
+        .unwrap_or_else(|e| e.exit());
+    let config = Config::load(&cli).unwrap_or_else(|e| {
+        eprintln!("{:#}", e);
+        std::process::exit(2);
+    });
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
+        .with_max_level(config.log_level())
         .init();
-    println!("Listening on port 127.0.0.1:3000");
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .expect("Cannot listen on port 3000");
-    axum::serve(listener, app).await.unwrap();
+    let (backend, checkpoint_interval) =
+        open_backend(&config).expect("Cannot restore state from the data directory");
+    let menu = Menu::load(&config.menu).expect("Cannot load the menu");
+    let credentials =
+        store::Credentials::load_or_bootstrap(&config.auth.keys, &config.auth.bootstrap_key)
+            .expect("Cannot load the api keys");
+    let billing =
+        billing::BillingRules::from_config(&config.billing).expect("Invalid billing rules");
+    let gateway = payments::PaymentGateway::from_config(&config.payments)
+        .expect("Cannot set up the payment provider");
+    let mut backend = backend
+        .with_menu(menu)
+        .with_credentials(credentials)
+        .with_billing(billing)
+        .with_payments(gateway);
+    // a fixed seed makes the cook times of a replayed sequence of orders reproducible
+    if let Some(seed) = config.seed {
+        backend = backend.with_seed(seed);
+    }
+    let state: AppState<Backend> = Arc::new(backend);
+    if let Some(interval) = checkpoint_interval {
+        tokio::spawn(checkpoint_periodically(state.clone(), interval));
+    }
+    // payments left pending by the last run are settled first
+    tokio::spawn(payments::reconcile_periodically(
+        state.clone(),
+        Duration::from_secs(config.payments.reconcile_secs),
+    ));
+    let app = router_with_config(state.clone(), &config);
+    if let Some(options) = tls::TlsOptions::from_config(&config.tls) {
+        let tls_config = tls::load(&options).expect("Cannot load the TLS certificates");
+        tokio::spawn(tls::reload_periodically(tls_config.clone(), options));
+        let handle = axum_server::Handle::new();
+        let shutdown = handle.clone();
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            shutdown.graceful_shutdown(Some(Duration::from_secs(10)));
+        });
+        println!("Listening on {} (https)", config.listen);
+        axum_server::bind_rustls(config.listen, tls_config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await
+            .unwrap_or_else(|e| panic!("Cannot listen on {}: {}", config.listen, e));
+    } else {
+        println!("Listening on {}", config.listen);
+        let listener = tokio::net::TcpListener::bind(config.listen)
+            .await
+            .unwrap_or_else(|e| panic!("Cannot listen on {}: {}", config.listen, e));
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await
+            .unwrap();
+    }
+    // a clean shutdown leaves an empty log behind
+    if let Err(e) = state.checkpoint().await {
+        tracing::error!("Could not write snapshot on shutdown: {}", e);
+    }
 }
@@ -0,0 +1,120 @@
+//! Paging through the listings of all tables and of a single table.
+//!
+//! Entries are sorted by a [`SortKey`] and ties are broken by table number or item id, so every entry has its own
+//! position. A cursor is the position of the last entry of a page and the next page starts right after it.
+//! Unlike an offset it does not shift when entries before it are added or removed.
+use restaurant_types::{ErrorKind, Page, SortKey};
+
+use crate::{
+    error::ApiError,
+    types::{MenuItem, QueryParam, Table},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Where an entry is in the sorted listing
+pub(crate) struct Position {
+    key: i64,
+    /// table number or item id
+    tiebreak: u64,
+}
+
+/// The position of `item` when sorting by `sort`
+pub(crate) fn item_position(item: &MenuItem, sort: SortKey) -> Position {
+    let key = match sort {
+        // all items of a table share it, so they stay in the order they were added
+        SortKey::TableNumber => 0,
+        SortKey::OrderedAt => item.ordered_at.timestamp_micros(),
+        SortKey::Remaining => item.remaining_minutes as i64,
+        SortKey::ItemNumber => item.item_number as i64,
+    };
+    Position {
+        key,
+        tiebreak: item.id,
+    }
+}
+
+/// The position of `table`, which has to have its items sorted by `sort` already
+pub(crate) fn table_position(table: &Table, sort: SortKey) -> Position {
+    let key = match (sort, table.items.first()) {
+        (SortKey::TableNumber, _) | (_, None) => table.table_number as i64,
+        (_, Some(first)) => item_position(first, sort).key,
+    };
+    Position {
+        key,
+        tiebreak: table.table_number as u64,
+    }
+}
+
+/// Refuse paging parameters without `paged`, where they would be silently ignored
+pub(crate) fn check_unpaged(query: &QueryParam) -> Result<(), ApiError> {
+    if query.cursor.is_some() || query.offset.is_some() || query.sort.is_some() {
+        Err(bad_request("cursor, offset and sort need paged=true"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Sort `entries` by `position` and cut out the page `query` asks for
+pub(crate) fn page<T>(
+    mut entries: Vec<T>,
+    position: impl Fn(&T) -> Position,
+    query: &QueryParam,
+) -> Result<Page<T>, ApiError> {
+    let sort = query.sort.unwrap_or_default();
+    entries.sort_by_key(|entry| position(entry));
+    let total = entries.len() as u64;
+    let start = match &query.cursor {
+        Some(cursor) => {
+            let after = decode(cursor, sort)?;
+            entries.partition_point(|entry| position(entry) <= after)
+        }
+        None => 0,
+    };
+    let start = start.saturating_add(query.offset.unwrap_or(0) as usize);
+    let end = query
+        .limit
+        .map_or(entries.len(), |limit| start.saturating_add(limit as usize))
+        .min(entries.len());
+    let items = entries
+        .into_iter()
+        .take(end)
+        .skip(start)
+        .collect::<Vec<T>>();
+    let next_cursor = match items.last() {
+        Some(last) if end < total as usize => Some(encode(position(last), sort)),
+        _ => None,
+    };
+    Ok(Page {
+        items,
+        next_cursor,
+        total,
+    })
+}
+
+/// The cursor remembers the sort it was made for, continuing it under another sort makes no sense
+fn encode(position: Position, sort: SortKey) -> String {
+    hex::encode(format!("{:?}:{}:{}", sort, position.key, position.tiebreak))
+}
+
+fn decode(cursor: &str, sort: SortKey) -> Result<Position, ApiError> {
+    let invalid = || bad_request("invalid cursor");
+    let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+    let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+    let mut parts = text.split(':');
+    let (Some(cursor_sort), Some(key), Some(tiebreak), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if cursor_sort != format!("{:?}", sort) {
+        return Err(bad_request("the cursor belongs to a different sort"));
+    }
+    Ok(Position {
+        key: key.parse().map_err(|_| invalid())?,
+        tiebreak: tiebreak.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn bad_request(message: &str) -> ApiError {
+    ApiError::new(ErrorKind::BadRequest, message)
+}
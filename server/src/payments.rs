@@ -0,0 +1,387 @@
+//! Taking money. Cash is recorded as it is, card and deferred payments go through a [`PaymentProvider`].
+//!
+//! A payment is journaled as pending before the provider is asked, under an idempotency key that stays the same
+//! for every attempt. Asking twice never charges twice, so failures reaching the provider are retried,
+//! and payments still pending after a restart or a long wait are reconciled by simply asking again.
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::PaymentsConfig,
+    store::RestaurantStore,
+    types::{Bill, BillPayments, Payment, PaymentMethod, PaymentStatus},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// Take `amount` yen from the guest
+pub(crate) struct Charge {
+    /// the same for every attempt, so the provider can tell a retry from a new charge
+    pub(crate) idempotency_key: String,
+    pub(crate) method: PaymentMethod,
+    pub(crate) amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+/// Pay `amount` yen of the charge `charge` back
+pub(crate) struct Refund {
+    /// the same for every attempt, so the provider can tell a retry from a new refund
+    pub(crate) idempotency_key: String,
+    /// the provider's reference of the charge
+    pub(crate) charge: String,
+    pub(crate) amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+/// What the provider decided
+pub(crate) enum Outcome {
+    Succeeded {
+        reference: String,
+    },
+    /// not decided yet, i.e., a deferred payment waiting for approval
+    Pending {
+        #[serde(default)]
+        reference: Option<String>,
+    },
+    Declined {
+        reason: String,
+    },
+}
+
+#[derive(Debug)]
+/// Why the provider did not decide
+pub(crate) enum ProviderError {
+    /// the provider could not be reached or had trouble of its own, asking again may work
+    Unavailable(String),
+    /// the provider refused the request itself, asking again will not help
+    Rejected(String),
+}
+
+/// Who actually moves the money for card and deferred payments
+pub(crate) trait PaymentProvider: Send + Sync + 'static {
+    /// Charge the guest. A charge with an idempotency key the provider has seen answers like the first one.
+    fn charge<'a>(&'a self, charge: &'a Charge) -> BoxFuture<'a, Result<Outcome, ProviderError>>;
+
+    /// Pay a charge back. A refund with an idempotency key the provider has seen answers like the first one.
+    fn refund<'a>(&'a self, refund: &'a Refund) -> BoxFuture<'a, Result<Outcome, ProviderError>>;
+}
+
+/// A provider speaking json over http: `POST /charges` and `POST /refunds` with the request as body
+/// and the idempotency key in the `Idempotency-Key` header as well, answering with an [`Outcome`].
+pub(crate) struct HttpProvider {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    key: Option<String>,
+}
+
+impl HttpProvider {
+    /// A provider at `url`, authenticated with `key` as bearer token if there is one
+    pub(crate) fn new(url: &str, key: Option<String>, timeout: Duration) -> anyhow::Result<Self> {
+        let mut url =
+            reqwest::Url::parse(url).with_context(|| format!("Invalid provider url {}", url))?;
+        // so joining `charges` keeps a path the url might have
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .context("Cannot build the http client")?,
+            url,
+            key,
+        })
+    }
+
+    async fn post<T: Serialize>(
+        &self,
+        path: &str,
+        idempotency_key: &str,
+        body: &T,
+    ) -> Result<Outcome, ProviderError> {
+        let url = self
+            .url
+            .join(path)
+            .map_err(|e| ProviderError::Rejected(e.to_string()))?;
+        let mut request = self
+            .client
+            .post(url)
+            .header("Idempotency-Key", idempotency_key)
+            .json(body);
+        if let Some(key) = &self.key {
+            request = request.bearer_auth(key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ProviderError::Unavailable(e.to_string()))?;
+        let status = response.status();
+        if status.is_server_error()
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        {
+            return Err(ProviderError::Unavailable(format!(
+                "the provider answered {}",
+                status
+            )));
+        }
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(ProviderError::Rejected(format!(
+                "the provider answered {}: {}",
+                status, text
+            )));
+        }
+        // a cut off answer may well be complete next time
+        response
+            .json()
+            .await
+            .map_err(|e| ProviderError::Unavailable(e.to_string()))
+    }
+}
+
+impl PaymentProvider for HttpProvider {
+    fn charge<'a>(&'a self, charge: &'a Charge) -> BoxFuture<'a, Result<Outcome, ProviderError>> {
+        Box::pin(self.post("charges", &charge.idempotency_key, charge))
+    }
+
+    fn refund<'a>(&'a self, refund: &'a Refund) -> BoxFuture<'a, Result<Outcome, ProviderError>> {
+        Box::pin(self.post("refunds", &refund.idempotency_key, refund))
+    }
+}
+
+#[derive(Clone)]
+/// The provider, if there is one, and how hard we try to reach it
+pub(crate) struct PaymentGateway {
+    provider: Option<Arc<dyn PaymentProvider>>,
+    /// attempts per request, at least one
+    attempts: u32,
+    /// how long we wait before the first retry, doubled for every further one
+    backoff: Duration,
+}
+
+impl Default for PaymentGateway {
+    /// Only cash, without a provider
+    fn default() -> Self {
+        Self {
+            provider: None,
+            attempts: 3,
+            backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+impl PaymentGateway {
+    /// Ask `provider`, trying every request up to `attempts` times
+    pub(crate) fn new(
+        provider: Arc<dyn PaymentProvider>,
+        attempts: u32,
+        backoff: Duration,
+    ) -> Self {
+        Self {
+            provider: Some(provider),
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// The gateway set in the `payments` section of the config, only taking cash without a provider url
+    pub(crate) fn from_config(config: &PaymentsConfig) -> anyhow::Result<Self> {
+        let Some(url) = &config.provider_url else {
+            return Ok(Self::default());
+        };
+        let provider = HttpProvider::new(
+            url,
+            config.provider_key.clone(),
+            Duration::from_secs(config.timeout_secs),
+        )?;
+        Ok(Self::new(
+            Arc::new(provider),
+            config.retries + 1,
+            Duration::from_millis(config.retry_backoff_ms),
+        ))
+    }
+
+    /// if payments with `method` can be taken
+    pub(crate) fn takes(&self, method: PaymentMethod) -> bool {
+        method == PaymentMethod::Cash || self.provider.is_some()
+    }
+
+    /// Ask the provider to carry out `payment`, a refund of the charge `charge` if it is one.
+    /// If the provider cannot be reached after all attempts the payment stays pending.
+    pub(crate) async fn settle(&self, payment: &Payment, charge: Option<String>) -> Outcome {
+        let Some(provider) = &self.provider else {
+            return Outcome::Pending { reference: None };
+        };
+        let idempotency_key = idempotency_key(payment);
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            let result = match (payment.refund_of, &charge) {
+                (Some(_), Some(charge)) => {
+                    provider
+                        .refund(&Refund {
+                            idempotency_key: idempotency_key.clone(),
+                            charge: charge.clone(),
+                            amount: payment.amount,
+                        })
+                        .await
+                }
+                (Some(_), None) => {
+                    return Outcome::Declined {
+                        reason: "the refunded payment has no reference at the provider".to_string(),
+                    }
+                }
+                (None, _) => {
+                    provider
+                        .charge(&Charge {
+                            idempotency_key: idempotency_key.clone(),
+                            method: payment.method,
+                            amount: payment.amount,
+                        })
+                        .await
+                }
+            };
+            match result {
+                Ok(outcome) => return outcome,
+                Err(ProviderError::Rejected(reason)) => return Outcome::Declined { reason },
+                Err(ProviderError::Unavailable(e)) if attempt < self.attempts => {
+                    tracing::debug!("Payment {} attempt {} failed: {}", payment.id, attempt, e);
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(ProviderError::Unavailable(e)) => {
+                    tracing::warn!("Payment {} stays pending: {}", payment.id, e);
+                    return Outcome::Pending { reference: None };
+                }
+            }
+        }
+    }
+}
+
+/// The key every attempt of `payment` is sent with. Ids start over with a fresh data directory,
+/// the time it was made keeps the keys of two restaurants at the same provider apart.
+pub(crate) fn idempotency_key(payment: &Payment) -> String {
+    format!(
+        "payment-{}-{}",
+        payment.id,
+        payment.created_at.timestamp_micros()
+    )
+}
+
+/// What of `bill` the `payments` of its session paid
+pub(crate) fn summary(bill: &Bill, payments: Vec<Payment>) -> BillPayments {
+    let sum = |refunds: bool, status: PaymentStatus| {
+        payments
+            .iter()
+            .filter(|p| p.refund_of.is_some() == refunds && p.status == status)
+            .map(|p| p.amount)
+            .sum::<u64>()
+    };
+    let paid =
+        sum(false, PaymentStatus::Succeeded).saturating_sub(sum(true, PaymentStatus::Succeeded));
+    let pending = sum(false, PaymentStatus::Pending);
+    BillPayments {
+        session_id: bill.session_id,
+        total: bill.total,
+        paid,
+        pending,
+        balance: bill.total.saturating_sub(paid + pending),
+        payments,
+    }
+}
+
+/// Reconcile the pending payments of `store` every `interval`, starting right away
+pub(crate) async fn reconcile_periodically<S: RestaurantStore>(store: Arc<S>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        store.reconcile_payments().await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) use mock::MockProvider;
+
+#[cfg(test)]
+mod mock {
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::{Mutex, PoisonError},
+    };
+
+    use super::*;
+
+    #[derive(Default)]
+    /// A provider in the same process. It answers with the scripted results first, then lets everything succeed.
+    /// Like a real provider it answers a key it has decided on like the first time.
+    pub(crate) struct MockProvider {
+        script: Mutex<VecDeque<Result<Outcome, ProviderError>>>,
+        decided: Mutex<HashMap<String, Outcome>>,
+        /// the idempotency keys of every request, in order
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl MockProvider {
+        /// A provider answering with `script` first
+        pub(crate) fn scripted(script: Vec<Result<Outcome, ProviderError>>) -> Self {
+            Self {
+                script: Mutex::new(script.into()),
+                ..Self::default()
+            }
+        }
+
+        /// the idempotency keys of every request so far
+        pub(crate) fn requests(&self) -> Vec<String> {
+            self.requests
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        }
+
+        fn answer(&self, key: &str) -> Result<Outcome, ProviderError> {
+            self.requests
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(key.to_string());
+            let mut decided = self.decided.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(outcome) = decided.get(key) {
+                return Ok(outcome.clone());
+            }
+            let result = self
+                .script
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop_front()
+                .unwrap_or_else(|| {
+                    Ok(Outcome::Succeeded {
+                        reference: format!("mock-{}", key),
+                    })
+                });
+            if let Ok(outcome @ (Outcome::Succeeded { .. } | Outcome::Declined { .. })) = &result {
+                decided.insert(key.to_string(), outcome.clone());
+            }
+            result
+        }
+    }
+
+    impl PaymentProvider for MockProvider {
+        fn charge<'a>(
+            &'a self,
+            charge: &'a Charge,
+        ) -> BoxFuture<'a, Result<Outcome, ProviderError>> {
+            Box::pin(async move { self.answer(&charge.idempotency_key) })
+        }
+
+        fn refund<'a>(
+            &'a self,
+            refund: &'a Refund,
+        ) -> BoxFuture<'a, Result<Outcome, ProviderError>> {
+            Box::pin(async move { self.answer(&refund.idempotency_key) })
+        }
+    }
+}
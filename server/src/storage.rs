@@ -0,0 +1,305 @@
+//! Durable storage for the restaurant.
+//! Every mutation is appended to a write-ahead log before it is applied to a table.
+//! From time to time all tables are written to a compacted snapshot, after which the log starts empty again.
+//! On startup the snapshot is loaded and the log is replayed on top of it.
+//!
+//! A log record is framed as `[length: u32][crc32: u32][json payload]`, both integers little endian.
+//! A record that is cut short or does not match its checksum can only be the result of a crash while writing,
+//! so it and everything after it is cut from the log.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::StorageConfig,
+    store::{replace_file, Checkpoint, Journal, Mutation},
+};
+
+static WAL_FILE: &str = "wal.log";
+static SNAPSHOT_FILE: &str = "snapshot.json";
+/// length and checksum in front of every record
+const HEADER_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// When we force the write-ahead log to disk
+pub(crate) enum Durability {
+    /// fsync after every record, an acknowledged request is never lost
+    Always,
+    /// fsync after every `n` records, a crash loses at most the last `n - 1` acknowledged requests
+    EveryN(u64),
+    /// leave flushing to the operating system
+    Never,
+}
+
+impl FromStr for Durability {
+    type Err = anyhow::Error;
+
+    /// Parses `always`, `never` or `every:<n>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Durability::Always),
+            "never" => Ok(Durability::Never),
+            _ => match s.strip_prefix("every:").map(str::parse::<u64>) {
+                Some(Ok(n)) if n > 0 => Ok(Durability::EveryN(n)),
+                _ => Err(anyhow::anyhow!(
+                    "Unknown durability '{}', expected always, never or every:<n>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Where and how we store the restaurant
+pub(crate) struct StorageOptions {
+    /// directory holding the log and the snapshot
+    pub(crate) dir: PathBuf,
+    pub(crate) durability: Durability,
+    /// how often we write a snapshot and compact the log
+    pub(crate) snapshot_interval: Duration,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("data"),
+            durability: Durability::Always,
+            snapshot_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl StorageOptions {
+    /// The options set in the `storage` section of the config
+    pub(crate) fn from_config(config: &StorageConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            dir: config.dir.clone(),
+            durability: config.durability.parse()?,
+            snapshot_interval: Duration::from_secs(config.snapshot_interval_secs),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A mutation together with its position in the log
+struct LogRecord {
+    seq: u64,
+    mutation: Mutation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// All tables at the point the log had reached `seq`
+pub(crate) struct Snapshot {
+    pub(crate) seq: u64,
+    #[serde(flatten)]
+    pub(crate) checkpoint: Checkpoint,
+}
+
+/// The open log file and how far it got
+#[derive(Debug)]
+struct Wal {
+    file: File,
+    /// sequence number of the last record written
+    seq: u64,
+    /// records written since the last fsync
+    unsynced: u64,
+}
+
+/// What the write-ahead log is written to, a file outside of tests
+pub(crate) trait LogFile: Write {
+    /// the current length in bytes
+    fn log_len(&self) -> io::Result<u64>;
+    /// cut the log back to `len` bytes, the next write goes to the new end
+    fn cut_to(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn log_len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn cut_to(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)?;
+        self.seek(SeekFrom::Start(len)).map(|_| ())
+    }
+}
+
+/// Write `frame` to `log` completely or not at all. A record torn by a failed write would otherwise sit in front
+/// of the next ones, and the next startup would cut them off together with it.
+pub(crate) fn append_frame(log: &mut impl LogFile, frame: &[u8]) -> io::Result<()> {
+    let len = log.log_len()?;
+    if let Err(e) = log.write_all(frame) {
+        if let Err(cut) = log.cut_to(len) {
+            tracing::error!("Cannot cut a torn record from the write-ahead log: {}", cut);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+/// The storage backing a restaurant
+pub(crate) struct Storage {
+    dir: PathBuf,
+    durability: Durability,
+    wal: Mutex<Wal>,
+}
+
+impl Storage {
+    /// Opens the storage in `options.dir`, creating it if needed.
+    /// Returns the storage, the latest snapshot, if one was written yet, and the mutations logged after it, in order.
+    pub(crate) fn open(
+        options: &StorageOptions,
+    ) -> anyhow::Result<(Storage, Option<Snapshot>, Vec<Mutation>)> {
+        fs::create_dir_all(&options.dir)?;
+        let snapshot_path = options.dir.join(SNAPSHOT_FILE);
+        let snapshot: Option<Snapshot> = if snapshot_path.exists() {
+            Some(serde_json::from_slice(&fs::read(&snapshot_path)?)?)
+        } else {
+            None
+        };
+        let snapshot_seq = snapshot.as_ref().map_or(0, |s| s.seq);
+
+        let wal_path = options.dir.join(WAL_FILE);
+        let records = read_log(&wal_path)?;
+        let seq = records
+            .last()
+            .map_or(snapshot_seq, |r| r.seq.max(snapshot_seq));
+        // a crash between writing a snapshot and emptying the log leaves records the snapshot already contains
+        let mutations = records
+            .into_iter()
+            .filter(|r| r.seq > snapshot_seq)
+            .map(|r| r.mutation)
+            .collect();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)?;
+        let storage = Storage {
+            dir: options.dir.clone(),
+            durability: options.durability,
+            wal: Mutex::new(Wal {
+                file,
+                seq,
+                unsynced: 0,
+            }),
+        };
+        Ok((storage, snapshot, mutations))
+    }
+}
+
+impl Journal for Storage {
+    /// Append a mutation to the log, syncing according to the durability policy.
+    /// Has to be called while holding the write lock of the table the mutation belongs to,
+    /// otherwise the log order can differ from the order the mutations were applied in.
+    fn append(&self, mutation: &Mutation) -> io::Result<()> {
+        let mut wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+        let record = LogRecord {
+            seq: wal.seq + 1,
+            mutation: mutation.clone(),
+        };
+        let payload = serde_json::to_vec(&record)?;
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        append_frame(&mut wal.file, &frame)?;
+        wal.seq = record.seq;
+        wal.unsynced += 1;
+
+        let sync = match self.durability {
+            Durability::Always => true,
+            Durability::EveryN(n) => wal.unsynced >= n,
+            Durability::Never => false,
+        };
+        if sync {
+            wal.file.sync_data()?;
+            wal.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Write a snapshot of `tables` and empty the log.
+    /// Has to be called while no mutation can be appended, i.e., while holding the read locks of all tables.
+    fn write_snapshot(&self, checkpoint: Checkpoint) -> io::Result<()> {
+        let mut wal = self.wal.lock().unwrap_or_else(PoisonError::into_inner);
+        let snapshot = Snapshot {
+            seq: wal.seq,
+            checkpoint,
+        };
+        replace_file(
+            &self.dir.join(SNAPSHOT_FILE),
+            serde_json::to_vec(&snapshot)?,
+        )?;
+
+        wal.file.set_len(0)?;
+        wal.file.sync_all()?;
+        wal.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Reads all intact records from the log at `path`.
+/// A truncated or corrupt tail is reported and cut off so new records are not appended after garbage.
+fn read_log(path: &Path) -> io::Result<Vec<LogRecord>> {
+    let mut bytes = vec![];
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut records = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        match decode_record(&bytes[offset..])? {
+            Some((record, len)) => {
+                records.push(record);
+                offset += len;
+            }
+            None => {
+                tracing::warn!(
+                    "Write-ahead log {} has a truncated or corrupt record at byte {}, discarding {} bytes",
+                    path.display(),
+                    offset,
+                    bytes.len() - offset
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(offset as u64)?;
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Decodes the record at the start of `bytes`, returning it and the number of bytes it takes up.
+/// Returns `None` if the record is truncated or corrupt.
+/// A record that is intact but cannot be parsed was written by an incompatible version and is an error,
+/// as cutting it off would silently lose acknowledged orders.
+fn decode_record(bytes: &[u8]) -> io::Result<Option<(LogRecord, usize)>> {
+    let Some(header) = bytes.get(..HEADER_LEN) else {
+        return Ok(None);
+    };
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let Some(payload) = bytes.get(HEADER_LEN..HEADER_LEN + len) else {
+        return Ok(None);
+    };
+    if crc32fast::hash(payload) != crc {
+        return Ok(None);
+    }
+    let record = serde_json::from_slice(payload)?;
+    Ok(Some((record, HEADER_LEN + len)))
+}
@@ -0,0 +1,288 @@
+//! The api keys handed out to devices and staff, each with the role it acts in.
+//! Only a hash of every key is kept. Keys are loaded from a TOML file at startup and every change made through
+//! the api is written back to that file.
+//! Next to the hash the key signed requests are signed with is kept, see [`restaurant_types::signing`].
+//! Unlike the hash it is enough to act as the key holder, so the keys file must not be readable by anyone else.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError, RwLock},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use restaurant_types::signing;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::types::{IssuedKey, KeyInfo, NewKey, Role};
+
+use super::{replace_file, StoreError};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A key as we store it
+struct StoredKey {
+    id: u64,
+    name: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+    /// hex encoded sha256 of the secret
+    hash: String,
+    /// hex encoded key signed requests are signed with, derived from the secret.
+    /// Missing for keys from before signed requests, those have to be rotated to sign.
+    #[serde(default)]
+    signing_key: String,
+}
+
+impl StoredKey {
+    fn info(&self) -> KeyInfo {
+        KeyInfo {
+            id: self.id,
+            name: self.name.clone(),
+            role: self.role,
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+/// The layout of the keys file
+struct KeysFile {
+    /// missing in files from before it was kept, then it follows the highest id
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    keys: Vec<StoredKey>,
+}
+
+#[derive(Clone, Debug)]
+/// The keys by id and the id the next one gets
+struct Keys {
+    by_id: BTreeMap<u64, StoredKey>,
+    /// only ever grows, so the id of a revoked key never names another holder
+    next_id: u64,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Self {
+            by_id: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// The nonces of the signed requests we accepted, until their timestamp is too old to be accepted anyway
+struct Nonces {
+    seen: HashSet<(u64, String)>,
+    /// (expires at, key id, nonce), the next to expire first
+    by_expiry: BTreeSet<(i64, u64, String)>,
+}
+
+#[derive(Debug, Default)]
+/// The api keys by id. Without any key every request is refused.
+pub(crate) struct Credentials {
+    keys: RwLock<Keys>,
+    /// where changes are written to, if the keys came from a file
+    path: Option<PathBuf>,
+    nonces: Mutex<Nonces>,
+}
+
+fn hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// A new random secret
+fn generate_secret() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 24]>())
+}
+
+impl Credentials {
+    /// Load the keys from `path`. If there is no such file yet it is created with a single admin key
+    /// with the secret `bootstrap_secret`, so the first admin can hand out the other keys.
+    pub(crate) fn load_or_bootstrap(path: &Path, bootstrap_secret: &str) -> anyhow::Result<Self> {
+        if !path.exists() {
+            let credentials = Self {
+                path: Some(path.to_path_buf()),
+                ..Self::default()
+            };
+            credentials
+                .insert("bootstrap", Role::Admin, bootstrap_secret)
+                .map_err(|e| anyhow::anyhow!("Cannot create {}: {:?}", path.display(), e))?;
+            tracing::warn!(
+                "Created {} with a bootstrap admin key, rotate it before going live",
+                path.display()
+            );
+            return Ok(credentials);
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read keys {}", path.display()))?;
+        let file: KeysFile =
+            toml::from_str(&content).with_context(|| format!("Invalid keys {}", path.display()))?;
+        let by_id = file
+            .keys
+            .into_iter()
+            .map(|key| (key.id, key))
+            .collect::<BTreeMap<u64, StoredKey>>();
+        let next_id = by_id
+            .keys()
+            .next_back()
+            .map_or(1, |id| id + 1)
+            .max(file.next_id);
+        Ok(Self {
+            keys: RwLock::new(Keys { by_id, next_id }),
+            path: Some(path.to_path_buf()),
+            nonces: Mutex::default(),
+        })
+    }
+
+    /// Who holds `secret`, if anybody
+    pub(crate) fn authenticate(&self, secret: &str) -> Option<KeyInfo> {
+        let hash = hash(secret);
+        self.read()
+            .by_id
+            .values()
+            .find(|key| key.hash == hash)
+            .map(StoredKey::info)
+    }
+
+    /// The key `key_id` and the key its requests are signed with
+    pub(crate) fn signing_key(&self, key_id: u64) -> Option<(KeyInfo, Vec<u8>)> {
+        let keys = self.read();
+        let key = keys.by_id.get(&key_id)?;
+        let signing_key = hex::decode(&key.signing_key)
+            .ok()
+            .filter(|signing_key| !signing_key.is_empty())?;
+        Some((key.info(), signing_key))
+    }
+
+    /// Remember `nonce` of `key_id` until `expires_at` (unix seconds), `now` being the current time.
+    /// Returns false if it is still remembered from an earlier request.
+    pub(crate) fn use_nonce(&self, key_id: u64, nonce: &str, expires_at: i64, now: i64) -> bool {
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        while let Some(oldest) = nonces
+            .by_expiry
+            .first()
+            .filter(|(at, ..)| *at < now)
+            .cloned()
+        {
+            nonces.by_expiry.remove(&oldest);
+            nonces.seen.remove(&(oldest.1, oldest.2));
+        }
+        if !nonces.seen.insert((key_id, nonce.to_string())) {
+            return false;
+        }
+        nonces
+            .by_expiry
+            .insert((expires_at, key_id, nonce.to_string()));
+        true
+    }
+
+    /// All keys ordered by id
+    pub(crate) fn list(&self) -> Vec<KeyInfo> {
+        self.read().by_id.values().map(StoredKey::info).collect()
+    }
+
+    /// Hand out a new key with a random secret
+    pub(crate) fn create(&self, key: NewKey) -> Result<IssuedKey, StoreError> {
+        if key.name.trim().is_empty() {
+            return Err(StoreError::InvalidKey("a key needs a name".to_string()));
+        }
+        self.insert(&key.name, key.role, &generate_secret())
+    }
+
+    /// Revoke a key. Returns if there was such a key.
+    pub(crate) fn revoke(&self, key_id: u64) -> Result<bool, StoreError> {
+        self.change(|keys| {
+            let keys = &mut keys.by_id;
+            let is_last_admin = keys.get(&key_id).is_some_and(|key| key.role == Role::Admin)
+                && keys.values().filter(|key| key.role == Role::Admin).count() == 1;
+            if is_last_admin {
+                return Err(StoreError::LastAdminKey);
+            }
+            Ok(keys.remove(&key_id).is_some())
+        })
+    }
+
+    /// Replace the secret of a key, keeping its id, name and role
+    pub(crate) fn rotate(&self, key_id: u64) -> Result<IssuedKey, StoreError> {
+        let secret = generate_secret();
+        self.change(|keys| {
+            let key = keys.by_id.get_mut(&key_id).ok_or(StoreError::UnknownKey)?;
+            key.hash = hash(&secret);
+            key.signing_key = hex::encode(signing::signing_key(&secret));
+            Ok(IssuedKey {
+                info: key.info(),
+                key: secret,
+            })
+        })
+    }
+
+    fn insert(&self, name: &str, role: Role, secret: &str) -> Result<IssuedKey, StoreError> {
+        self.change(|keys| {
+            let key = StoredKey {
+                id: keys.next_id,
+                name: name.to_string(),
+                role,
+                created_at: Utc::now(),
+                hash: hash(secret),
+                signing_key: hex::encode(signing::signing_key(secret)),
+            };
+            let issued = IssuedKey {
+                info: key.info(),
+                key: secret.to_string(),
+            };
+            keys.next_id += 1;
+            keys.by_id.insert(key.id, key);
+            Ok(issued)
+        })
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Keys> {
+        self.keys.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Apply `f` to a copy of the keys and, if it succeeds and a file backs them, save the copy.
+    /// Only then the copy replaces the current keys, so a failed write changes nothing.
+    fn change<T>(
+        &self,
+        f: impl FnOnce(&mut Keys) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut keys = self.keys.write().unwrap_or_else(PoisonError::into_inner);
+        let mut changed = keys.clone();
+        let result = f(&mut changed)?;
+        if let Some(path) = &self.path {
+            let file = KeysFile {
+                next_id: changed.next_id,
+                keys: changed.by_id.values().cloned().collect(),
+            };
+            let content = toml::to_string_pretty(&file)
+                .map_err(|e| StoreError::Storage(std::io::Error::other(e)))?;
+            replace_file(path, &content).map_err(StoreError::Storage)?;
+        }
+        *keys = changed;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+impl Credentials {
+    /// The development key as admin and the keys `waiter`, `kitchen` and `manager` with the role they are named after
+    pub(crate) fn for_tests() -> Self {
+        let credentials = Self::default();
+        for (name, role, secret) in [
+            ("admin", Role::Admin, crate::types::API_KEY),
+            ("waiter", Role::Waiter, "waiter"),
+            ("kitchen", Role::Kitchen, "kitchen"),
+            ("manager", Role::Manager, "manager"),
+        ] {
+            credentials
+                .insert(name, role, secret)
+                .expect("keys in memory cannot fail to save");
+        }
+        credentials
+    }
+}
@@ -0,0 +1,1335 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, SeedableRng};
+
+use tokio::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "durable")]
+use crate::storage::{Storage, StorageOptions};
+use crate::types::{
+    new_menu_item, Adjustment, AdjustmentKind, Bill, BillPayments, EventKind, IssuedKey,
+    ItemStatus, KeyInfo, MenuEntry, MenuItem, NewAdjustment, NewKey, NewPayment, NewRefund,
+    NewTable, Payment, PaymentMethod, PaymentStatus, Session, SessionDetails, SessionInfo,
+    Snapshot, SplitBill, SplitMode, Table, TableInfo, AMOUNT_OF_TABLES,
+};
+use crate::{
+    billing::{self, BillingRules},
+    clock::{Clock, SystemClock},
+    cook_time,
+    events::{EventFilter, EventHub, Subscription},
+    payments::{self, Outcome, PaymentGateway},
+};
+
+use super::{
+    check_expected, move_items, Checkpoint, Credentials, Journal, Menu, Mutation, NoJournal,
+    RestaurantStore, StoreError,
+};
+
+/// The tables by their number, each behind its own lock
+type Tables = BTreeMap<usize, RwLock<Table>>;
+
+/// The restaurant is a map of tables, the closed sessions and the counters we hand out ids and table numbers from.
+/// We use RwLock inside as multiple people rarely will add items to the same table.
+/// Changes to a table hold the read lock of the map, adding and removing tables its write lock.
+/// Every mutation goes through the journal `J` first.
+pub(crate) struct MemoryStore<J: Journal = NoJournal> {
+    tables: RwLock<Tables>,
+    /// the next id we give to a menu item. Only ever counts up, so ids are never reused.
+    next_item_id: AtomicU64,
+    /// the number the next added table gets. Only ever counts up, so removed tables keep their number to themselves.
+    next_table_number: AtomicUsize,
+    /// the next id we give to a session. Only ever counts up.
+    next_session_id: AtomicU64,
+    /// the closed sessions of all tables, in the order they were closed.
+    /// Only changes while holding the write lock of the table whose session is closed.
+    sessions: Mutex<Vec<Session>>,
+    /// the next id we give to a payment or refund. Only ever counts up.
+    next_payment_id: AtomicU64,
+    /// all payments and refunds by id. Held while journaling one, so the log has them in the order they were made.
+    payments: Mutex<BTreeMap<u64, Payment>>,
+    /// how many mutations were applied, ever. Only changes while holding the write lock of a table or of the map.
+    version: AtomicU64,
+    journal: J,
+    /// where order and status change times come from
+    clock: Arc<dyn Clock>,
+    /// what can be ordered
+    menu: Menu,
+    /// how bills are worked out
+    billing: BillingRules,
+    /// who takes card and deferred payments
+    gateway: PaymentGateway,
+    /// who may do what
+    credentials: Credentials,
+    /// where cook times are drawn from
+    rng: Mutex<StdRng>,
+    /// tells subscribers about every committed mutation
+    events: EventHub,
+}
+
+/// A memory store that persists every mutation in a write-ahead log
+#[cfg(feature = "durable")]
+pub(crate) type DurableStore = MemoryStore<Storage>;
+
+/// The state of a restaurant that just opened with `count` empty tables
+fn fresh(count: usize) -> Checkpoint {
+    Checkpoint {
+        next_item_id: 1,
+        next_table_number: count,
+        next_session_id: 1,
+        tables: (0..count)
+            .map(|i| Table::new(TableInfo::numbered(i)))
+            .collect(),
+        sessions: vec![],
+        next_payment_id: 1,
+        payments: vec![],
+    }
+}
+
+impl MemoryStore {
+    /// Create a new store with [`AMOUNT_OF_TABLES`] empty tables. Nothing is persisted.
+    /// The menu starts empty, see [`MemoryStore::with_menu`].
+    pub(crate) fn new() -> Self {
+        Self::with_table_count(AMOUNT_OF_TABLES)
+    }
+
+    /// Like [`MemoryStore::new`] with `count` tables
+    pub(crate) fn with_table_count(count: usize) -> Self {
+        Self::with_journal(fresh(count), 0, NoJournal)
+    }
+}
+
+impl<J: Journal> MemoryStore<J> {
+    /// Only take orders for what is on `menu`
+    pub(crate) fn with_menu(mut self, menu: Menu) -> Self {
+        self.menu = menu;
+        self
+    }
+
+    /// Work out bills following `rules`
+    pub(crate) fn with_billing(mut self, rules: BillingRules) -> Self {
+        self.billing = rules;
+        self
+    }
+
+    /// Take card and deferred payments through `gateway`
+    pub(crate) fn with_payments(mut self, gateway: PaymentGateway) -> Self {
+        self.gateway = gateway;
+        self
+    }
+
+    /// Only accept the api keys in `credentials`
+    pub(crate) fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Draw cook times from a generator seeded with `seed`, so the same orders get the same cook times
+    pub(crate) fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Use `clock` instead of the system clock from now on
+    #[cfg(test)]
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "durable")]
+impl MemoryStore<Storage> {
+    /// Open a store persisted in `options.dir`, restoring all tables from the snapshot and the log.
+    /// A fresh directory starts with `table_count` tables, after that the persisted floor plan counts.
+    pub(crate) fn open(options: &StorageOptions, table_count: usize) -> anyhow::Result<Self> {
+        let (storage, snapshot, mutations) = Storage::open(options)?;
+        let (mut checkpoint, seq) = match snapshot {
+            Some(snapshot) => (snapshot.checkpoint, snapshot.seq),
+            None => (fresh(table_count), 0),
+        };
+        let mut tables = std::mem::take(&mut checkpoint.tables)
+            .into_iter()
+            .map(|table| (table.table_number, table))
+            .collect::<BTreeMap<_, _>>();
+        // ids of items that were deleted again are only found in the snapshot counter or the log
+        let mut next_item_id = checkpoint.next_item_id.max(1);
+        let mut payments = std::mem::take(&mut checkpoint.payments)
+            .into_iter()
+            .map(|payment| (payment.id, payment))
+            .collect::<BTreeMap<_, _>>();
+        // the log sequence keeps counting over restarts, so versions are never handed out twice
+        let version = seq + mutations.len() as u64;
+        for mutation in mutations {
+            if let Mutation::AddItems { items, .. } = &mutation {
+                next_item_id = items.iter().map(|i| i.id + 1).fold(next_item_id, u64::max);
+            }
+            checkpoint.next_table_number = checkpoint
+                .next_table_number
+                .max(mutation.table_number() + 1);
+            mutation.replay(&mut tables, &mut checkpoint.sessions, &mut payments);
+        }
+        checkpoint.next_item_id = next_item_id;
+        checkpoint.next_payment_id = payments
+            .keys()
+            .map(|id| id + 1)
+            .fold(checkpoint.next_payment_id.max(1), u64::max);
+        checkpoint.payments = payments.into_values().collect();
+        // snapshots from before tables could be added or sessions opened do not have these counters
+        checkpoint.next_table_number = tables
+            .keys()
+            .map(|n| n + 1)
+            .fold(checkpoint.next_table_number, usize::max);
+        checkpoint.next_session_id = tables
+            .values()
+            .filter_map(|table| table.session.as_ref())
+            .chain(checkpoint.sessions.iter().map(|session| &session.info))
+            .map(|session| session.id + 1)
+            .fold(checkpoint.next_session_id.max(1), u64::max);
+        checkpoint.tables = tables.into_values().collect();
+        Ok(Self::with_journal(checkpoint, version, storage))
+    }
+}
+
+impl<J: Journal> MemoryStore<J> {
+    fn with_journal(checkpoint: Checkpoint, version: u64, journal: J) -> Self {
+        Self {
+            tables: RwLock::new(
+                checkpoint
+                    .tables
+                    .into_iter()
+                    .map(|table| (table.table_number, RwLock::new(table)))
+                    .collect(),
+            ),
+            next_item_id: AtomicU64::new(checkpoint.next_item_id),
+            next_table_number: AtomicUsize::new(checkpoint.next_table_number),
+            next_session_id: AtomicU64::new(checkpoint.next_session_id),
+            sessions: Mutex::new(checkpoint.sessions),
+            next_payment_id: AtomicU64::new(checkpoint.next_payment_id),
+            payments: Mutex::new(
+                checkpoint
+                    .payments
+                    .into_iter()
+                    .map(|payment| (payment.id, payment))
+                    .collect(),
+            ),
+            version: AtomicU64::new(version),
+            journal,
+            clock: Arc::new(SystemClock),
+            menu: Menu::default(),
+            billing: BillingRules::default(),
+            gateway: PaymentGateway::default(),
+            credentials: Credentials::default(),
+            rng: Mutex::new(StdRng::from_entropy()),
+            events: EventHub::new(),
+        }
+    }
+
+    /// Reserve a fresh item id
+    fn next_item_id(&self) -> u64 {
+        self.next_item_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Feed how long the kitchen took for `item`, which got ready `at`, to the cook time model of its dish
+    fn learn_cook_time(&self, item: &MenuItem, at: DateTime<Utc>) {
+        let started = item
+            .status_history
+            .iter()
+            .rev()
+            .find(|change| change.status == ItemStatus::Cooking)
+            .map_or(item.ordered_at, |change| change.at);
+        let minutes = (at - started).num_seconds().max(0) as f64 / 60.0;
+        if let Err(e) = self.menu.observe_cook_time(item.item_number, minutes) {
+            // the status change itself went through, the model just does not learn from it
+            tracing::warn!(
+                "Could not update cook time of {}: {:?}",
+                item.item_number,
+                e
+            );
+        }
+    }
+
+    /// Journal `mutation`, apply it to `table`, which has to be the write-locked table the mutation is for,
+    /// and publish what happened. If journaling fails the table is left untouched.
+    /// Returns the session the mutation closed, which is archived as well.
+    fn commit(&self, table: &mut Table, mutation: Mutation) -> Result<Option<Session>, StoreError> {
+        let was_open = is_open(table);
+        let events = self.events_of(table, &mutation);
+        self.journal
+            .append(&mutation)
+            .map_err(StoreError::Storage)?;
+        let closed = mutation.apply(table);
+        if let Some(session) = &closed {
+            self.sessions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(session.clone());
+        }
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        let now = self.clock.now();
+        for (station, kind) in events {
+            self.events.publish(now, table.table_number, station, kind);
+        }
+        if was_open && !is_open(table) {
+            self.events
+                .publish(now, table.table_number, None, EventKind::TableClosed);
+        }
+        Ok(closed)
+    }
+
+    /// A new session starting now
+    fn new_session(&self, details: SessionDetails) -> SessionInfo {
+        SessionInfo {
+            id: self.next_session_id.fetch_add(1, Ordering::Relaxed),
+            guests: details.guests,
+            staff: details.staff,
+            opened_at: self.clock.now(),
+            adjustments: vec![],
+        }
+    }
+
+    /// The bill of the session at `table`, which has to be locked, as of now
+    fn bill_of(&self, table: &Table) -> Result<Bill, StoreError> {
+        let session = table.session.as_ref().ok_or(StoreError::NoSession)?;
+        Ok(billing::bill(
+            session,
+            table.table_number,
+            &table.items,
+            &self.menu.entries(),
+            &self.billing,
+            self.clock.now(),
+        ))
+    }
+
+    /// The session items added to `table` belong to, and the session they open if nobody is seated yet
+    fn session_for(&self, table: &Table) -> (u64, Option<SessionInfo>) {
+        match &table.session {
+            Some(session) => (session.id, None),
+            None => {
+                let session = self.new_session(SessionDetails::default());
+                (session.id, Some(session))
+            }
+        }
+    }
+
+    /// Journal moving `item_ids` from `source` to `target`, which both have to be write-locked, move them
+    /// and publish what happened. If journaling fails both tables are left untouched.
+    /// The moved items join the session of `target`, which is opened if needed.
+    fn commit_transfer(
+        &self,
+        source: &mut Table,
+        target: &mut Table,
+        item_ids: Vec<u64>,
+    ) -> Result<Vec<MenuItem>, StoreError> {
+        let was_open = is_open(source);
+        let (session_id, opens) = self.session_for(target);
+        let mutation = Mutation::TransferItems {
+            from: source.table_number,
+            to: target.table_number,
+            item_ids,
+            session_id: Some(session_id),
+            opens,
+        };
+        self.journal
+            .append(&mutation)
+            .map_err(StoreError::Storage)?;
+        let Mutation::TransferItems {
+            item_ids, opens, ..
+        } = mutation
+        else {
+            unreachable!("built above")
+        };
+        if opens.is_some() {
+            target.session = opens;
+        }
+        let moved = move_items(source, target, &item_ids, Some(session_id));
+        self.version.fetch_add(1, Ordering::Relaxed);
+
+        let now = self.clock.now();
+        for item in &moved {
+            self.events.publish(
+                now,
+                target.table_number,
+                self.menu.get(item.item_number).and_then(|e| e.station),
+                EventKind::ItemMoved {
+                    item: item.clone(),
+                    from: source.table_number,
+                },
+            );
+        }
+        if was_open && !is_open(source) {
+            self.events
+                .publish(now, source.table_number, None, EventKind::TableClosed);
+        }
+        Ok(moved)
+    }
+
+    /// Journal a mutation adding or removing a table and apply it to `tables`, which has to be write-locked.
+    /// If journaling fails the tables are left untouched.
+    fn commit_floor(&self, tables: &mut Tables, mutation: Mutation) -> Result<(), StoreError> {
+        self.journal
+            .append(&mutation)
+            .map_err(StoreError::Storage)?;
+        let table_number = mutation.table_number();
+        let kind = match mutation {
+            Mutation::AddTable { table } => {
+                tables.insert(table_number, RwLock::new(Table::new(table.clone())));
+                self.next_table_number
+                    .fetch_max(table_number + 1, Ordering::Relaxed);
+                EventKind::TableChanged { table }
+            }
+            Mutation::RemoveTable { .. } => {
+                tables.remove(&table_number);
+                EventKind::TableRemoved
+            }
+            _ => unreachable!("only tables are added and removed here"),
+        };
+        self.version.fetch_add(1, Ordering::Relaxed);
+        self.events
+            .publish(self.clock.now(), table_number, None, kind);
+        Ok(())
+    }
+
+    /// Journal `payment` and record it in `payments`, which has to be the locked payments, replacing an earlier
+    /// version of it, and tell the subscribers of its table. If journaling fails nothing is recorded.
+    fn commit_payment(
+        &self,
+        payments: &mut BTreeMap<u64, Payment>,
+        payment: Payment,
+    ) -> Result<(), StoreError> {
+        let mutation = Mutation::RecordPayment { payment };
+        self.journal
+            .append(&mutation)
+            .map_err(StoreError::Storage)?;
+        let Mutation::RecordPayment { payment } = mutation else {
+            unreachable!("built above")
+        };
+        payments.insert(payment.id, payment.clone());
+        self.version.fetch_add(1, Ordering::Relaxed);
+        self.events.publish(
+            self.clock.now(),
+            payment.table_number,
+            None,
+            EventKind::PaymentChanged { payment },
+        );
+        Ok(())
+    }
+
+    /// A new payment or refund starting now, pending unless it is cash
+    fn new_payment(
+        &self,
+        session_id: u64,
+        table_number: usize,
+        method: PaymentMethod,
+        amount: u64,
+    ) -> Payment {
+        let now = self.clock.now();
+        Payment {
+            id: self.next_payment_id.fetch_add(1, Ordering::Relaxed),
+            session_id,
+            table_number,
+            method,
+            amount,
+            status: if method == PaymentMethod::Cash {
+                PaymentStatus::Succeeded
+            } else {
+                PaymentStatus::Pending
+            },
+            refund_of: None,
+            reason: None,
+            reference: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Ask the provider to carry out the pending `payment` and record what it decided.
+    /// The provider is asked without holding any lock, if somebody else settled the payment meanwhile that stays.
+    async fn settle(&self, payment: Payment) -> Result<Payment, StoreError> {
+        let charge = payment.refund_of.and_then(|id| {
+            self.payments
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(&id)
+                .and_then(|refunded| refunded.reference.clone())
+        });
+        let outcome = self.gateway.settle(&payment, charge).await;
+        let mut payments = self.payments.lock().unwrap_or_else(PoisonError::into_inner);
+        let current = payments
+            .get(&payment.id)
+            .cloned()
+            .ok_or(StoreError::UnknownPayment)?;
+        if current.status != PaymentStatus::Pending {
+            return Ok(current);
+        }
+        let settled = match outcome {
+            Outcome::Succeeded { reference } => Payment {
+                status: PaymentStatus::Succeeded,
+                reference: Some(reference),
+                ..current
+            },
+            Outcome::Pending { reference }
+                if reference.is_some() && reference != current.reference =>
+            {
+                Payment {
+                    reference,
+                    ..current
+                }
+            }
+            Outcome::Pending { .. } => return Ok(current),
+            Outcome::Declined { reason } => Payment {
+                status: PaymentStatus::Failed,
+                error: Some(reason),
+                ..current
+            },
+        };
+        let settled = Payment {
+            updated_at: self.clock.now(),
+            ..settled
+        };
+        self.commit_payment(&mut payments, settled.clone())?;
+        Ok(settled)
+    }
+
+    /// The events `mutation` causes on `table`, before it is applied, with the station of the item they are about
+    fn events_of(&self, table: &Table, mutation: &Mutation) -> Vec<(Option<String>, EventKind)> {
+        let item_number = |item_id: u64| {
+            table
+                .items
+                .iter()
+                .find(|item| item.id == item_id)
+                .map_or(0, |item| item.item_number)
+        };
+        let station = |item_number: u64| self.menu.get(item_number).and_then(|e| e.station);
+        match mutation {
+            Mutation::AddItems { items, .. } => items
+                .iter()
+                .map(|item| {
+                    (
+                        station(item.item_number),
+                        EventKind::ItemAdded { item: item.clone() },
+                    )
+                })
+                .collect(),
+            Mutation::RemoveItem { item_id, .. } => {
+                let item_number = item_number(*item_id);
+                vec![(
+                    station(item_number),
+                    EventKind::ItemRemoved {
+                        item_id: *item_id,
+                        item_number,
+                    },
+                )]
+            }
+            Mutation::SetStatus {
+                item_id, status, ..
+            } => {
+                let item_number = item_number(*item_id);
+                vec![(
+                    station(item_number),
+                    EventKind::StatusChanged {
+                        item_id: *item_id,
+                        item_number,
+                        status: *status,
+                    },
+                )]
+            }
+            Mutation::SetSeat { item_id, seat, .. } => {
+                let item_number = item_number(*item_id);
+                vec![(
+                    station(item_number),
+                    EventKind::SeatChanged {
+                        item_id: *item_id,
+                        item_number,
+                        seat: *seat,
+                    },
+                )]
+            }
+            Mutation::UpdateTable { table } => vec![(
+                None,
+                EventKind::TableChanged {
+                    table: table.clone(),
+                },
+            )],
+            Mutation::SetSession { session, .. } => vec![(
+                None,
+                EventKind::SessionChanged {
+                    session: session.clone(),
+                },
+            )],
+            Mutation::CloseSession { .. } => table
+                .session
+                .iter()
+                .map(|session| {
+                    (
+                        None,
+                        EventKind::SessionClosed {
+                            session_id: session.id,
+                        },
+                    )
+                })
+                .collect(),
+            // published by `commit_floor`, `commit_transfer` and `commit_payment`
+            Mutation::TransferItems { .. }
+            | Mutation::AddTable { .. }
+            | Mutation::RemoveTable { .. }
+            | Mutation::RecordPayment { .. } => vec![],
+        }
+    }
+}
+
+/// if somebody is seated at `table` or it still has items
+fn in_use(table: &Table) -> bool {
+    table.session.is_some() || !table.items.is_empty()
+}
+
+/// Check the details of a session
+fn validate_session(details: &SessionDetails) -> Result<(), StoreError> {
+    if details.guests == Some(0) {
+        Err(StoreError::InvalidSession(
+            "a session needs at least one guest".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check a discount or comp against the `session` at `table` it is for, whose bill has `subtotal` so far
+fn validate_adjustment(
+    table: &Table,
+    session: &SessionInfo,
+    subtotal: u64,
+    adjustment: &NewAdjustment,
+) -> Result<(), StoreError> {
+    let invalid = |reason: &str| Err(StoreError::InvalidAdjustment(reason.to_string()));
+    if adjustment.reason.trim().is_empty() {
+        return invalid("a discount or comp needs a reason");
+    }
+    match adjustment.kind {
+        AdjustmentKind::PercentOff { percent } if percent == 0 || percent > 100 => {
+            invalid("a discount has to be between 1 and 100 percent")
+        }
+        AdjustmentKind::AmountOff { amount: 0 } => invalid("a discount has to take something off"),
+        AdjustmentKind::AmountOff { amount } if amount > subtotal => {
+            invalid("a discount cannot take off more than the bill comes to")
+        }
+        AdjustmentKind::Comp { item_id } => {
+            let item = table
+                .items
+                .iter()
+                .find(|item| item.id == item_id)
+                .ok_or(StoreError::UnknownItem)?;
+            if item.status == ItemStatus::Cancelled {
+                invalid("a cancelled item is not on the bill")
+            } else if session
+                .adjustments
+                .iter()
+                .any(|a| a.kind == AdjustmentKind::Comp { item_id })
+            {
+                invalid("the item is already on the house")
+            } else {
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The amount `requested` of what is `left`, all of it without a request
+fn amount_of(requested: Option<u64>, left: u64, what: &str) -> Result<u64, StoreError> {
+    let invalid = |reason: String| Err(StoreError::InvalidPayment(reason));
+    match requested.unwrap_or(left) {
+        0 if requested.is_some() => invalid("an amount has to be at least 1 yen".to_string()),
+        0 => invalid(format!("nothing is left to {}", what)),
+        amount if amount > left => invalid(format!("only {} yen are left to {}", left, what)),
+        amount => Ok(amount),
+    }
+}
+
+/// The lock of table `table_number`
+fn table_lock(tables: &Tables, table_number: usize) -> Result<&RwLock<Table>, StoreError> {
+    tables.get(&table_number).ok_or(StoreError::UnknownTable)
+}
+
+/// Write lock table `table_number`, failing if the request expects it at another version, see [`check_expected`]
+async fn write_table(
+    tables: &Tables,
+    table_number: usize,
+) -> Result<RwLockWriteGuard<'_, Table>, StoreError> {
+    let table = table_lock(tables, table_number)?.write().await;
+    check_expected(&table)?;
+    Ok(table)
+}
+
+/// Check the parts of a floor plan entry the handlers cannot
+fn validate_table(name: &Option<String>, capacity: Option<u32>) -> Result<(), StoreError> {
+    if name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+        Err(StoreError::InvalidTable(
+            "the name of a table cannot be empty".to_string(),
+        ))
+    } else if capacity == Some(0) {
+        Err(StoreError::InvalidTable(
+            "a table has to seat at least one guest".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// if `table` has something that is neither served nor cancelled
+fn is_open(table: &Table) -> bool {
+    table.items.iter().any(|item| !item.status.is_final())
+}
+
+impl<J: Journal> RestaurantStore for MemoryStore<J> {
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    async fn add_items(
+        &self,
+        table_number: usize,
+        item_numbers: Vec<u64>,
+        seat: Option<u32>,
+    ) -> Result<Vec<MenuItem>, StoreError> {
+        if seat == Some(0) {
+            return Err(StoreError::InvalidSeat);
+        }
+        let tables = self.tables.read().await;
+        let lock = table_lock(&tables, table_number)?;
+        let entries = self.menu.orderable(&item_numbers)?;
+        let durations = {
+            let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+            entries
+                .iter()
+                .map(|entry| cook_time::sample(entry, &mut *rng))
+                .collect::<Vec<u64>>()
+        };
+        let mut table = lock.write().await;
+        check_expected(&table)?;
+        if !table.active {
+            return Err(StoreError::InactiveTable);
+        }
+        let (session_id, opens) = self.session_for(&table);
+        let now = self.clock.now();
+        let items = entries
+            .iter()
+            .zip(durations)
+            .map(|(entry, duration)| {
+                new_menu_item(self.next_item_id(), entry, duration, now, session_id, seat)
+            })
+            .collect::<Vec<MenuItem>>();
+        self.commit(
+            &mut table,
+            Mutation::AddItems {
+                table_number,
+                items: items.clone(),
+                opens,
+            },
+        )?;
+        Ok(items)
+    }
+
+    async fn remove_item(&self, table_number: usize, item_id: u64) -> Result<bool, StoreError> {
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        if table.items.iter().any(|item| item.id == item_id) {
+            self.commit(
+                &mut table,
+                Mutation::RemoveItem {
+                    table_number,
+                    item_id,
+                },
+            )?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn set_status(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        status: ItemStatus,
+    ) -> Result<MenuItem, StoreError> {
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        let current = table
+            .items
+            .iter()
+            .find(|item| item.id == item_id)
+            .ok_or(StoreError::UnknownItem)?
+            .status;
+        if !current.can_transition_to(status) {
+            return Err(StoreError::IllegalTransition {
+                from: current,
+                to: status,
+            });
+        }
+        let at = self.clock.now();
+        self.commit(
+            &mut table,
+            Mutation::SetStatus {
+                table_number,
+                item_id,
+                status,
+                at,
+            },
+        )?;
+        let item = table
+            .items
+            .iter()
+            .find(|item| item.id == item_id)
+            .cloned()
+            .expect("the item was there while we hold the lock");
+        drop(table);
+        drop(tables);
+
+        if status == ItemStatus::Ready {
+            self.learn_cook_time(&item, at);
+        }
+        Ok(item)
+    }
+
+    async fn transfer_items(
+        &self,
+        from: usize,
+        to: usize,
+        item_ids: Option<Vec<u64>>,
+    ) -> Result<Vec<MenuItem>, StoreError> {
+        if from == to {
+            return Err(StoreError::SameTable);
+        }
+        let tables = self.tables.read().await;
+        let (source_lock, target_lock) = (table_lock(&tables, from)?, table_lock(&tables, to)?);
+        // the lower table number is always locked first, so two opposite transfers cannot deadlock
+        let (mut source, mut target) = if from < to {
+            let source = source_lock.write().await;
+            (source, target_lock.write().await)
+        } else {
+            let target = target_lock.write().await;
+            (source_lock.write().await, target)
+        };
+        check_expected(&source)?;
+        if !target.active {
+            return Err(StoreError::InactiveTable);
+        }
+        let item_ids = match item_ids {
+            Some(item_ids) => {
+                if !item_ids
+                    .iter()
+                    .all(|id| source.items.iter().any(|item| item.id == *id))
+                {
+                    return Err(StoreError::UnknownItem);
+                }
+                item_ids
+            }
+            None => source.items.iter().map(|item| item.id).collect(),
+        };
+        if item_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        self.commit_transfer(&mut source, &mut target, item_ids)
+    }
+
+    async fn set_seat(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        seat: Option<u32>,
+    ) -> Result<MenuItem, StoreError> {
+        if seat == Some(0) {
+            return Err(StoreError::InvalidSeat);
+        }
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        if !table.items.iter().any(|item| item.id == item_id) {
+            return Err(StoreError::UnknownItem);
+        }
+        self.commit(
+            &mut table,
+            Mutation::SetSeat {
+                table_number,
+                item_id,
+                seat,
+            },
+        )?;
+        let item = table
+            .items
+            .iter()
+            .find(|item| item.id == item_id)
+            .cloned()
+            .expect("the item was there while we hold the lock");
+        Ok(item)
+    }
+
+    async fn table(&self, table_number: usize) -> Result<Table, StoreError> {
+        let tables = self.tables.read().await;
+        let table = table_lock(&tables, table_number)?.read().await.clone();
+        Ok(table)
+    }
+
+    async fn table_version(&self, table_number: usize) -> Result<u64, StoreError> {
+        let tables = self.tables.read().await;
+        let version = table_lock(&tables, table_number)?.read().await.version;
+        Ok(version)
+    }
+
+    async fn item(
+        &self,
+        table_number: usize,
+        item_id: u64,
+    ) -> Result<Option<MenuItem>, StoreError> {
+        let tables = self.tables.read().await;
+        let item = table_lock(&tables, table_number)?
+            .read()
+            .await
+            .items
+            .iter()
+            .find(|item| item.id == item_id)
+            .cloned();
+        Ok(item)
+    }
+
+    async fn snapshot_all(&self) -> Snapshot {
+        // like a checkpoint, holding every read lock means no mutation is applied while we copy
+        let tables = self.tables.read().await;
+        let mut guards = Vec::with_capacity(tables.len());
+        for table in tables.values() {
+            guards.push(table.read().await);
+        }
+        Snapshot {
+            version: self.version.load(Ordering::Relaxed),
+            tables: guards.iter().map(|t| (**t).clone()).collect(),
+        }
+    }
+
+    async fn open_session(
+        &self,
+        table_number: usize,
+        details: SessionDetails,
+    ) -> Result<SessionInfo, StoreError> {
+        validate_session(&details)?;
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        if !table.active {
+            return Err(StoreError::InactiveTable);
+        }
+        if table.session.is_some() {
+            return Err(StoreError::SessionOpen);
+        }
+        let session = self.new_session(details);
+        self.commit(
+            &mut table,
+            Mutation::SetSession {
+                table_number,
+                session: session.clone(),
+            },
+        )?;
+        Ok(session)
+    }
+
+    async fn update_session(
+        &self,
+        table_number: usize,
+        details: SessionDetails,
+    ) -> Result<SessionInfo, StoreError> {
+        validate_session(&details)?;
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        let current = table.session.clone().ok_or(StoreError::NoSession)?;
+        let session = SessionInfo {
+            guests: details.guests.or(current.guests),
+            staff: details.staff.or(current.staff),
+            ..current
+        };
+        self.commit(
+            &mut table,
+            Mutation::SetSession {
+                table_number,
+                session: session.clone(),
+            },
+        )?;
+        Ok(session)
+    }
+
+    async fn close_session(&self, table_number: usize) -> Result<Session, StoreError> {
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        if table.session.is_none() {
+            return Err(StoreError::NoSession);
+        }
+        if is_open(&table) {
+            return Err(StoreError::OpenItems);
+        }
+        let bill = Bill {
+            closed: true,
+            ..self.bill_of(&table)?
+        };
+        let at = bill.issued_at;
+        let closed = self.commit(
+            &mut table,
+            Mutation::CloseSession {
+                table_number,
+                at,
+                bill: Some(bill),
+            },
+        )?;
+        Ok(closed.expect("the table had a session while we hold the lock"))
+    }
+
+    async fn sessions(&self, table_number: usize) -> Vec<Session> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|session| session.table_number == table_number)
+            .cloned()
+            .collect()
+    }
+
+    async fn bill(&self, table_number: usize) -> Result<Bill, StoreError> {
+        let tables = self.tables.read().await;
+        let table = table_lock(&tables, table_number)?.read().await;
+        self.bill_of(&table)
+    }
+
+    async fn split_bill(
+        &self,
+        table_number: usize,
+        mode: SplitMode,
+    ) -> Result<SplitBill, StoreError> {
+        let tables = self.tables.read().await;
+        let table = table_lock(&tables, table_number)?.read().await;
+        billing::split(&self.bill_of(&table)?, &mode).map_err(StoreError::InvalidSplit)
+    }
+
+    async fn adjust_bill(
+        &self,
+        table_number: usize,
+        adjustment: NewAdjustment,
+    ) -> Result<Adjustment, StoreError> {
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        let mut session = table.session.clone().ok_or(StoreError::NoSession)?;
+        let subtotal = self.bill_of(&table)?.subtotal;
+        validate_adjustment(&table, &session, subtotal, &adjustment)?;
+        let adjustment = Adjustment {
+            id: session
+                .adjustments
+                .iter()
+                .map(|a| a.id + 1)
+                .max()
+                .unwrap_or(1),
+            kind: adjustment.kind,
+            reason: adjustment.reason,
+        };
+        session.adjustments.push(adjustment.clone());
+        self.commit(
+            &mut table,
+            Mutation::SetSession {
+                table_number,
+                session,
+            },
+        )?;
+        Ok(adjustment)
+    }
+
+    async fn remove_adjustment(
+        &self,
+        table_number: usize,
+        adjustment_id: u64,
+    ) -> Result<bool, StoreError> {
+        let tables = self.tables.read().await;
+        let mut table = write_table(&tables, table_number).await?;
+        let mut session = table.session.clone().ok_or(StoreError::NoSession)?;
+        let before = session.adjustments.len();
+        session.adjustments.retain(|a| a.id != adjustment_id);
+        if session.adjustments.len() == before {
+            return Ok(false);
+        }
+        self.commit(
+            &mut table,
+            Mutation::SetSession {
+                table_number,
+                session,
+            },
+        )?;
+        Ok(true)
+    }
+
+    async fn bill_payments(&self, table_number: usize) -> Result<BillPayments, StoreError> {
+        let tables = self.tables.read().await;
+        let table = table_lock(&tables, table_number)?.read().await;
+        let bill = self.bill_of(&table)?;
+        let payments = self.payments(Some(bill.session_id)).await;
+        Ok(payments::summary(&bill, payments))
+    }
+
+    async fn pay(&self, table_number: usize, payment: NewPayment) -> Result<Payment, StoreError> {
+        if !self.gateway.takes(payment.method) {
+            return Err(StoreError::NoPaymentProvider);
+        }
+        let made = {
+            let tables = self.tables.read().await;
+            let table = table_lock(&tables, table_number)?.read().await;
+            let bill = self.bill_of(&table)?;
+            // checking the balance and recording the payment under one lock, so two payments cannot both take it
+            let mut payments = self.payments.lock().unwrap_or_else(PoisonError::into_inner);
+            let so_far = payments
+                .values()
+                .filter(|p| p.session_id == bill.session_id)
+                .cloned()
+                .collect();
+            let balance = payments::summary(&bill, so_far).balance;
+            let amount = amount_of(payment.amount, balance, "pay")?;
+            let made = self.new_payment(bill.session_id, table_number, payment.method, amount);
+            self.commit_payment(&mut payments, made.clone())?;
+            made
+        };
+        match made.status {
+            PaymentStatus::Pending => self.settle(made).await,
+            _ => Ok(made),
+        }
+    }
+
+    async fn refund(&self, payment_id: u64, refund: NewRefund) -> Result<Payment, StoreError> {
+        if refund.reason.trim().is_empty() {
+            return Err(StoreError::InvalidPayment(
+                "a refund needs a reason".to_string(),
+            ));
+        }
+        let made = {
+            let mut payments = self.payments.lock().unwrap_or_else(PoisonError::into_inner);
+            let refunded = payments
+                .get(&payment_id)
+                .cloned()
+                .ok_or(StoreError::UnknownPayment)?;
+            if refunded.refund_of.is_some() {
+                return Err(StoreError::InvalidPayment(
+                    "a refund cannot be refunded".to_string(),
+                ));
+            }
+            if refunded.status != PaymentStatus::Succeeded {
+                return Err(StoreError::InvalidPayment(format!(
+                    "only succeeded payments can be refunded, this one is {}",
+                    refunded.status
+                )));
+            }
+            if !self.gateway.takes(refunded.method) {
+                return Err(StoreError::NoPaymentProvider);
+            }
+            let so_far = payments
+                .values()
+                .filter(|p| p.refund_of == Some(payment_id) && p.status != PaymentStatus::Failed)
+                .map(|p| p.amount)
+                .sum::<u64>();
+            let amount = amount_of(refund.amount, refunded.amount - so_far, "refund")?;
+            let made = Payment {
+                refund_of: Some(payment_id),
+                reason: Some(refund.reason),
+                ..self.new_payment(
+                    refunded.session_id,
+                    refunded.table_number,
+                    refunded.method,
+                    amount,
+                )
+            };
+            self.commit_payment(&mut payments, made.clone())?;
+            made
+        };
+        match made.status {
+            PaymentStatus::Pending => self.settle(made).await,
+            _ => Ok(made),
+        }
+    }
+
+    async fn payment(&self, payment_id: u64) -> Option<Payment> {
+        self.payments
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&payment_id)
+            .cloned()
+    }
+
+    async fn payments(&self, session_id: Option<u64>) -> Vec<Payment> {
+        self.payments
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|p| session_id.is_none_or(|id| p.session_id == id))
+            .cloned()
+            .collect()
+    }
+
+    async fn reconcile_payments(&self) {
+        let pending = self
+            .payments
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|p| p.status == PaymentStatus::Pending)
+            .cloned()
+            .collect::<Vec<Payment>>();
+        for payment in pending {
+            let id = payment.id;
+            match self.settle(payment).await {
+                Ok(settled) if settled.status != PaymentStatus::Pending => {
+                    tracing::info!("Payment {} {}", id, settled.status)
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Could not reconcile payment {}: {:?}", id, e),
+            }
+        }
+    }
+
+    async fn floor_plan(&self) -> Vec<TableInfo> {
+        let tables = self.tables.read().await;
+        let mut floor_plan = Vec::with_capacity(tables.len());
+        for table in tables.values() {
+            floor_plan.push(table.read().await.info());
+        }
+        floor_plan
+    }
+
+    async fn add_table(&self, table: NewTable) -> Result<TableInfo, StoreError> {
+        validate_table(&table.name, table.capacity)?;
+        let mut tables = self.tables.write().await;
+        let info = TableInfo {
+            table_number: self.next_table_number.load(Ordering::Relaxed),
+            name: table.name,
+            section: table.section,
+            capacity: table.capacity,
+            active: true,
+        };
+        self.commit_floor(
+            &mut tables,
+            Mutation::AddTable {
+                table: info.clone(),
+            },
+        )?;
+        Ok(info)
+    }
+
+    async fn update_table(&self, table: TableInfo) -> Result<TableInfo, StoreError> {
+        validate_table(&table.name, table.capacity)?;
+        let tables = self.tables.read().await;
+        let mut locked = write_table(&tables, table.table_number).await?;
+        self.commit(
+            &mut locked,
+            Mutation::UpdateTable {
+                table: table.clone(),
+            },
+        )?;
+        Ok(table)
+    }
+
+    async fn remove_table(&self, table_number: usize) -> Result<bool, StoreError> {
+        let mut tables = self.tables.write().await;
+        match tables.get(&table_number) {
+            None => return Ok(false),
+            Some(table) => {
+                let table = table.read().await;
+                check_expected(&table)?;
+                if in_use(&table) {
+                    return Err(StoreError::TableNotEmpty);
+                }
+            }
+        }
+        self.commit_floor(&mut tables, Mutation::RemoveTable { table_number })?;
+        Ok(true)
+    }
+
+    fn subscribe(&self, filter: EventFilter, last_event_id: Option<u64>) -> Subscription {
+        self.events.subscribe(filter, last_event_id)
+    }
+
+    async fn menu(&self) -> Vec<MenuEntry> {
+        self.menu.entries()
+    }
+
+    async fn menu_entry(&self, item_number: u64) -> Option<MenuEntry> {
+        self.menu.get(item_number)
+    }
+
+    async fn add_menu_entry(&self, entry: MenuEntry) -> Result<MenuEntry, StoreError> {
+        self.menu.insert(entry)
+    }
+
+    async fn replace_menu_entry(&self, entry: MenuEntry) -> Result<MenuEntry, StoreError> {
+        self.menu.replace(entry)
+    }
+
+    async fn remove_menu_entry(&self, item_number: u64) -> Result<bool, StoreError> {
+        self.menu.remove(item_number)
+    }
+
+    fn authenticate(&self, key: &str) -> Option<KeyInfo> {
+        self.credentials.authenticate(key)
+    }
+
+    fn signing_key(&self, key_id: u64) -> Option<(KeyInfo, Vec<u8>)> {
+        self.credentials.signing_key(key_id)
+    }
+
+    fn use_nonce(&self, key_id: u64, nonce: &str, expires_at: i64) -> bool {
+        self.credentials
+            .use_nonce(key_id, nonce, expires_at, self.now().timestamp())
+    }
+
+    async fn keys(&self) -> Vec<KeyInfo> {
+        self.credentials.list()
+    }
+
+    async fn create_key(&self, key: NewKey) -> Result<IssuedKey, StoreError> {
+        self.credentials.create(key)
+    }
+
+    async fn revoke_key(&self, key_id: u64) -> Result<bool, StoreError> {
+        self.credentials.revoke(key_id)
+    }
+
+    async fn rotate_key(&self, key_id: u64) -> Result<IssuedKey, StoreError> {
+        self.credentials.rotate(key_id)
+    }
+
+    async fn checkpoint(&self) -> io::Result<()> {
+        // holding every read lock means no mutation is halfway between the journal and its table
+        let tables = self.tables.read().await;
+        let mut guards = Vec::with_capacity(tables.len());
+        for table in tables.values() {
+            guards.push(table.read().await);
+        }
+        // and holding the payments means no payment is halfway either
+        let payments = self.payments.lock().unwrap_or_else(PoisonError::into_inner);
+        self.journal.write_snapshot(Checkpoint {
+            next_item_id: self.next_item_id.load(Ordering::Relaxed),
+            next_table_number: self.next_table_number.load(Ordering::Relaxed),
+            next_session_id: self.next_session_id.load(Ordering::Relaxed),
+            tables: guards.iter().map(|t| (**t).clone()).collect(),
+            sessions: self
+                .sessions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            next_payment_id: self.next_payment_id.load(Ordering::Relaxed),
+            payments: payments.values().cloned().collect(),
+        })
+    }
+}
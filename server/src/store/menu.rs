@@ -0,0 +1,236 @@
+//! The menu card. Only dishes on it can be ordered.
+//! It is loaded from a TOML or JSON file at startup and every change made through the api is written back to that file.
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{PoisonError, RwLock},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{cook_time, types::MenuEntry};
+
+use super::{replace_file, StoreError};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+/// The layout of the menu file
+struct MenuFile {
+    #[serde(default)]
+    items: Vec<MenuEntry>,
+}
+
+#[derive(Debug, Default)]
+/// The menu entries by item number
+pub(crate) struct Menu {
+    entries: RwLock<BTreeMap<u64, MenuEntry>>,
+    /// where changes are written to, if the menu came from a file
+    path: Option<PathBuf>,
+}
+
+/// Check that `entry` makes sense on its own
+fn validate(entry: &MenuEntry) -> Result<(), String> {
+    if entry.name.trim().is_empty() {
+        Err(format!("menu entry {} has no name", entry.item_number))
+    } else if entry.min_cook_minutes == 0 {
+        Err(format!(
+            "menu entry {} cannot be cooked in zero minutes",
+            entry.item_number
+        ))
+    } else if entry.min_cook_minutes > entry.max_cook_minutes {
+        Err(format!(
+            "menu entry {} cooks at least {} but at most {} minutes",
+            entry.item_number, entry.min_cook_minutes, entry.max_cook_minutes
+        ))
+    } else {
+        cook_time::validate(entry)
+    }
+}
+
+/// if the file at `path` is JSON rather than TOML
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+impl Menu {
+    /// A menu that only lives in memory
+    pub(crate) fn new(entries: Vec<MenuEntry>) -> anyhow::Result<Self> {
+        let mut map = BTreeMap::new();
+        for entry in entries {
+            validate(&entry).map_err(anyhow::Error::msg)?;
+            let item_number = entry.item_number;
+            if map.insert(item_number, entry).is_some() {
+                anyhow::bail!("item number {} is listed twice on the menu", item_number);
+            }
+        }
+        Ok(Self {
+            entries: RwLock::new(map),
+            path: None,
+        })
+    }
+
+    /// Load the menu from `path`, a JSON file if it ends in `.json` and TOML otherwise
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read menu {}", path.display()))?;
+        let file: MenuFile = if is_json(path) {
+            serde_json::from_str(&content)?
+        } else {
+            toml::from_str(&content)?
+        };
+        let mut menu =
+            Self::new(file.items).with_context(|| format!("Invalid menu {}", path.display()))?;
+        menu.path = Some(path.to_path_buf());
+        Ok(menu)
+    }
+
+    /// All entries ordered by item number
+    pub(crate) fn entries(&self) -> Vec<MenuEntry> {
+        self.read().values().cloned().collect()
+    }
+
+    pub(crate) fn get(&self, item_number: u64) -> Option<MenuEntry> {
+        self.read().get(&item_number).cloned()
+    }
+
+    /// The entries for `item_numbers` in the same order, if every one of them is on the menu and available.
+    /// Otherwise all offending item numbers are reported at once.
+    pub(crate) fn orderable(&self, item_numbers: &[u64]) -> Result<Vec<MenuEntry>, StoreError> {
+        let entries = self.read();
+        let mut unknown = vec![];
+        let mut unavailable = vec![];
+        let mut found = Vec::with_capacity(item_numbers.len());
+        for number in item_numbers {
+            match entries.get(number) {
+                None => unknown.push(*number),
+                Some(entry) if !entry.available => unavailable.push(*number),
+                Some(entry) => found.push(entry.clone()),
+            }
+        }
+        if unknown.is_empty() && unavailable.is_empty() {
+            Ok(found)
+        } else {
+            unknown.sort_unstable();
+            unknown.dedup();
+            unavailable.sort_unstable();
+            unavailable.dedup();
+            Err(StoreError::NotOrderable {
+                unknown,
+                unavailable,
+            })
+        }
+    }
+
+    /// Add a new entry, failing if its item number is taken
+    pub(crate) fn insert(&self, entry: MenuEntry) -> Result<MenuEntry, StoreError> {
+        validate(&entry).map_err(StoreError::InvalidMenuEntry)?;
+        self.change(|entries| {
+            if entries.contains_key(&entry.item_number) {
+                return Err(StoreError::DuplicateMenuEntry);
+            }
+            entries.insert(entry.item_number, entry.clone());
+            Ok(entry)
+        })
+    }
+
+    /// Replace the entry with the same item number, failing if there is none
+    pub(crate) fn replace(&self, entry: MenuEntry) -> Result<MenuEntry, StoreError> {
+        validate(&entry).map_err(StoreError::InvalidMenuEntry)?;
+        self.change(|entries| match entries.get_mut(&entry.item_number) {
+            Some(old) => {
+                *old = entry.clone();
+                Ok(entry)
+            }
+            None => Err(StoreError::UnknownMenuEntry),
+        })
+    }
+
+    /// Remove an entry. Returns if there was such an entry.
+    /// Items already ordered keep their item number.
+    pub(crate) fn remove(&self, item_number: u64) -> Result<bool, StoreError> {
+        self.change(|entries| Ok(entries.remove(&item_number).is_some()))
+    }
+
+    /// Let the cook time model of `item_number` know the kitchen took `minutes` for it.
+    /// Only learned models change, so only they cause the menu file to be written.
+    pub(crate) fn observe_cook_time(
+        &self,
+        item_number: u64,
+        minutes: f64,
+    ) -> Result<(), StoreError> {
+        let learns = self.read().get(&item_number).is_some_and(|entry| {
+            matches!(entry.cook_time, crate::types::CookTimeModel::Learned { .. })
+        });
+        if learns {
+            self.change(|entries| {
+                if let Some(entry) = entries.get_mut(&item_number) {
+                    cook_time::observe(&mut entry.cook_time, minutes);
+                }
+                Ok(())
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<u64, MenuEntry>> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Apply `f` to a copy of the entries and, if it succeeds and a file backs the menu, save the copy.
+    /// Only then the copy replaces the current entries, so a failed write changes nothing.
+    fn change<T>(
+        &self,
+        f: impl FnOnce(&mut BTreeMap<u64, MenuEntry>) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        let mut changed = entries.clone();
+        let result = f(&mut changed)?;
+        if let Some(path) = &self.path {
+            save(path, &changed).map_err(StoreError::Storage)?;
+        }
+        *entries = changed;
+        Ok(result)
+    }
+}
+
+/// Write `entries` to `path`, see [`replace_file`]
+fn save(path: &Path, entries: &BTreeMap<u64, MenuEntry>) -> io::Result<()> {
+    let file = MenuFile {
+        items: entries.values().cloned().collect(),
+    };
+    let content = if is_json(path) {
+        serde_json::to_string_pretty(&file)?
+    } else {
+        toml::to_string_pretty(&file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+    };
+    replace_file(path, &content)
+}
+
+#[cfg(test)]
+use crate::types::CookTimeModel;
+
+#[cfg(test)]
+impl Menu {
+    /// A menu with the item numbers 1 to 40, each cooking in 5 to 15 minutes
+    pub(crate) fn for_tests() -> Self {
+        Self::new(
+            (1..=40)
+                .map(|item_number| MenuEntry {
+                    item_number,
+                    name: format!("Dish {}", item_number),
+                    category: "Mains".to_string(),
+                    price: 100 * item_number,
+                    min_cook_minutes: 5,
+                    max_cook_minutes: 15,
+                    cook_time: CookTimeModel::Uniform,
+                    station: None,
+                    priority: 0,
+                    available: true,
+                })
+                .collect(),
+        )
+        .expect("the test menu is valid")
+    }
+}
@@ -0,0 +1,650 @@
+//! The storage backends behind the http handlers.
+//! Handlers only talk to a [`RestaurantStore`], so the business rules can be tested without http
+//! and the backend can be swapped, see the `durable` feature.
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    events::{EventFilter, Subscription},
+    types::{
+        Adjustment, Bill, BillPayments, IssuedKey, ItemStatus, KeyInfo, MenuEntry, MenuItem,
+        NewAdjustment, NewKey, NewPayment, NewRefund, NewTable, Payment, Session, SessionDetails,
+        SessionInfo, Snapshot, SplitBill, SplitMode, StatusChange, Table, TableInfo,
+    },
+};
+
+mod credentials;
+mod memory;
+mod menu;
+
+#[cfg(feature = "durable")]
+pub(crate) use memory::DurableStore;
+// without the `durable` feature and in the tests we use the plain in-memory store
+pub(crate) use credentials::Credentials;
+#[allow(unused_imports)]
+pub(crate) use memory::MemoryStore;
+pub(crate) use menu::Menu;
+
+#[derive(Debug)]
+/// Why a store operation failed
+pub(crate) enum StoreError {
+    /// there is no table with this number
+    UnknownTable,
+    /// the table is inactive and takes no new orders
+    InactiveTable,
+    /// the table still has items or a session, so it cannot be removed
+    TableNotEmpty,
+    /// the floor plan entry does not make sense, with the reason
+    InvalidTable(String),
+    /// nobody is seated at the table
+    NoSession,
+    /// a party is already seated at the table
+    SessionOpen,
+    /// the session still has items that are neither served nor cancelled
+    OpenItems,
+    /// the session details do not make sense, with the reason
+    InvalidSession(String),
+    /// the discount or comp does not make sense, with the reason
+    InvalidAdjustment(String),
+    /// there is no item with this id on the table
+    UnknownItem,
+    /// seats are counted from 1
+    InvalidSeat,
+    /// the bill cannot be split like this, with the reason
+    InvalidSplit(String),
+    /// there is no payment with this id
+    UnknownPayment,
+    /// the payment or refund does not make sense, with the reason
+    InvalidPayment(String),
+    /// card and deferred payments need a payment provider
+    NoPaymentProvider,
+    /// items were to be moved to the table they are already on
+    SameTable,
+    /// the request expected the table at another version, see [`Expectation`]
+    VersionMismatch { current: u64 },
+    /// the item cannot move from its current status to the requested one
+    IllegalTransition { from: ItemStatus, to: ItemStatus },
+    /// some of the ordered item numbers are not on the menu or not available
+    NotOrderable {
+        unknown: Vec<u64>,
+        unavailable: Vec<u64>,
+    },
+    /// there is no menu entry with this item number
+    UnknownMenuEntry,
+    /// there already is a menu entry with this item number
+    DuplicateMenuEntry,
+    /// the menu entry does not make sense, with the reason
+    InvalidMenuEntry(String),
+    /// there is no api key with this id
+    UnknownKey,
+    /// the key is the only admin key left, without it nobody could manage keys anymore
+    LastAdminKey,
+    /// the new api key does not make sense, with the reason
+    InvalidKey(String),
+    /// the change could not be persisted and was not applied
+    Storage(io::Error),
+}
+
+/// Everything the handlers need from a backend.
+/// Tables are identified by their number starting at zero, items by their server assigned id.
+pub(crate) trait RestaurantStore: Send + Sync + 'static {
+    /// The current time according to the store's clock
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Create items for the given `item_numbers` on a table, ordered by the guest at `seat`, and return them.
+    /// Fails with [`StoreError::NotOrderable`] unless every item number is on the menu and available.
+    fn add_items(
+        &self,
+        table_number: usize,
+        item_numbers: Vec<u64>,
+        seat: Option<u32>,
+    ) -> impl Future<Output = Result<Vec<MenuItem>, StoreError>> + Send;
+
+    /// Remove the item `item_id` from a table. Returns if there was such an item.
+    fn remove_item(
+        &self,
+        table_number: usize,
+        item_id: u64,
+    ) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// Move the item `item_id` to `status`, returning the updated item.
+    /// Fails with [`StoreError::IllegalTransition`] if the item's state machine does not allow it.
+    fn set_status(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        status: ItemStatus,
+    ) -> impl Future<Output = Result<MenuItem, StoreError>> + Send;
+
+    /// Move the item `item_id` to the guest at `seat`, or to the whole table without one
+    fn set_seat(
+        &self,
+        table_number: usize,
+        item_id: u64,
+        seat: Option<u32>,
+    ) -> impl Future<Output = Result<MenuItem, StoreError>> + Send;
+
+    /// A copy of a table
+    fn table(&self, table_number: usize) -> impl Future<Output = Result<Table, StoreError>> + Send;
+
+    /// The version of a table, without copying it
+    fn table_version(
+        &self,
+        table_number: usize,
+    ) -> impl Future<Output = Result<u64, StoreError>> + Send;
+
+    /// A copy of the item `item_id` on a table, if there is one
+    fn item(
+        &self,
+        table_number: usize,
+        item_id: u64,
+    ) -> impl Future<Output = Result<Option<MenuItem>, StoreError>> + Send;
+
+    /// A copy of all tables, ordered by table number, taken at a single point in time.
+    /// Its version counts the changes made so far, so equal versions mean equal tables.
+    fn snapshot_all(&self) -> impl Future<Output = Snapshot> + Send;
+
+    /// Move the items `item_ids` from the table `from` to the end of the table `to` in a single step,
+    /// keeping their ids, times and status. Without `item_ids` all items move, merging the tables.
+    /// Fails without moving anything if one of the items is not on `from`.
+    fn transfer_items(
+        &self,
+        from: usize,
+        to: usize,
+        item_ids: Option<Vec<u64>>,
+    ) -> impl Future<Output = Result<Vec<MenuItem>, StoreError>> + Send;
+
+    /// Seat a party at `table_number`. Adding items to a table without a session opens one as well.
+    fn open_session(
+        &self,
+        table_number: usize,
+        details: SessionDetails,
+    ) -> impl Future<Output = Result<SessionInfo, StoreError>> + Send;
+
+    /// Change the given details of the session at `table_number`
+    fn update_session(
+        &self,
+        table_number: usize,
+        details: SessionDetails,
+    ) -> impl Future<Output = Result<SessionInfo, StoreError>> + Send;
+
+    /// Archive the session at `table_number` with all its items and its final bill, leaving the table empty.
+    /// Fails with [`StoreError::OpenItems`] while the kitchen or the waiters are not done with an item.
+    fn close_session(
+        &self,
+        table_number: usize,
+    ) -> impl Future<Output = Result<Session, StoreError>> + Send;
+
+    /// The closed sessions of `table_number`, oldest first
+    fn sessions(&self, table_number: usize) -> impl Future<Output = Vec<Session>> + Send;
+
+    /// The bill of the session at `table_number` as it would be if the party left now
+    fn bill(&self, table_number: usize) -> impl Future<Output = Result<Bill, StoreError>> + Send;
+
+    /// Split the bill of the session at `table_number` into shares, see [`crate::billing::split`]
+    fn split_bill(
+        &self,
+        table_number: usize,
+        mode: SplitMode,
+    ) -> impl Future<Output = Result<SplitBill, StoreError>> + Send;
+
+    /// Put a discount or comp on the bill of the session at `table_number`
+    fn adjust_bill(
+        &self,
+        table_number: usize,
+        adjustment: NewAdjustment,
+    ) -> impl Future<Output = Result<Adjustment, StoreError>> + Send;
+
+    /// Take the discount or comp `adjustment_id` off the bill again. Returns if there was such an adjustment.
+    fn remove_adjustment(
+        &self,
+        table_number: usize,
+        adjustment_id: u64,
+    ) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// What the party at `table_number` paid so far and what is still open
+    fn bill_payments(
+        &self,
+        table_number: usize,
+    ) -> impl Future<Output = Result<BillPayments, StoreError>> + Send;
+
+    /// Pay towards the bill of the session at `table_number`, everything still open without an amount.
+    /// Card and deferred payments are pending until the provider decides, see [`crate::payments`].
+    fn pay(
+        &self,
+        table_number: usize,
+        payment: NewPayment,
+    ) -> impl Future<Output = Result<Payment, StoreError>> + Send;
+
+    /// Pay back the payment `payment_id`, everything not refunded yet without an amount
+    fn refund(
+        &self,
+        payment_id: u64,
+        refund: NewRefund,
+    ) -> impl Future<Output = Result<Payment, StoreError>> + Send;
+
+    /// The payment or refund `payment_id`, if there is one
+    fn payment(&self, payment_id: u64) -> impl Future<Output = Option<Payment>> + Send;
+
+    /// All payments and refunds, or those of `session_id`, oldest first
+    fn payments(&self, session_id: Option<u64>) -> impl Future<Output = Vec<Payment>> + Send;
+
+    /// Ask the provider again about every pending payment and refund
+    fn reconcile_payments(&self) -> impl Future<Output = ()> + Send;
+
+    /// The floor plan, all tables including inactive ones, ordered by table number
+    fn floor_plan(&self) -> impl Future<Output = Vec<TableInfo>> + Send;
+
+    /// Add an empty table, numbered after every table there ever was
+    fn add_table(
+        &self,
+        table: NewTable,
+    ) -> impl Future<Output = Result<TableInfo, StoreError>> + Send;
+
+    /// Replace the floor plan entry of the table with the same number, keeping its items
+    fn update_table(
+        &self,
+        table: TableInfo,
+    ) -> impl Future<Output = Result<TableInfo, StoreError>> + Send;
+
+    /// Take a table off the floor plan. Returns if there was such a table.
+    /// Fails with [`StoreError::TableNotEmpty`] while it has items or a session, deactivate it instead.
+    fn remove_table(
+        &self,
+        table_number: usize,
+    ) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// Follow the changes matching `filter` from now on, or from after `last_event_id` if it is given
+    fn subscribe(&self, filter: EventFilter, last_event_id: Option<u64>) -> Subscription;
+
+    /// The menu, ordered by item number
+    fn menu(&self) -> impl Future<Output = Vec<MenuEntry>> + Send;
+
+    /// The menu entry for `item_number`, if there is one
+    fn menu_entry(&self, item_number: u64) -> impl Future<Output = Option<MenuEntry>> + Send;
+
+    /// Put a new entry on the menu
+    fn add_menu_entry(
+        &self,
+        entry: MenuEntry,
+    ) -> impl Future<Output = Result<MenuEntry, StoreError>> + Send;
+
+    /// Replace the menu entry with the same item number
+    fn replace_menu_entry(
+        &self,
+        entry: MenuEntry,
+    ) -> impl Future<Output = Result<MenuEntry, StoreError>> + Send;
+
+    /// Take an entry off the menu. Returns if there was such an entry.
+    fn remove_menu_entry(
+        &self,
+        item_number: u64,
+    ) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// Who holds `key`, if it is a valid api key
+    fn authenticate(&self, key: &str) -> Option<KeyInfo>;
+
+    /// The api key `key_id` and the key its requests are signed with
+    fn signing_key(&self, key_id: u64) -> Option<(KeyInfo, Vec<u8>)>;
+
+    /// Remember the nonce of a signed request of `key_id` until `expires_at` (unix seconds).
+    /// Returns false for a nonce that is still remembered, i.e., a replayed request.
+    fn use_nonce(&self, key_id: u64, nonce: &str, expires_at: i64) -> bool;
+
+    /// All api keys, ordered by id
+    fn keys(&self) -> impl Future<Output = Vec<KeyInfo>> + Send;
+
+    /// Hand out a new api key
+    fn create_key(&self, key: NewKey)
+        -> impl Future<Output = Result<IssuedKey, StoreError>> + Send;
+
+    /// Revoke an api key. Returns if there was such a key.
+    /// Fails with [`StoreError::LastAdminKey`] instead of revoking the only admin key.
+    fn revoke_key(&self, key_id: u64) -> impl Future<Output = Result<bool, StoreError>> + Send;
+
+    /// Give an api key a new secret, the old one stops working
+    fn rotate_key(&self, key_id: u64)
+        -> impl Future<Output = Result<IssuedKey, StoreError>> + Send;
+
+    /// Make everything durable that the backend would otherwise only keep in a log or in memory
+    fn checkpoint(&self) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Checkpoint `store` every `interval`, forever
+pub(crate) async fn checkpoint_periodically<S: RestaurantStore>(store: Arc<S>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = store.checkpoint().await {
+            tracing::error!("Could not write snapshot: {}", e);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A change to a single table or to which tables there are. Stores apply these and journals record them.
+pub(crate) enum Mutation {
+    AddItems {
+        table_number: usize,
+        items: Vec<MenuItem>,
+        /// the session the items open, if nobody was seated yet
+        #[serde(default)]
+        opens: Option<SessionInfo>,
+    },
+    RemoveItem {
+        table_number: usize,
+        item_id: u64,
+    },
+    SetStatus {
+        table_number: usize,
+        item_id: u64,
+        status: ItemStatus,
+        at: DateTime<Utc>,
+    },
+    SetSeat {
+        table_number: usize,
+        item_id: u64,
+        seat: Option<u32>,
+    },
+    TransferItems {
+        from: usize,
+        to: usize,
+        item_ids: Vec<u64>,
+        /// the session of `to` the items join
+        #[serde(default)]
+        session_id: Option<u64>,
+        /// the session the items open at `to`, if nobody was seated there yet
+        #[serde(default)]
+        opens: Option<SessionInfo>,
+    },
+    SetSession {
+        table_number: usize,
+        session: SessionInfo,
+    },
+    CloseSession {
+        table_number: usize,
+        at: DateTime<Utc>,
+        /// the final bill, worked out before so replaying does not depend on today's menu and rules
+        #[serde(default)]
+        bill: Option<Bill>,
+    },
+    AddTable {
+        table: TableInfo,
+    },
+    UpdateTable {
+        table: TableInfo,
+    },
+    RemoveTable {
+        table_number: usize,
+    },
+    /// a payment or refund was made or its status changed
+    RecordPayment {
+        payment: Payment,
+    },
+}
+
+impl Mutation {
+    /// the table this mutation changes, needed when replaying a journal
+    pub(crate) fn table_number(&self) -> usize {
+        match self {
+            Mutation::AddItems { table_number, .. } => *table_number,
+            Mutation::RemoveItem { table_number, .. } => *table_number,
+            Mutation::SetStatus { table_number, .. } => *table_number,
+            Mutation::SetSeat { table_number, .. } => *table_number,
+            Mutation::TransferItems { from, .. } => *from,
+            Mutation::SetSession { table_number, .. } => *table_number,
+            Mutation::CloseSession { table_number, .. } => *table_number,
+            Mutation::AddTable { table } => table.table_number,
+            Mutation::UpdateTable { table } => table.table_number,
+            Mutation::RemoveTable { table_number } => *table_number,
+            Mutation::RecordPayment { payment } => payment.table_number,
+        }
+    }
+
+    /// Apply the mutation to `tables`, adding and removing tables as needed, archiving closed sessions in `sessions`
+    /// and recording payments in `payments`
+    #[cfg_attr(not(feature = "durable"), allow(dead_code))]
+    pub(crate) fn replay(
+        self,
+        tables: &mut BTreeMap<usize, Table>,
+        sessions: &mut Vec<Session>,
+        payments: &mut BTreeMap<u64, Payment>,
+    ) {
+        match self {
+            Mutation::RecordPayment { payment } => {
+                payments.insert(payment.id, payment);
+            }
+            Mutation::AddTable { table } => {
+                tables.insert(table.table_number, Table::new(table));
+            }
+            Mutation::RemoveTable { table_number } => {
+                tables.remove(&table_number);
+            }
+            Mutation::TransferItems {
+                from,
+                to,
+                item_ids,
+                session_id,
+                opens,
+            } => {
+                if let Some(mut source) = tables.remove(&from) {
+                    if let Some(target) = tables.get_mut(&to) {
+                        if opens.is_some() {
+                            target.session = opens;
+                        }
+                        move_items(&mut source, target, &item_ids, session_id);
+                    }
+                    tables.insert(from, source);
+                }
+            }
+            // logs from before tables could be added only know tables by their number
+            mutation => {
+                let table = tables
+                    .entry(mutation.table_number())
+                    .or_insert_with_key(|&n| Table::new(TableInfo::numbered(n)));
+                sessions.extend(mutation.apply(table));
+            }
+        }
+    }
+
+    /// Apply the mutation to its table and count up its version, returning the session it closed for the archive.
+    /// Adding and removing tables and moving items between them is up to the caller, see [`Mutation::replay`].
+    pub(crate) fn apply(self, table: &mut Table) -> Option<Session> {
+        let mut closed = None;
+        match self {
+            Mutation::AddItems { items, opens, .. } => {
+                if opens.is_some() {
+                    table.session = opens;
+                }
+                table.items.extend(items);
+            }
+            Mutation::RemoveItem { item_id, .. } => {
+                table.items.retain(|item| item.id != item_id);
+            }
+            Mutation::SetStatus {
+                item_id,
+                status,
+                at,
+                ..
+            } => {
+                if let Some(item) = table.items.iter_mut().find(|item| item.id == item_id) {
+                    item.status = status;
+                    item.status_history.push(StatusChange { status, at });
+                }
+            }
+            Mutation::SetSeat { item_id, seat, .. } => {
+                if let Some(item) = table.items.iter_mut().find(|item| item.id == item_id) {
+                    item.seat = seat;
+                }
+            }
+            Mutation::UpdateTable { table: info } => {
+                table.name = info.name;
+                table.section = info.section;
+                table.capacity = info.capacity;
+                table.active = info.active;
+            }
+            Mutation::SetSession { session, .. } => table.session = Some(session),
+            Mutation::CloseSession { at, bill, .. } => {
+                closed = table.session.take().map(|info| Session {
+                    info,
+                    table_number: table.table_number,
+                    closed_at: at,
+                    items: std::mem::take(&mut table.items),
+                    bill,
+                });
+            }
+            Mutation::TransferItems { .. }
+            | Mutation::AddTable { .. }
+            | Mutation::RemoveTable { .. }
+            | Mutation::RecordPayment { .. } => return None,
+        }
+        table.version += 1;
+        closed
+    }
+}
+
+/// Move the items `item_ids` from `source` to the end of `target` and into its session `session_id`,
+/// keeping their order, and count up the versions of both. Returns the moved items.
+pub(crate) fn move_items(
+    source: &mut Table,
+    target: &mut Table,
+    item_ids: &[u64],
+    session_id: Option<u64>,
+) -> Vec<MenuItem> {
+    let (mut moved, kept): (Vec<MenuItem>, Vec<MenuItem>) = std::mem::take(&mut source.items)
+        .into_iter()
+        .partition(|item| item_ids.contains(&item.id));
+    source.items = kept;
+    if session_id.is_some() {
+        for item in &mut moved {
+            item.session_id = session_id;
+        }
+    }
+    target.items.extend(moved.iter().cloned());
+    source.version += 1;
+    target.version += 1;
+    moved
+}
+
+tokio::task_local! {
+    /// what the request handled by the task expects, see [`Expectation::scope`]
+    static EXPECTED: Expectation;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The versions a request accepts table `table_number` at when changing it, from its `If-Match` header
+pub(crate) struct Expectation {
+    pub(crate) table_number: usize,
+    pub(crate) versions: Vec<u64>,
+}
+
+impl Expectation {
+    /// Run `f`, which fails with [`StoreError::VersionMismatch`] if it goes to change the table at another version.
+    /// The version is checked while holding the write lock of the table, so nothing can change it in between.
+    pub(crate) async fn scope<F: Future>(self, f: F) -> F::Output {
+        EXPECTED.scope(self, f).await
+    }
+}
+
+/// Fails if the running request expects `table`, which has to be locked, at another version
+pub(crate) fn check_expected(table: &Table) -> Result<(), StoreError> {
+    EXPECTED
+        .try_with(|expected| {
+            if expected.table_number == table.table_number
+                && !expected.versions.contains(&table.version)
+            {
+                Err(StoreError::VersionMismatch {
+                    current: table.version,
+                })
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_or(Ok(()))
+}
+
+/// Write `content` to `path` through a temporary file, so a crash leaves either the old or the new content.
+/// The file is synced before it replaces the old one and the directory after, so the new content has reached
+/// the disk when this returns.
+pub(crate) fn replace_file(path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content.as_ref())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    sync_dir(dir.unwrap_or(Path::new(".")))
+}
+
+/// fsync a directory so a rename inside it is durable
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Everything needed to restore a store, apart from the mutations made after it
+pub(crate) struct Checkpoint {
+    pub(crate) next_item_id: u64,
+    /// missing in snapshots from before tables could be added
+    #[serde(default)]
+    pub(crate) next_table_number: usize,
+    /// missing in snapshots from before there were sessions
+    #[serde(default)]
+    pub(crate) next_session_id: u64,
+    pub(crate) tables: Vec<Table>,
+    /// the closed sessions of all tables
+    #[serde(default)]
+    pub(crate) sessions: Vec<Session>,
+    /// missing in snapshots from before there were payments
+    #[serde(default)]
+    pub(crate) next_payment_id: u64,
+    /// all payments and refunds, by id
+    #[serde(default)]
+    pub(crate) payments: Vec<Payment>,
+}
+
+/// Records mutations before the [`MemoryStore`] applies them
+pub(crate) trait Journal: Send + Sync + 'static {
+    /// Record a mutation. Called while holding the write lock of the mutated table,
+    /// of the floor plan for adding and removing tables, or of the payments for recording one.
+    /// If this fails the mutation is not applied.
+    fn append(&self, mutation: &Mutation) -> io::Result<()>;
+
+    /// Record the complete state, after which earlier mutations are no longer needed.
+    /// Called while holding the read locks of all tables and the lock of the payments.
+    fn write_snapshot(&self, checkpoint: Checkpoint) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+/// A journal that forgets everything, for a purely in-memory store
+pub(crate) struct NoJournal;
+
+impl Journal for NoJournal {
+    fn append(&self, _mutation: &Mutation) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_snapshot(&self, _checkpoint: Checkpoint) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,15 +1,56 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
     use crate::{
-        router,
-        types::{MenuItem, Table, API_KEY},
+        auth,
+        billing::{BillingRules, Rounding},
+        clock::{Clock, ManualClock},
+        config::{Cli, Config},
+        events::EventFilter,
+        payments::{
+            self, HttpProvider, MockProvider, Outcome, PaymentGateway, PaymentProvider,
+            ProviderError,
+        },
+        router, router_with_config, router_with_state,
+        store::{Credentials, Expectation, MemoryStore, Menu, RestaurantStore, StoreError},
+        types::{
+            Adjustment, AdjustmentKind, Bill, BillPayments, BillShare, CookTimeModel, IssuedKey,
+            KeyInfo, MenuEntry, MenuItem, NewAdjustment, NewKey, NewPayment, NewRefund, NewTable,
+            Payment, PaymentMethod, PaymentStatus, Role, SeatUpdate, Snapshot, SplitBill,
+            SplitMode, Table, TableInfo, API_KEY,
+        },
+    };
+    use axum::{
+        extract::State,
+        http::{
+            header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH},
+            HeaderMap, StatusCode,
+        },
+        response::{IntoResponse, Response},
+        Json,
     };
     use axum_test::{TestResponse, TestServer};
+    use restaurant_types::{
+        routes,
+        signing::{self, MAX_SKEW_SECONDS},
+        ErrorBody, ErrorKind, Event, EventKind, ItemStatus, ItemTransfer, Page, QueueEntry,
+        QueueGroup, Session, SessionDetails, SessionInfo, StatusUpdate, IDEMPOTENCY_KEY,
+        IDEMPOTENT_REPLAYED,
+    };
 
     /// helper function that does a request to the serviceworker to insert `items`` into `table`
     async fn add_items(server: &TestServer, table: usize, items: Vec<usize>) -> TestResponse {
         server
-            .post(&format!("/{}/", table))
+            .post(&routes::table(table))
             .add_query_param("key", API_KEY)
             .json(&items)
             .await
@@ -18,15 +59,37 @@ mod tests {
     /// helper function that does a delete request for `table` on `item_position`
     async fn delete_item(server: &TestServer, table: usize, item_position: usize) -> TestResponse {
         server
-            .delete(&format!("/{}/{}/", table, item_position))
+            .delete(&routes::item_position(table, item_position))
+            .add_query_param("key", API_KEY)
+            .await
+    }
+
+    /// helper function that does a delete request for `table` on the item with `item_id`
+    async fn delete_item_by_id(server: &TestServer, table: usize, item_id: u64) -> TestResponse {
+        server
+            .delete(&routes::item(table, item_id))
+            .add_query_param("key", API_KEY)
+            .await
+    }
+
+    /// helper function that moves the item `item_id` on `table` to `status`
+    async fn set_status(
+        server: &TestServer,
+        table: usize,
+        item_id: u64,
+        status: ItemStatus,
+    ) -> TestResponse {
+        server
+            .patch(&routes::item(table, item_id))
             .add_query_param("key", API_KEY)
+            .json(&StatusUpdate { status })
             .await
     }
 
     /// helper function that does a request to the serviceworker to query items and returns it
     async fn get_items(server: &TestServer, table: usize) -> Vec<MenuItem> {
         server
-            .get(&format!("/{}/", table))
+            .get(&routes::table(table))
             .add_query_param("key", API_KEY)
             .await
             .json()
@@ -80,10 +143,10 @@ mod tests {
         let server = setup_server().await.unwrap();
         let insert1 = add_items(&server, 1, vec![1, 2, 3]).await;
         insert1.assert_status_ok();
-        assert_eq!(insert1.json::<bool>(), true);
+        assert_eq!(insert1.json::<Vec<u64>>().len(), 3);
         let insert2 = add_items(&server, 2, vec![4, 5, 6]).await;
         insert2.assert_status_ok();
-        assert_eq!(insert2.json::<bool>(), true);
+        assert_eq!(insert2.json::<Vec<u64>>().len(), 3);
         let menu_items: Vec<u64> = get_items(&server, 1)
             .await
             .iter()
@@ -98,15 +161,15 @@ mod tests {
         let server = setup_server().await.unwrap();
         let insert1 = add_items(&server, 1, vec![1, 2, 3]).await;
         insert1.assert_status_ok();
-        assert_eq!(insert1.json::<bool>(), true);
+        assert_eq!(insert1.json::<Vec<u64>>().len(), 3);
 
         let delete1 = delete_item(&server, 1, 2).await;
-        assert_eq!(delete1.json::<bool>(), true);
+        assert!(delete1.json::<bool>());
         delete1.assert_status_ok();
 
         let insert2 = add_items(&server, 2, vec![4, 5, 6]).await;
         insert2.assert_status_ok();
-        assert_eq!(insert2.json::<bool>(), true);
+        assert_eq!(insert2.json::<Vec<u64>>().len(), 3);
         let menu_items: Vec<u64> = get_items(&server, 1)
             .await
             .iter()
@@ -121,15 +184,15 @@ mod tests {
         let server = setup_server().await.unwrap();
         let insert1 = add_items(&server, 1, vec![10, 20, 30]).await;
         insert1.assert_status_ok();
-        assert_eq!(insert1.json::<bool>(), true);
+        assert_eq!(insert1.json::<Vec<u64>>().len(), 3);
 
         let delete1 = delete_item(&server, 1, 2).await;
         delete1.assert_status_ok();
-        assert_eq!(delete1.json::<bool>(), true);
+        assert!(delete1.json::<bool>());
 
         let insert2 = add_items(&server, 2, vec![4, 5, 6]).await;
         insert2.assert_status_ok();
-        assert_eq!(insert2.json::<bool>(), true);
+        assert_eq!(insert2.json::<Vec<u64>>().len(), 3);
         let menu_items: Vec<u64> = get_items(&server, 1)
             .await
             .iter()
@@ -144,15 +207,15 @@ mod tests {
         let server = setup_server().await.unwrap();
         let insert1 = add_items(&server, 1, vec![10, 20, 30]).await;
         insert1.assert_status_ok();
-        assert_eq!(insert1.json::<bool>(), true);
+        assert_eq!(insert1.json::<Vec<u64>>().len(), 3);
 
         let delete1 = delete_item(&server, 1, 2).await;
         delete1.assert_status_ok();
-        assert_eq!(delete1.json::<bool>(), true);
+        assert!(delete1.json::<bool>());
 
         let insert2 = add_items(&server, 2, vec![4, 5, 6]).await;
         insert2.assert_status_ok();
-        assert_eq!(insert2.json::<bool>(), true);
+        assert_eq!(insert2.json::<Vec<u64>>().len(), 3);
         let menu_items: Vec<u64> = get_items(&server, 2)
             .await
             .iter()
@@ -167,7 +230,7 @@ mod tests {
         let server = setup_server().await.unwrap();
         let insert1 = add_items(&server, 1, vec![10, 20, 30]).await;
         insert1.assert_status_ok();
-        assert_eq!(insert1.json::<bool>(), true);
+        assert_eq!(insert1.json::<Vec<u64>>().len(), 3);
 
         let get = server.get("/1/1/").add_query_param("key", API_KEY).await;
         get.assert_status_ok();
@@ -180,11 +243,11 @@ mod tests {
         let server = setup_server().await.unwrap();
         let get = server.get("/1/").await;
 
-        assert_eq!(get.status_code(), crate::StatusCode::BAD_REQUEST);
+        assert_eq!(get.status_code(), StatusCode::UNAUTHORIZED);
         let insert = server.post("/1/").json(&vec![1, 2, 3]).await;
-        assert_eq!(insert.status_code(), crate::StatusCode::BAD_REQUEST);
+        assert_eq!(insert.status_code(), StatusCode::UNAUTHORIZED);
         let delete = server.delete("/1/1/").await;
-        assert_eq!(delete.status_code(), crate::StatusCode::BAD_REQUEST);
+        assert_eq!(delete.status_code(), StatusCode::UNAUTHORIZED);
     }
 
     #[tokio::test]
@@ -193,7 +256,7 @@ mod tests {
         let server = setup_server().await.unwrap();
         //panic!("NYI");
         let get = server.get("/1/").add_query_param("key", "foo").await;
-        assert_eq!(get.status_code(), crate::StatusCode::UNAUTHORIZED);
+        assert_eq!(get.status_code(), StatusCode::UNAUTHORIZED);
         let insert = server
             .post("/1/")
             .add_query_param("key", "foo")
@@ -201,7 +264,21 @@ mod tests {
             .await;
         insert.assert_status_unauthorized();
         let delete = server.delete("/1/1/").add_query_param("key", "foo").await;
-        assert_eq!(delete.status_code(), crate::StatusCode::UNAUTHORIZED);
+        assert_eq!(delete.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    /// test that errors come with a body telling what went wrong
+    async fn error_payloads() {
+        let server = setup_server().await.unwrap();
+        let unauthorized = server.get("/1/").add_query_param("key", "foo").await;
+        assert_eq!(
+            unauthorized.json::<ErrorBody>().error,
+            ErrorKind::Unauthorized
+        );
+        let not_found = server.get("/300/").add_query_param("key", API_KEY).await;
+        not_found.assert_status_not_found();
+        assert_eq!(not_found.json::<ErrorBody>().error, ErrorKind::NotFound);
     }
 
     #[tokio::test]
@@ -287,13 +364,12 @@ mod tests {
         let item_numbers = all_items
             .json::<Vec<Table>>()
             .iter()
-            .map(|t: &Table| {
+            .flat_map(|t: &Table| {
                 t.items
                     .iter()
                     .map(|mi| mi.item_number)
                     .collect::<Vec<u64>>()
             })
-            .flatten()
             .collect::<Vec<u64>>();
 
         assert_eq!(item_numbers, vec![10, 20, 30, 12, 22, 32]);
@@ -338,21 +414,4140 @@ mod tests {
         let all_items = server
             .get("/")
             .add_query_param("key", API_KEY)
-            .add_query_param("limit", 2)
+            .add_query_param("limit", 1)
             .await;
         all_items.assert_status_ok();
 
         let item_numbers = all_items
             .json::<Vec<Table>>()
             .iter()
-            .map(|t: &Table| {
+            .flat_map(|t: &Table| {
                 t.items
                     .iter()
                     .map(|mi| mi.item_number)
                     .collect::<Vec<u64>>()
             })
-            .flatten()
             .collect::<Vec<u64>>();
         assert_eq!(item_numbers, vec![10, 20, 30]);
     }
+
+    #[tokio::test]
+    /// test that every created item gets a distinct id, also across tables
+    async fn ids_are_unique() {
+        let server = setup_server().await.unwrap();
+        let mut ids = add_items(&server, 1, vec![1, 1, 1])
+            .await
+            .json::<Vec<u64>>();
+        ids.extend(
+            add_items(&server, 2, vec![1, 1, 1])
+                .await
+                .json::<Vec<u64>>(),
+        );
+        let mut deduplicated = ids.clone();
+        deduplicated.sort();
+        deduplicated.dedup();
+        assert_eq!(ids.len(), 6);
+        assert_eq!(deduplicated.len(), 6);
+    }
+
+    #[tokio::test]
+    /// test that the returned ids match the items we query
+    async fn returned_ids_match_items() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+        let items = get_items(&server, 1).await;
+        assert_eq!(items.iter().map(|i| i.id).collect::<Vec<u64>>(), ids);
+    }
+
+    #[tokio::test]
+    /// test that ids are not reused after a deletion
+    async fn ids_are_not_reused() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        delete_item_by_id(&server, 1, ids[0])
+            .await
+            .assert_status_ok();
+        let new_ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        assert_ne!(ids, new_ids);
+    }
+
+    #[tokio::test]
+    /// test that deleting by id removes exactly that item, even if positions shifted before
+    async fn deletion_by_id() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+
+        let delete1 = delete_item_by_id(&server, 1, ids[0]).await;
+        delete1.assert_status_ok();
+        assert!(delete1.json::<bool>());
+        let delete2 = delete_item_by_id(&server, 1, ids[2]).await;
+        delete2.assert_status_ok();
+        assert!(delete2.json::<bool>());
+
+        let menu_items: Vec<u64> = get_items(&server, 1)
+            .await
+            .iter()
+            .map(|i| i.item_number)
+            .collect();
+        assert_eq!(menu_items, vec![20]);
+    }
+
+    #[tokio::test]
+    /// test that deleting the same id twice only deletes one item
+    async fn deletion_by_id_twice() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+
+        let delete1 = delete_item_by_id(&server, 1, ids[1]).await;
+        assert!(delete1.json::<bool>());
+        let delete2 = delete_item_by_id(&server, 1, ids[1]).await;
+        assert!(!delete2.json::<bool>());
+
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+    }
+
+    #[tokio::test]
+    /// test that we cannot delete an item through a table it is not on
+    async fn deletion_by_id_wrong_table() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+
+        let delete = delete_item_by_id(&server, 2, ids[1]).await;
+        delete.assert_status_ok();
+        assert!(!delete.json::<bool>());
+        assert_eq!(get_items(&server, 1).await.len(), 3);
+    }
+
+    #[tokio::test]
+    /// can we get a specific item by its id
+    async fn get_specific_item_by_id() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+
+        let get = server
+            .get(&routes::item(1, ids[2]))
+            .add_query_param("key", API_KEY)
+            .await;
+        get.assert_status_ok();
+        let items = get.json::<Vec<MenuItem>>();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, ids[2]);
+        assert_eq!(items[0].item_number, 30);
+    }
+
+    #[tokio::test]
+    /// test that deleting a position that does not exist does not remove anything
+    async fn deletion_position_out_of_range() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .assert_status_ok();
+
+        let delete = delete_item(&server, 1, 3).await;
+        delete.assert_status_ok();
+        assert!(!delete.json::<bool>());
+        assert_eq!(get_items(&server, 1).await.len(), 3);
+    }
+
+    #[tokio::test]
+    /// test if we reject the id routes with the wrong key parameter supplied
+    async fn unauthorized_wrong_key_by_id() {
+        let server = setup_server().await.unwrap();
+        let get = server.get("/1/items/1").add_query_param("key", "foo").await;
+        get.assert_status_unauthorized();
+        let delete = server
+            .delete("/1/items/1")
+            .add_query_param("key", "foo")
+            .await;
+        delete.assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    /// test the store without going through http
+    async fn store_add_and_remove() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        let items = store.add_items(3, vec![10, 20], None).await.unwrap();
+        assert_eq!(
+            items.iter().map(|i| i.item_number).collect::<Vec<u64>>(),
+            vec![10, 20]
+        );
+        assert!(store.remove_item(3, items[0].id).await.unwrap());
+        assert!(!store.remove_item(3, items[0].id).await.unwrap());
+        assert_eq!(
+            store.item(3, items[1].id).await.unwrap(),
+            Some(items[1].clone())
+        );
+        assert_eq!(store.table(3).await.unwrap().items, vec![items[1].clone()]);
+    }
+
+    #[tokio::test]
+    /// test that the store rejects tables that do not exist
+    async fn store_unknown_table() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        assert!(matches!(
+            store.add_items(300, vec![1], None).await,
+            Err(StoreError::UnknownTable)
+        ));
+        assert!(matches!(
+            store.remove_item(300, 1).await,
+            Err(StoreError::UnknownTable)
+        ));
+        assert!(matches!(
+            store.table(300).await,
+            Err(StoreError::UnknownTable)
+        ));
+    }
+
+    #[tokio::test]
+    /// test that snapshot_all returns every table in order
+    async fn store_snapshot_all() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        store.add_items(2, vec![1], None).await.unwrap();
+        let snapshot = store.snapshot_all().await;
+        assert_eq!(snapshot.version, 1);
+        let tables = snapshot.tables;
+        assert_eq!(tables.len(), crate::types::AMOUNT_OF_TABLES);
+        assert!(tables.iter().enumerate().all(|(i, t)| t.table_number == i));
+        assert_eq!(tables[2].items.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    /// test that a snapshot never sees a change halfway, its version always matches the tables
+    async fn snapshot_is_atomic() {
+        let store = Arc::new(MemoryStore::new().with_menu(Menu::for_tests()));
+        let writer = {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for i in 0..500 {
+                    store.add_items(i % 7, vec![1], None).await.unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let snapshot = store.snapshot_all().await;
+            let items = snapshot.tables.iter().map(|t| t.items.len()).sum::<usize>();
+            assert_eq!(snapshot.version, items as u64);
+        }
+        writer.await.unwrap();
+        assert_eq!(store.snapshot_all().await.version, 500);
+    }
+
+    /// helper function that reads all tables as a snapshot
+    async fn get_snapshot(server: &TestServer) -> Snapshot {
+        let response = server
+            .get(routes::ALL_TABLES)
+            .add_query_param("key", API_KEY)
+            .add_query_param("snapshot", true)
+            .await;
+        response.assert_status_ok();
+        response.json::<Snapshot>()
+    }
+
+    #[tokio::test]
+    /// test that the snapshot version only moves with changes, so equal versions give identical answers
+    async fn snapshot_versions() {
+        let (server, clock) = setup_server_with_clock();
+        let empty = get_snapshot(&server).await;
+        assert_eq!(empty.tables, vec![]);
+
+        let ids = add_items(&server, 1, vec![10, 20]).await.json::<Vec<u64>>();
+        let first = get_snapshot(&server).await;
+        assert!(first.version > empty.version);
+        assert_eq!(first.tables[0].items.len(), 2);
+
+        clock.advance(chrono::Duration::minutes(3));
+        add_items(&server, 1, vec![99999])
+            .await
+            .assert_status_bad_request();
+        set_status(&server, 1, ids[0], ItemStatus::Served)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        assert_eq!(get_snapshot(&server).await, first);
+
+        set_status(&server, 1, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+        let second = get_snapshot(&server).await;
+        assert!(second.version > first.version);
+        assert_ne!(second.tables, first.tables);
+    }
+
+    #[tokio::test]
+    /// test that new items start out as ordered
+    async fn new_items_are_ordered() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![10]).await.assert_status_ok();
+        let item = get_items(&server, 1).await.remove(0);
+        assert_eq!(item.status, ItemStatus::Ordered);
+        assert_eq!(item.status_history.len(), 1);
+        assert_eq!(item.status_history[0].status, ItemStatus::Ordered);
+    }
+
+    #[tokio::test]
+    /// test that an item can go through its whole lifecycle and every step is recorded
+    async fn status_lifecycle() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        for status in [ItemStatus::Cooking, ItemStatus::Ready, ItemStatus::Served] {
+            let response = set_status(&server, 1, ids[0], status).await;
+            response.assert_status_ok();
+            assert_eq!(response.json::<MenuItem>().status, status);
+        }
+        let item = get_items(&server, 1).await.remove(0);
+        assert_eq!(
+            item.status_history
+                .iter()
+                .map(|c| c.status)
+                .collect::<Vec<ItemStatus>>(),
+            vec![
+                ItemStatus::Ordered,
+                ItemStatus::Cooking,
+                ItemStatus::Ready,
+                ItemStatus::Served
+            ]
+        );
+        assert!(item.status_history.windows(2).all(|w| w[0].at <= w[1].at));
+    }
+
+    #[tokio::test]
+    /// test that illegal transitions are rejected and leave the item alone
+    async fn illegal_status_transition() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        let skip = set_status(&server, 1, ids[0], ItemStatus::Served).await;
+        assert_eq!(skip.status_code(), StatusCode::CONFLICT);
+        assert_eq!(skip.json::<ErrorBody>().error, ErrorKind::Conflict);
+
+        set_status(&server, 1, ids[0], ItemStatus::Cancelled)
+            .await
+            .assert_status_ok();
+        let back = set_status(&server, 1, ids[0], ItemStatus::Cooking).await;
+        assert_eq!(back.status_code(), StatusCode::CONFLICT);
+        assert_eq!(get_items(&server, 1).await[0].status, ItemStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    /// test that a served item cannot go back to the kitchen
+    async fn served_cannot_go_back_to_cooking() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        for status in [ItemStatus::Cooking, ItemStatus::Ready, ItemStatus::Served] {
+            set_status(&server, 1, ids[0], status)
+                .await
+                .assert_status_ok();
+        }
+        let response = set_status(&server, 1, ids[0], ItemStatus::Cooking).await;
+        assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    /// test that changing the status of an item that does not exist is a 404
+    async fn status_of_unknown_item() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        set_status(&server, 2, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_not_found();
+        set_status(&server, 300, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    /// test that we can filter the items of a table by status
+    async fn filter_table_by_status() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30])
+            .await
+            .json::<Vec<u64>>();
+        set_status(&server, 1, ids[1], ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+        let cooking = server
+            .get(&routes::table(1))
+            .add_query_param("key", API_KEY)
+            .add_query_param("status", "cooking")
+            .await
+            .json::<Vec<MenuItem>>();
+        assert_eq!(
+            cooking.iter().map(|i| i.id).collect::<Vec<u64>>(),
+            vec![ids[1]]
+        );
+        let ordered = server
+            .get(&routes::table(1))
+            .add_query_param("key", API_KEY)
+            .add_query_param("status", "ordered")
+            .await
+            .json::<Vec<MenuItem>>();
+        assert_eq!(ordered.len(), 2);
+    }
+
+    #[tokio::test]
+    /// test that filtering all tables by status leaves out tables without matching items
+    async fn filter_all_by_status() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20]).await.json::<Vec<u64>>();
+        add_items(&server, 2, vec![30]).await.assert_status_ok();
+        set_status(&server, 1, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+        set_status(&server, 1, ids[0], ItemStatus::Ready)
+            .await
+            .assert_status_ok();
+        let ready = server
+            .get("/")
+            .add_query_param("key", API_KEY)
+            .add_query_param("status", "ready")
+            .await
+            .json::<Vec<Table>>();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].table_number, 1);
+        assert_eq!(
+            ready[0].items.iter().map(|i| i.id).collect::<Vec<u64>>(),
+            vec![ids[0]]
+        );
+    }
+
+    #[tokio::test]
+    /// test that an unknown status in the filter is rejected
+    async fn filter_unknown_status() {
+        let server = setup_server().await.unwrap();
+        server
+            .get("/1/")
+            .add_query_param("key", API_KEY)
+            .add_query_param("status", "burnt")
+            .await
+            .assert_status_bad_request();
+    }
+
+    /// helper function that starts the testserver with a clock the test moves by hand
+    fn setup_server_with_clock() -> (TestServer, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(
+            "2024-07-01T12:00:00Z".parse().expect("a valid timestamp"),
+        ));
+        let store = MemoryStore::new()
+            .with_menu(Menu::for_tests())
+            .with_credentials(Credentials::for_tests())
+            .with_clock(clock.clone());
+        let server = TestServer::new(router_with_state(Arc::new(store))).unwrap();
+        (server, clock)
+    }
+
+    #[tokio::test]
+    /// test that an item knows when it was ordered and when it will be ready
+    async fn ready_at_follows_duration() {
+        let (server, clock) = setup_server_with_clock();
+        add_items(&server, 1, vec![10]).await.assert_status_ok();
+        let item = get_items(&server, 1).await.remove(0);
+        assert_eq!(item.ordered_at, clock.now());
+        assert_eq!(
+            item.ready_at - item.ordered_at,
+            chrono::Duration::minutes(item.duration_in_minutes as i64)
+        );
+        assert_eq!(item.remaining_minutes, item.duration_in_minutes);
+    }
+
+    #[tokio::test]
+    /// test that the remaining time counts down with the clock, rounding up partial minutes, and stops at zero
+    async fn remaining_minutes_count_down() {
+        let (server, clock) = setup_server_with_clock();
+        add_items(&server, 1, vec![10]).await.assert_status_ok();
+        let duration = get_items(&server, 1).await[0].duration_in_minutes;
+
+        clock.advance(chrono::Duration::seconds(30));
+        assert_eq!(get_items(&server, 1).await[0].remaining_minutes, duration);
+
+        clock.advance(chrono::Duration::seconds(30));
+        assert_eq!(
+            get_items(&server, 1).await[0].remaining_minutes,
+            duration - 1
+        );
+
+        clock.advance(chrono::Duration::minutes(duration as i64));
+        assert_eq!(get_items(&server, 1).await[0].remaining_minutes, 0);
+    }
+
+    #[tokio::test]
+    /// test that a ready item has no time remaining even if it was faster than estimated
+    async fn ready_items_have_nothing_remaining() {
+        let (server, clock) = setup_server_with_clock();
+        let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+        clock.advance(chrono::Duration::minutes(1));
+        let cooking = set_status(&server, 1, ids[0], ItemStatus::Cooking)
+            .await
+            .json::<MenuItem>();
+        assert!(cooking.remaining_minutes > 0);
+        let ready = set_status(&server, 1, ids[0], ItemStatus::Ready)
+            .await
+            .json::<MenuItem>();
+        assert_eq!(ready.remaining_minutes, 0);
+        assert_eq!(get_items(&server, 1).await[0].remaining_minutes, 0);
+    }
+
+    /// a menu entry for `item_number` that is available and cooks in exactly `minutes`
+    fn menu_entry(item_number: u64, minutes: u64) -> MenuEntry {
+        MenuEntry {
+            item_number,
+            name: format!("Dish {}", item_number),
+            category: "Sides".to_string(),
+            price: 500,
+            min_cook_minutes: minutes,
+            max_cook_minutes: minutes,
+            cook_time: CookTimeModel::Uniform,
+            station: None,
+            priority: 0,
+            available: true,
+        }
+    }
+
+    /// helper function that puts `entry` on the menu of `server`
+    async fn add_menu_entry(server: &TestServer, entry: &MenuEntry) -> TestResponse {
+        server
+            .post(&routes::menu())
+            .add_query_param("key", API_KEY)
+            .json(entry)
+            .await
+    }
+
+    #[tokio::test]
+    /// test that ordering something that is not on the menu fails, names the offenders and adds nothing
+    async fn unknown_item_numbers_are_rejected() {
+        let server = setup_server().await.unwrap();
+        let response = add_items(&server, 1, vec![1, 9999, 2, 4242]).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body = response.json::<ErrorBody>();
+        assert_eq!(body.error, ErrorKind::BadRequest);
+        assert!(body.message.contains("not on the menu: 4242, 9999"));
+        assert!(get_items(&server, 1).await.is_empty());
+    }
+
+    #[tokio::test]
+    /// test that a sold out dish cannot be ordered until it is available again
+    async fn unavailable_items_are_rejected() {
+        let server = setup_server().await.unwrap();
+        let mut entry = server
+            .get(&routes::menu_entry(3))
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<MenuEntry>();
+        entry.available = false;
+        server
+            .put(&routes::menu_entry(3))
+            .add_query_param("key", API_KEY)
+            .json(&entry)
+            .await
+            .assert_status_ok();
+
+        let response = add_items(&server, 1, vec![3, 1000]).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let message = response.json::<ErrorBody>().message;
+        assert!(message.contains("not on the menu: 1000"));
+        assert!(message.contains("not available: 3"));
+
+        entry.available = true;
+        server
+            .put(&routes::menu_entry(3))
+            .add_query_param("key", API_KEY)
+            .json(&entry)
+            .await
+            .assert_status_ok();
+        add_items(&server, 1, vec![3]).await.assert_status_ok();
+    }
+
+    #[tokio::test]
+    /// test adding, reading, replacing and removing menu entries
+    async fn menu_crud() {
+        let server = setup_server().await.unwrap();
+        let entry = menu_entry(100, 7);
+        let response = server
+            .post(&routes::menu())
+            .add_query_param("key", API_KEY)
+            .json(&entry)
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<MenuEntry>(), entry);
+        server
+            .post(&routes::menu())
+            .add_query_param("key", API_KEY)
+            .json(&entry)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        let menu = server
+            .get(&routes::menu())
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<Vec<MenuEntry>>();
+        assert_eq!(menu.len(), 41);
+        assert_eq!(menu.last(), Some(&entry));
+
+        let renamed = MenuEntry {
+            name: "Edamame".to_string(),
+            ..entry.clone()
+        };
+        server
+            .put(&routes::menu_entry(100))
+            .add_query_param("key", API_KEY)
+            .json(&renamed)
+            .await
+            .assert_status_ok();
+        assert_eq!(
+            server
+                .get(&routes::menu_entry(100))
+                .add_query_param("key", API_KEY)
+                .await
+                .json::<MenuEntry>(),
+            renamed
+        );
+
+        let response = server
+            .delete(&routes::menu_entry(100))
+            .add_query_param("key", API_KEY)
+            .await;
+        assert!(response.json::<bool>());
+        server
+            .get(&routes::menu_entry(100))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_not_found();
+        server
+            .put(&routes::menu_entry(100))
+            .add_query_param("key", API_KEY)
+            .json(&renamed)
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    /// test that entries that make no sense are refused
+    async fn invalid_menu_entries() {
+        let server = setup_server().await.unwrap();
+        let backwards = MenuEntry {
+            min_cook_minutes: 10,
+            max_cook_minutes: 5,
+            ..menu_entry(100, 5)
+        };
+        let nameless = MenuEntry {
+            name: " ".to_string(),
+            ..menu_entry(101, 5)
+        };
+        for entry in [backwards, nameless] {
+            server
+                .post(&routes::menu())
+                .add_query_param("key", API_KEY)
+                .json(&entry)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        // the path decides which entry is replaced
+        server
+            .put(&routes::menu_entry(1))
+            .add_query_param("key", API_KEY)
+            .json(&menu_entry(2, 5))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .get(&routes::menu())
+            .add_query_param("key", "wrong")
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    /// test that an item cooks within the time range of its menu entry
+    async fn cook_time_comes_from_menu() {
+        let server = setup_server().await.unwrap();
+        server
+            .post(&routes::menu())
+            .add_query_param("key", API_KEY)
+            .json(&menu_entry(100, 7))
+            .await
+            .assert_status_ok();
+        add_items(&server, 1, vec![100, 100])
+            .await
+            .assert_status_ok();
+        assert!(get_items(&server, 1)
+            .await
+            .iter()
+            .all(|item| item.duration_in_minutes == 7));
+    }
+
+    #[tokio::test]
+    /// test loading menus in both formats and that changes are written back to the file
+    async fn menu_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_path = dir.path().join("menu.toml");
+        std::fs::write(
+            &toml_path,
+            r#"
+            [[items]]
+            item_number = 1
+            name = "Potato Fries"
+            category = "Sides"
+            price = 450
+            min_cook_minutes = 5
+            max_cook_minutes = 8
+            "#,
+        )
+        .unwrap();
+        let json_path = dir.path().join("menu.json");
+        std::fs::write(
+            &json_path,
+            r#"{"items": [{"item_number": 1, "name": "Potato Fries", "category": "Sides",
+                "price": 450, "min_cook_minutes": 5, "max_cook_minutes": 8}]}"#,
+        )
+        .unwrap();
+
+        for path in [toml_path, json_path] {
+            let menu = Menu::load(&path).unwrap();
+            assert_eq!(menu.entries().len(), 1);
+            assert!(menu.entries()[0].available);
+            menu.insert(menu_entry(2, 3)).unwrap();
+            assert!(menu.remove(1).unwrap());
+            drop(menu);
+
+            let menu = Menu::load(&path).unwrap();
+            assert_eq!(menu.entries(), vec![menu_entry(2, 3)]);
+        }
+    }
+
+    #[test]
+    /// test that a broken menu file is refused at startup
+    fn invalid_menu_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("menu.toml");
+        let entry = r#"
+            [[items]]
+            item_number = 1
+            name = "Potato Fries"
+            category = "Sides"
+            price = 450
+            min_cook_minutes = 5
+            max_cook_minutes = 8
+        "#;
+        std::fs::write(&path, format!("{}{}", entry, entry)).unwrap();
+        let error = Menu::load(&path).err().unwrap();
+        assert!(format!("{:#}", error).contains("listed twice"));
+        assert!(Menu::load(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[tokio::test]
+    /// test that a fixed cook time is used as is
+    async fn fixed_cook_time() {
+        let server = setup_server().await.unwrap();
+        let entry = MenuEntry {
+            min_cook_minutes: 5,
+            max_cook_minutes: 10,
+            cook_time: CookTimeModel::Fixed { minutes: 7 },
+            ..menu_entry(100, 5)
+        };
+        add_menu_entry(&server, &entry).await.assert_status_ok();
+        add_items(&server, 1, vec![100, 100, 100])
+            .await
+            .assert_status_ok();
+        assert!(get_items(&server, 1)
+            .await
+            .iter()
+            .all(|item| item.duration_in_minutes == 7));
+    }
+
+    #[tokio::test]
+    /// test that a normal distribution is kept within the range of the entry
+    async fn normal_cook_time_is_clamped() {
+        let server = setup_server().await.unwrap();
+        for (item_number, mean) in [(100, 8.0), (101, 100.0), (102, -3.0)] {
+            let entry = MenuEntry {
+                min_cook_minutes: 5,
+                max_cook_minutes: 15,
+                cook_time: CookTimeModel::Normal { mean, std_dev: 0.0 },
+                ..menu_entry(item_number, 5)
+            };
+            add_menu_entry(&server, &entry).await.assert_status_ok();
+        }
+        add_items(&server, 1, vec![100, 101, 102])
+            .await
+            .assert_status_ok();
+        assert_eq!(
+            get_items(&server, 1)
+                .await
+                .iter()
+                .map(|item| item.duration_in_minutes)
+                .collect::<Vec<u64>>(),
+            vec![8, 15, 5]
+        );
+    }
+
+    #[tokio::test]
+    /// test that two stores seeded alike hand out the same cook times
+    async fn seeded_cook_times_repeat() {
+        let mut durations = vec![];
+        for _ in 0..2 {
+            let store = MemoryStore::new()
+                .with_menu(Menu::for_tests())
+                .with_seed(42);
+            let items = store.add_items(1, (1..=20).collect(), None).await.unwrap();
+            durations.push(
+                items
+                    .iter()
+                    .map(|item| item.duration_in_minutes)
+                    .collect::<Vec<u64>>(),
+            );
+        }
+        assert_eq!(durations[0], durations[1]);
+        assert!(durations[0].iter().all(|d| (5..=15).contains(d)));
+    }
+
+    #[tokio::test]
+    /// test that a learned model starts in the middle of the range and then follows how long cooking actually took
+    async fn learned_cook_time() {
+        let (server, clock) = setup_server_with_clock();
+        let entry = MenuEntry {
+            min_cook_minutes: 4,
+            max_cook_minutes: 20,
+            cook_time: CookTimeModel::Learned {
+                samples: 0,
+                mean: 0.0,
+            },
+            ..menu_entry(100, 4)
+        };
+        add_menu_entry(&server, &entry).await.assert_status_ok();
+        let ids = add_items(&server, 1, vec![100]).await.json::<Vec<u64>>();
+        assert_eq!(get_items(&server, 1).await[0].duration_in_minutes, 12);
+
+        // waiting for the kitchen does not count, only the cooking itself
+        clock.advance(chrono::Duration::minutes(3));
+        set_status(&server, 1, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+        clock.advance(chrono::Duration::minutes(6));
+        set_status(&server, 1, ids[0], ItemStatus::Ready)
+            .await
+            .assert_status_ok();
+        let learned = server
+            .get(&routes::menu_entry(100))
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<MenuEntry>();
+        assert_eq!(
+            learned.cook_time,
+            CookTimeModel::Learned {
+                samples: 1,
+                mean: 6.0
+            }
+        );
+
+        let ids = add_items(&server, 1, vec![100]).await.json::<Vec<u64>>();
+        let item = server
+            .get(&routes::item(1, ids[0]))
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<Vec<MenuItem>>()
+            .remove(0);
+        assert_eq!(item.duration_in_minutes, 6);
+    }
+
+    #[tokio::test]
+    /// test that cook time models that do not fit their entry are refused
+    async fn invalid_cook_time_models() {
+        let server = setup_server().await.unwrap();
+        let outside = MenuEntry {
+            cook_time: CookTimeModel::Fixed { minutes: 9 },
+            ..menu_entry(100, 5)
+        };
+        let negative = MenuEntry {
+            cook_time: CookTimeModel::Normal {
+                mean: 5.0,
+                std_dev: -1.0,
+            },
+            ..menu_entry(101, 5)
+        };
+        for entry in [outside, negative] {
+            add_menu_entry(&server, &entry)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    /// helper function that puts a dish cooked at `station` with `priority` on the menu
+    async fn add_station_dish(server: &TestServer, item_number: u64, station: &str, priority: u8) {
+        let entry = MenuEntry {
+            station: Some(station.to_string()),
+            priority,
+            ..menu_entry(item_number, 5)
+        };
+        add_menu_entry(server, &entry).await.assert_status_ok();
+    }
+
+    #[tokio::test]
+    /// test that the queue holds the open items of all tables, higher priority first and then the longest waiting
+    async fn kitchen_queue_order() {
+        let (server, clock) = setup_server_with_clock();
+        add_station_dish(&server, 100, "fryer", 0).await;
+        add_station_dish(&server, 101, "bar", 1).await;
+        let first = add_items(&server, 7, vec![100]).await.json::<Vec<u64>>();
+        clock.advance(chrono::Duration::minutes(1));
+        let second = add_items(&server, 2, vec![100, 100])
+            .await
+            .json::<Vec<u64>>();
+        clock.advance(chrono::Duration::minutes(1));
+        let drink = add_items(&server, 5, vec![101]).await.json::<Vec<u64>>();
+        // the kitchen is done with these
+        set_status(&server, 2, second[1], ItemStatus::Cancelled)
+            .await
+            .assert_status_ok();
+
+        let queue = server
+            .get(&routes::kitchen_queue())
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<Vec<QueueEntry>>();
+        assert_eq!(
+            queue
+                .iter()
+                .map(|entry| (entry.table_number, entry.item.id))
+                .collect::<Vec<(usize, u64)>>(),
+            vec![(5, drink[0]), (7, first[0]), (2, second[0])]
+        );
+        assert_eq!(queue[1].name, "Dish 100");
+        assert_eq!(queue[1].station.as_deref(), Some("fryer"));
+        assert_eq!(queue[1].item.remaining_minutes, 3);
+    }
+
+    #[tokio::test]
+    /// test that a station only sees its own dishes, grouped if asked to
+    async fn kitchen_queue_station_and_groups() {
+        let server = setup_server().await.unwrap();
+        add_station_dish(&server, 100, "fryer", 0).await;
+        add_station_dish(&server, 101, "grill", 0).await;
+        add_items(&server, 1, vec![100, 101, 100])
+            .await
+            .assert_status_ok();
+        add_items(&server, 3, vec![100, 1]).await.assert_status_ok();
+
+        let fryer = server
+            .get(&routes::kitchen_queue())
+            .add_query_param("key", API_KEY)
+            .add_query_param("station", "fryer")
+            .await
+            .json::<Vec<QueueEntry>>();
+        assert_eq!(fryer.len(), 3);
+        assert!(fryer.iter().all(|entry| entry.item.item_number == 100));
+
+        let groups = server
+            .get(&routes::kitchen_queue())
+            .add_query_param("key", API_KEY)
+            .add_query_param("group", true)
+            .await
+            .json::<Vec<QueueGroup>>();
+        assert_eq!(
+            groups
+                .iter()
+                .map(|group| (group.item_number, group.count))
+                .collect::<Vec<(u64, usize)>>(),
+            vec![(100, 3), (101, 1), (1, 1)]
+        );
+        assert_eq!(
+            groups[0].item_ids,
+            fryer.iter().map(|e| e.item.id).collect::<Vec<u64>>()
+        );
+
+        server
+            .get(&routes::kitchen_queue())
+            .add_query_param("key", "wrong")
+            .await
+            .assert_status_unauthorized();
+    }
+
+    /// helper function that starts a testserver listening on a real port, needed for streaming responses
+    fn setup_http_server() -> TestServer {
+        axum_test::TestServerConfig::builder()
+            .http_transport()
+            .build_server(router())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    /// test that every change is published in order, with the station of the item and a closing table at the end
+    async fn events_follow_changes() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        store
+            .add_menu_entry(MenuEntry {
+                station: Some("fryer".to_string()),
+                ..menu_entry(100, 5)
+            })
+            .await
+            .unwrap();
+        let mut events = store.subscribe(EventFilter::default(), None);
+        let items = store.add_items(2, vec![100, 1], None).await.unwrap();
+        store
+            .set_status(2, items[0].id, ItemStatus::Cancelled)
+            .await
+            .unwrap();
+        store.remove_item(2, items[1].id).await.unwrap();
+
+        let mut received = vec![];
+        for _ in 0..5 {
+            received.push(events.next().await.unwrap());
+        }
+        assert!(received.windows(2).all(|w| w[0].id < w[1].id));
+        assert!(received.iter().all(|e| e.table_number == Some(2)));
+        assert_eq!(
+            received[0].kind,
+            EventKind::ItemAdded {
+                item: items[0].clone()
+            }
+        );
+        assert_eq!(received[0].station.as_deref(), Some("fryer"));
+        assert_eq!(received[1].station, None);
+        assert_eq!(
+            received[2].kind,
+            EventKind::StatusChanged {
+                item_id: items[0].id,
+                item_number: 100,
+                status: ItemStatus::Cancelled
+            }
+        );
+        assert_eq!(
+            received[3].kind,
+            EventKind::ItemRemoved {
+                item_id: items[1].id,
+                item_number: 1
+            }
+        );
+        assert_eq!(received[4].kind, EventKind::TableClosed);
+    }
+
+    #[tokio::test]
+    /// test that subscribers only get the events of their table or station
+    async fn event_filters() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        store
+            .add_menu_entry(MenuEntry {
+                station: Some("grill".to_string()),
+                ..menu_entry(100, 5)
+            })
+            .await
+            .unwrap();
+        let mut table = store.subscribe(
+            EventFilter {
+                table_number: Some(4),
+                station: None,
+            },
+            None,
+        );
+        let mut grill = store.subscribe(
+            EventFilter {
+                table_number: None,
+                station: Some("grill".to_string()),
+            },
+            None,
+        );
+        store.add_items(3, vec![1, 100], None).await.unwrap();
+        store.add_items(4, vec![2], None).await.unwrap();
+
+        let event = table.next().await.unwrap();
+        assert_eq!(event.table_number, Some(4));
+        let event = grill.next().await.unwrap();
+        assert!(matches!(event.kind, EventKind::ItemAdded { item } if item.item_number == 100));
+    }
+
+    #[tokio::test]
+    /// test resuming after the last seen event, and resyncing when it is not known
+    async fn events_resume() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        let mut events = store.subscribe(EventFilter::default(), None);
+        store.add_items(1, vec![1, 2, 3], None).await.unwrap();
+        let first = events.next().await.unwrap();
+
+        let mut resumed = store.subscribe(EventFilter::default(), Some(first.id));
+        let second = resumed.next().await.unwrap();
+        assert_eq!(second.id, first.id + 1);
+        assert_eq!(resumed.next().await.unwrap().id, first.id + 2);
+
+        let mut unknown = store.subscribe(EventFilter::default(), Some(1));
+        let resync = unknown.next().await.unwrap();
+        assert_eq!(resync.kind, EventKind::Resync);
+        assert_eq!(resync.id, first.id + 2);
+    }
+
+    #[tokio::test]
+    /// test that a subscriber falling too far behind is told to resync and then gets the newest events
+    async fn slow_subscriber_resyncs() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        let mut events = store.subscribe(EventFilter::default(), None);
+        for _ in 0..1100 {
+            store.add_items(1, vec![1], None).await.unwrap();
+        }
+        let resync = events.next().await.unwrap();
+        assert_eq!(resync.kind, EventKind::Resync);
+        let items = store.add_items(1, vec![2], None).await.unwrap();
+        let event = events.next().await.unwrap();
+        assert_eq!(event.id, resync.id + 1);
+        assert_eq!(
+            event.kind,
+            EventKind::ItemAdded {
+                item: items[0].clone()
+            }
+        );
+    }
+
+    /// helper function that reads from `stream` until what was read contains `needle`
+    async fn read_until(stream: &mut tokio::net::TcpStream, needle: &str) -> String {
+        use tokio::io::AsyncReadExt;
+        let mut read = String::new();
+        let mut buffer = [0; 4096];
+        while !read.contains(needle) {
+            let n =
+                tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buffer))
+                    .await
+                    .expect("the event arrives in time")
+                    .unwrap();
+            assert!(n > 0, "the stream ended early: {}", read);
+            read.push_str(&String::from_utf8_lossy(&buffer[..n]));
+        }
+        read
+    }
+
+    #[tokio::test]
+    /// test the server-sent event stream, including resuming with the `Last-Event-ID` header
+    async fn sse_stream() {
+        use tokio::io::AsyncWriteExt;
+        let server = setup_http_server();
+        let address = server.server_address().unwrap();
+        let host = format!(
+            "{}:{}",
+            address.host_str().unwrap(),
+            address.port().unwrap()
+        );
+        let request = |last_event_id: Option<u64>| {
+            format!(
+                "GET {}?key={}&table=1 HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+                routes::EVENTS,
+                API_KEY,
+                host,
+                last_event_id
+                    .map(|id| format!("Last-Event-ID: {}\r\n", id))
+                    .unwrap_or_default()
+            )
+        };
+
+        let mut stream = tokio::net::TcpStream::connect(&host).await.unwrap();
+        stream.write_all(request(None).as_bytes()).await.unwrap();
+        read_until(&mut stream, "text/event-stream").await;
+        let ids = add_items(&server, 1, vec![10, 20]).await.json::<Vec<u64>>();
+        let read = read_until(&mut stream, &format!("\"id\":{},", ids[1])).await;
+        assert!(read.contains("\"type\":\"item_added\""));
+        let first_id = read
+            .lines()
+            .find_map(|line| line.strip_prefix("id: "))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        drop(stream);
+
+        let mut stream = tokio::net::TcpStream::connect(&host).await.unwrap();
+        stream
+            .write_all(request(Some(first_id)).as_bytes())
+            .await
+            .unwrap();
+        let read = read_until(&mut stream, &format!("\"id\":{},", ids[1])).await;
+        assert!(!read.contains(&format!("\"id\":{},", ids[0])));
+    }
+
+    #[tokio::test]
+    /// test the websocket event stream
+    async fn websocket_stream() {
+        let server = setup_http_server();
+        server
+            .get_websocket(routes::EVENTS_WS)
+            .add_query_param("key", "wrong")
+            .await
+            .assert_status_unauthorized();
+        let mut socket = server
+            .get_websocket(routes::EVENTS_WS)
+            .add_query_param("key", API_KEY)
+            .add_query_param("table", 2)
+            .await
+            .into_websocket()
+            .await;
+        add_items(&server, 1, vec![10]).await.assert_status_ok();
+        let ids = add_items(&server, 2, vec![20]).await.json::<Vec<u64>>();
+        set_status(&server, 2, ids[0], ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+
+        let added = socket.receive_json::<Event>().await;
+        assert!(matches!(added.kind, EventKind::ItemAdded { item } if item.id == ids[0]));
+        let changed = socket.receive_json::<Event>().await;
+        assert_eq!(
+            changed.kind,
+            EventKind::StatusChanged {
+                item_id: ids[0],
+                item_number: 20,
+                status: ItemStatus::Cooking
+            }
+        );
+    }
+
+    /// helper function that gets `path` with the query `params` added to the api key
+    async fn get_with(server: &TestServer, path: &str, params: &[(&str, &str)]) -> TestResponse {
+        let mut request = server.get(path).add_query_param("key", API_KEY);
+        for (key, value) in params {
+            request = request.add_query_param(key, value);
+        }
+        request.await
+    }
+
+    /// helper function that gets a page of `path`
+    async fn get_page<T: serde::de::DeserializeOwned>(
+        server: &TestServer,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Page<T> {
+        let mut params = params.to_vec();
+        params.push(("paged", "true"));
+        let response = get_with(server, path, &params).await;
+        response.assert_status_ok();
+        response.json()
+    }
+
+    #[tokio::test]
+    /// test that the limit of a page counts the tables returned, not the empty ones skipped
+    async fn paged_tables() {
+        let server = setup_server().await.unwrap();
+        for table in [1, 5, 9] {
+            add_items(&server, table, vec![10]).await.assert_status_ok();
+        }
+        let unpaged = get_with(&server, routes::ALL_TABLES, &[("limit", "5")]).await;
+        assert_eq!(unpaged.json::<Vec<Table>>().len(), 3);
+
+        let first = get_page::<Table>(&server, routes::ALL_TABLES, &[("limit", "2")]).await;
+        assert_eq!(
+            first
+                .items
+                .iter()
+                .map(|t| t.table_number)
+                .collect::<Vec<usize>>(),
+            vec![1, 5]
+        );
+        assert_eq!(first.total, 3);
+        let cursor = first.next_cursor.unwrap();
+        let second = get_page::<Table>(
+            &server,
+            routes::ALL_TABLES,
+            &[("limit", "2"), ("cursor", &cursor)],
+        )
+        .await;
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.items[0].table_number, 9);
+        assert_eq!(second.next_cursor, None);
+    }
+
+    #[tokio::test]
+    /// test that the limit without paging counts the tables returned, not the empty ones before them
+    async fn limit_skips_empty_tables() {
+        let server = setup_server().await.unwrap();
+        for table in 7..13 {
+            add_items(&server, table, vec![10]).await.assert_status_ok();
+        }
+        for params in [
+            &[("limit", "5")][..],
+            &[("limit", "5"), ("snapshot", "true")],
+        ] {
+            let response = get_with(&server, routes::ALL_TABLES, params).await;
+            response.assert_status_ok();
+            let tables = if params.len() == 2 {
+                response.json::<Snapshot>().tables
+            } else {
+                response.json::<Vec<Table>>()
+            };
+            assert_eq!(
+                tables
+                    .iter()
+                    .map(|t| t.table_number)
+                    .collect::<Vec<usize>>(),
+                vec![7, 8, 9, 10, 11]
+            );
+        }
+    }
+
+    #[tokio::test]
+    /// test that a cursor keeps its place when earlier items are removed, while an offset shifts
+    async fn cursor_survives_removal() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![10, 20, 30, 40])
+            .await
+            .json::<Vec<u64>>();
+        let path = routes::table(1);
+        let first = get_page::<MenuItem>(&server, &path, &[("limit", "2")]).await;
+        assert_eq!(
+            first.items.iter().map(|i| i.id).collect::<Vec<u64>>(),
+            ids[..2]
+        );
+        delete_item_by_id(&server, 1, ids[0])
+            .await
+            .assert_status_ok();
+
+        let cursor = first.next_cursor.unwrap();
+        let next = get_page::<MenuItem>(&server, &path, &[("cursor", &cursor)]).await;
+        assert_eq!(
+            next.items.iter().map(|i| i.id).collect::<Vec<u64>>(),
+            ids[2..]
+        );
+        assert_eq!(next.total, 3);
+        assert_eq!(next.next_cursor, None);
+
+        let offset = get_page::<MenuItem>(&server, &path, &[("offset", "2"), ("limit", "2")]).await;
+        assert_eq!(
+            offset.items.iter().map(|i| i.id).collect::<Vec<u64>>(),
+            ids[3..]
+        );
+    }
+
+    #[tokio::test]
+    /// test sorting tables and items by order time, remaining cook time and item number
+    async fn paged_sorting() {
+        let (server, clock) = setup_server_with_clock();
+        for (item_number, minutes) in [(100, 20), (101, 5), (102, 10)] {
+            add_menu_entry(&server, &menu_entry(item_number, minutes))
+                .await
+                .assert_status_ok();
+        }
+        add_items(&server, 4, vec![102]).await.assert_status_ok();
+        clock.advance(chrono::Duration::minutes(1));
+        add_items(&server, 1, vec![100]).await.assert_status_ok();
+        clock.advance(chrono::Duration::minutes(1));
+        add_items(&server, 1, vec![101]).await.assert_status_ok();
+
+        let tables = |page: &Page<Table>| {
+            page.items
+                .iter()
+                .map(|t| t.table_number)
+                .collect::<Vec<usize>>()
+        };
+        let by_number = get_page(&server, routes::ALL_TABLES, &[]).await;
+        assert_eq!(tables(&by_number), vec![1, 4]);
+        let by_time = get_page(&server, routes::ALL_TABLES, &[("sort", "ordered_at")]).await;
+        assert_eq!(tables(&by_time), vec![4, 1]);
+        let by_remaining =
+            get_page::<Table>(&server, routes::ALL_TABLES, &[("sort", "remaining")]).await;
+        assert_eq!(
+            by_remaining.items[0]
+                .items
+                .iter()
+                .map(|i| i.item_number)
+                .collect::<Vec<u64>>(),
+            vec![101, 100]
+        );
+
+        let items = |sort: &'static str| {
+            let server = &server;
+            async move {
+                get_page::<MenuItem>(server, &routes::table(1), &[("sort", sort)])
+                    .await
+                    .items
+                    .iter()
+                    .map(|i| i.item_number)
+                    .collect::<Vec<u64>>()
+            }
+        };
+        assert_eq!(items("ordered_at").await, vec![100, 101]);
+        assert_eq!(items("remaining").await, vec![101, 100]);
+        assert_eq!(items("item_number").await, vec![100, 101]);
+    }
+
+    #[tokio::test]
+    /// test that broken cursors and paging parameters without a page are refused
+    async fn paging_errors() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![10, 20]).await.assert_status_ok();
+        let path = routes::table(1);
+        let cursor = get_page::<MenuItem>(&server, &path, &[("limit", "1")])
+            .await
+            .next_cursor
+            .unwrap();
+
+        let refused = [
+            vec![("paged", "true"), ("cursor", "zz")],
+            vec![
+                ("paged", "true"),
+                ("cursor", "4e6f"),
+                ("sort", "item_number"),
+            ],
+            vec![
+                ("paged", "true"),
+                ("cursor", &cursor),
+                ("sort", "item_number"),
+            ],
+            vec![("cursor", &cursor)],
+            vec![("offset", "1")],
+        ];
+        for params in refused {
+            get_with(&server, &path, &params)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        get_with(
+            &server,
+            routes::ALL_TABLES,
+            &[("paged", "true"), ("snapshot", "true")],
+        )
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    /// helper function that adds `items` to `table` using `key`
+    async fn add_items_as(
+        server: &TestServer,
+        key: &str,
+        table: usize,
+        items: Vec<u64>,
+    ) -> TestResponse {
+        server
+            .post(&routes::table(table))
+            .add_query_param("key", key)
+            .json(&items)
+            .await
+    }
+
+    /// helper function that creates a key with `role` as admin
+    async fn create_key(server: &TestServer, name: &str, role: Role) -> IssuedKey {
+        let response = server
+            .post(&routes::keys())
+            .add_query_param("key", API_KEY)
+            .json(&NewKey {
+                name: name.to_string(),
+                role,
+            })
+            .await;
+        response.assert_status_ok();
+        response.json()
+    }
+
+    #[tokio::test]
+    /// test that every role may do exactly what it is meant to
+    async fn role_permissions() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items_as(&server, "waiter", 1, vec![10])
+            .await
+            .json::<Vec<u64>>();
+        let refused = add_items_as(&server, "kitchen", 1, vec![10]).await;
+        refused.assert_status(StatusCode::FORBIDDEN);
+        assert_eq!(refused.json::<ErrorBody>().error, ErrorKind::Forbidden);
+        server
+            .patch(&routes::item(1, ids[0]))
+            .add_query_param("key", "kitchen")
+            .json(&StatusUpdate {
+                status: ItemStatus::Cooking,
+            })
+            .await
+            .assert_status_ok();
+        server
+            .get(routes::KITCHEN_QUEUE)
+            .add_query_param("key", "kitchen")
+            .await
+            .assert_status_ok();
+
+        for (key, status) in [
+            ("waiter", StatusCode::FORBIDDEN),
+            ("kitchen", StatusCode::FORBIDDEN),
+            ("manager", StatusCode::OK),
+        ] {
+            server
+                .put(&routes::menu_entry(3))
+                .add_query_param("key", key)
+                .json(&MenuEntry {
+                    available: false,
+                    ..Menu::for_tests().get(3).unwrap()
+                })
+                .await
+                .assert_status(status);
+        }
+        for (key, status) in [
+            ("manager", StatusCode::FORBIDDEN),
+            (API_KEY, StatusCode::OK),
+        ] {
+            server
+                .get(&routes::keys())
+                .add_query_param("key", key)
+                .await
+                .assert_status(status);
+        }
+    }
+
+    #[tokio::test]
+    /// test creating, rotating and revoking a key, and that a key works exactly as long as it should
+    async fn key_lifecycle() {
+        let server = setup_server().await.unwrap();
+        let issued = create_key(&server, "tablet 3", Role::Waiter).await;
+        assert_eq!(issued.info.role, Role::Waiter);
+        add_items_as(&server, &issued.key, 1, vec![10])
+            .await
+            .assert_status_ok();
+        let listed = server
+            .get(&routes::keys())
+            .add_query_param("key", API_KEY)
+            .await;
+        assert!(listed.json::<Vec<KeyInfo>>().contains(&issued.info));
+        assert!(!listed.text().contains(&issued.key));
+
+        let rotated = server
+            .post(&routes::key_rotate(issued.info.id))
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<IssuedKey>();
+        assert_eq!(rotated.info, issued.info);
+        add_items_as(&server, &issued.key, 1, vec![10])
+            .await
+            .assert_status_unauthorized();
+        add_items_as(&server, &rotated.key, 1, vec![10])
+            .await
+            .assert_status_ok();
+
+        let revoke = |key_id: u64| {
+            server
+                .delete(&routes::key(key_id))
+                .add_query_param("key", API_KEY)
+        };
+        assert!(revoke(issued.info.id).await.json::<bool>());
+        assert!(!revoke(issued.info.id).await.json::<bool>());
+        add_items_as(&server, &rotated.key, 1, vec![10])
+            .await
+            .assert_status_unauthorized();
+        server
+            .post(&routes::key_rotate(issued.info.id))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    /// test that the last admin key cannot be revoked, so somebody can always manage keys
+    async fn last_admin_key_stays() {
+        let server = setup_server().await.unwrap();
+        let admin = server
+            .get(&routes::keys())
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<Vec<KeyInfo>>()
+            .into_iter()
+            .find(|key| key.role == Role::Admin)
+            .unwrap();
+        server
+            .delete(&routes::key(admin.id))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        let second = create_key(&server, "owner", Role::Admin).await;
+        server
+            .delete(&routes::key(admin.id))
+            .add_query_param("key", &second.key)
+            .await
+            .assert_status_ok();
+        server
+            .get(&routes::keys())
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    /// test that a missing keys file is bootstrapped with an admin key and changes are saved without secrets
+    async fn keys_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys.toml");
+        let credentials = Credentials::load_or_bootstrap(&path, "first").unwrap();
+        assert_eq!(credentials.authenticate("first").unwrap().role, Role::Admin);
+        let issued = credentials
+            .create(NewKey {
+                name: "grill".to_string(),
+                role: Role::Kitchen,
+            })
+            .unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&issued.key));
+
+        let reloaded = Credentials::load_or_bootstrap(&path, "ignored").unwrap();
+        assert_eq!(
+            reloaded.authenticate(&issued.key),
+            Some(issued.info.clone())
+        );
+        assert!(reloaded.authenticate("first").is_some());
+        assert!(reloaded.authenticate("ignored").is_none());
+
+        // the id of a revoked key is not handed out again, neither before nor after a restart
+        assert!(reloaded.revoke(issued.info.id).unwrap());
+        let new_key = |name: &str| NewKey {
+            name: name.to_string(),
+            role: Role::Waiter,
+        };
+        let next = reloaded.create(new_key("bar")).unwrap();
+        assert_eq!(next.info.id, issued.info.id + 1);
+        assert!(reloaded.revoke(next.info.id).unwrap());
+        let reloaded = Credentials::load_or_bootstrap(&path, "ignored").unwrap();
+        let next = reloaded.create(new_key("terrace")).unwrap();
+        assert_eq!(next.info.id, issued.info.id + 2);
+    }
+
+    /// helper function that moves `item_ids` from `table` to `to`, all of them without ids
+    async fn transfer(
+        server: &TestServer,
+        table: usize,
+        to: usize,
+        item_ids: Option<Vec<u64>>,
+    ) -> TestResponse {
+        server
+            .post(&routes::table_transfer(table))
+            .add_query_param("key", API_KEY)
+            .json(&ItemTransfer { to, item_ids })
+            .await
+    }
+
+    #[tokio::test]
+    /// test that moved items keep their ids, times and cook times and that a failed transfer moves nothing
+    async fn transfer_items_between_tables() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 2, vec![4]).await.assert_status_ok();
+        add_items(&server, 1, vec![1, 2, 3])
+            .await
+            .assert_status_ok();
+        let before = get_items(&server, 1).await;
+        let session_id = get_items(&server, 2).await[0].session_id;
+        assert_ne!(session_id, before[0].session_id);
+
+        let moved = transfer(&server, 1, 2, Some(vec![before[2].id, before[0].id])).await;
+        moved.assert_status_ok();
+        let moved = moved.json::<Vec<MenuItem>>();
+        // they join the party at the other table
+        let joined = |item: &MenuItem| MenuItem {
+            session_id,
+            ..item.clone()
+        };
+        assert_eq!(moved, vec![joined(&before[0]), joined(&before[2])]);
+        assert_eq!(get_items(&server, 1).await, vec![before[1].clone()]);
+        assert_eq!(&get_items(&server, 2).await[1..], &moved[..]);
+
+        for (to, item_ids, status) in [
+            (2, Some(vec![before[1].id, 999]), StatusCode::NOT_FOUND),
+            (2, Some(vec![before[0].id]), StatusCode::NOT_FOUND),
+            (1, None, StatusCode::BAD_REQUEST),
+            (300, None, StatusCode::NOT_FOUND),
+        ] {
+            transfer(&server, 1, to, item_ids)
+                .await
+                .assert_status(status);
+        }
+        assert_eq!(get_items(&server, 1).await, vec![before[1].clone()]);
+    }
+
+    #[tokio::test]
+    /// test that moving without item ids merges the tables and that inactive tables take no items
+    async fn merge_tables() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![1, 2]).await.assert_status_ok();
+        add_items(&server, 2, vec![3]).await.assert_status_ok();
+        update_table(
+            &server,
+            &TableInfo {
+                active: false,
+                ..TableInfo::numbered(3)
+            },
+        )
+        .await
+        .assert_status_ok();
+        transfer(&server, 1, 3, None)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        let moved = transfer(&server, 1, 2, None).await.json::<Vec<MenuItem>>();
+        assert_eq!(moved.len(), 2);
+        assert!(get_items(&server, 1).await.is_empty());
+        assert_eq!(get_items(&server, 2).await.len(), 3);
+        assert!(transfer(&server, 1, 2, None)
+            .await
+            .json::<Vec<MenuItem>>()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    /// test that subscribers of either table see the move and the emptied table closes
+    async fn transfer_events() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        let items = store.add_items(1, vec![1], None).await.unwrap();
+        let subscribe = |table_number| {
+            store.subscribe(
+                EventFilter {
+                    table_number: Some(table_number),
+                    station: None,
+                },
+                None,
+            )
+        };
+        let (mut source, mut target) = (subscribe(1), subscribe(2));
+        store.transfer_items(1, 2, None).await.unwrap();
+
+        let moved = EventKind::ItemMoved {
+            item: MenuItem {
+                session_id: Some(2),
+                ..items[0].clone()
+            },
+            from: 1,
+        };
+        let event = source.next().await.unwrap();
+        assert_eq!((event.table_number, &event.kind), (Some(2), &moved));
+        assert_eq!(source.next().await.unwrap().kind, EventKind::TableClosed);
+        assert_eq!(target.next().await.unwrap().kind, moved);
+    }
+
+    #[tokio::test]
+    /// test that transfers in opposite directions at the same time do not deadlock
+    async fn opposite_transfers() {
+        let store = Arc::new(MemoryStore::new().with_menu(Menu::for_tests()));
+        store.add_items(1, vec![1; 20], None).await.unwrap();
+        store.add_items(2, vec![2; 20], None).await.unwrap();
+        let tasks = (0..40)
+            .map(|i| {
+                let store = store.clone();
+                let (from, to) = if i % 2 == 0 { (1, 2) } else { (2, 1) };
+                tokio::spawn(async move { store.transfer_items(from, to, None).await })
+            })
+            .collect::<Vec<_>>();
+        tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            for task in tasks {
+                task.await.unwrap().unwrap();
+            }
+        })
+        .await
+        .expect("transfers deadlocked");
+        let total =
+            store.table(1).await.unwrap().items.len() + store.table(2).await.unwrap().items.len();
+        assert_eq!(total, 40);
+    }
+
+    /// helper function that adds a table as manager
+    async fn add_table(server: &TestServer, table: &NewTable) -> TestResponse {
+        server
+            .post(&routes::tables())
+            .add_query_param("key", "manager")
+            .json(table)
+            .await
+    }
+
+    /// helper function that replaces the floor plan entry of `table` as manager
+    async fn update_table(server: &TestServer, table: &TableInfo) -> TestResponse {
+        server
+            .put(&routes::table_info(table.table_number))
+            .add_query_param("key", "manager")
+            .json(table)
+            .await
+    }
+
+    /// helper function that removes `table` from the floor plan as manager
+    async fn remove_table(server: &TestServer, table: usize) -> TestResponse {
+        server
+            .delete(&routes::table_info(table))
+            .add_query_param("key", "manager")
+            .await
+    }
+
+    #[tokio::test]
+    /// test adding, renaming, deactivating and removing a table, and that its number is never reused
+    async fn floor_plan_lifecycle() {
+        let server = setup_server().await.unwrap();
+        let added = add_table(
+            &server,
+            &NewTable {
+                name: Some("Terrace 3".to_string()),
+                section: Some("Terrace".to_string()),
+                capacity: Some(4),
+            },
+        )
+        .await
+        .json::<TableInfo>();
+        assert_eq!(added.table_number, 100);
+        assert!(added.active);
+        let floor_plan = server
+            .get(&routes::tables())
+            .add_query_param("key", "waiter")
+            .await
+            .json::<Vec<TableInfo>>();
+        assert_eq!(floor_plan.len(), 101);
+        assert_eq!(floor_plan.last(), Some(&added));
+
+        let ids = add_items(&server, 100, vec![1, 2]).await.json::<Vec<u64>>();
+        let renamed = TableInfo {
+            name: Some("Terrace 4".to_string()),
+            capacity: Some(6),
+            active: false,
+            ..added.clone()
+        };
+        assert_eq!(
+            update_table(&server, &renamed).await.json::<TableInfo>(),
+            renamed
+        );
+        // an inactive table keeps its orders but takes no new ones
+        let refused = add_items(&server, 100, vec![3]).await;
+        refused.assert_status(StatusCode::CONFLICT);
+        assert_eq!(refused.json::<ErrorBody>().error, ErrorKind::Conflict);
+        assert_eq!(get_items(&server, 100).await.len(), 2);
+        let tables = server
+            .get(routes::ALL_TABLES)
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<Vec<Table>>();
+        assert_eq!(tables[0].name.as_deref(), Some("Terrace 4"));
+        assert!(!tables[0].active);
+
+        remove_table(&server, 100)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        for id in ids {
+            delete_item_by_id(&server, 100, id).await.assert_status_ok();
+        }
+        // the party is still seated
+        remove_table(&server, 100)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        server
+            .delete(&routes::session(100))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_ok();
+        assert!(remove_table(&server, 100).await.json::<bool>());
+        assert!(!remove_table(&server, 100).await.json::<bool>());
+        server
+            .get(&routes::table(100))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_not_found();
+        let next = add_table(&server, &NewTable::default())
+            .await
+            .json::<TableInfo>();
+        assert_eq!(next.table_number, 101);
+    }
+
+    #[tokio::test]
+    /// test that nonsensical floor plan changes are refused and only managers may make them
+    async fn floor_plan_errors() {
+        let server = setup_server().await.unwrap();
+        let table = TableInfo::numbered(3);
+        server
+            .put(&routes::table_info(4))
+            .add_query_param("key", "manager")
+            .json(&table)
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        update_table(&server, &TableInfo::numbered(300))
+            .await
+            .assert_status_not_found();
+        for invalid in [
+            TableInfo {
+                capacity: Some(0),
+                ..table.clone()
+            },
+            TableInfo {
+                name: Some(" ".to_string()),
+                ..table.clone()
+            },
+        ] {
+            update_table(&server, &invalid)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        add_table(
+            &server,
+            &NewTable {
+                capacity: Some(0),
+                ..NewTable::default()
+            },
+        )
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .post(&routes::tables())
+            .add_query_param("key", "waiter")
+            .json(&NewTable::default())
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        server
+            .delete(&routes::table_info(3))
+            .add_query_param("key", "waiter")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    /// test that subscribers of a table hear about changes to its floor plan entry
+    async fn floor_plan_events() {
+        let store = MemoryStore::with_table_count(2);
+        let mut events = store.subscribe(
+            EventFilter {
+                table_number: Some(2),
+                station: None,
+            },
+            None,
+        );
+        let added = store.add_table(NewTable::default()).await.unwrap();
+        assert_eq!(added.table_number, 2);
+        let renamed = TableInfo {
+            name: Some("Bar 1".to_string()),
+            ..added.clone()
+        };
+        store.update_table(renamed.clone()).await.unwrap();
+        assert!(store.remove_table(2).await.unwrap());
+
+        assert_eq!(
+            events.next().await.unwrap().kind,
+            EventKind::TableChanged { table: added }
+        );
+        assert_eq!(
+            events.next().await.unwrap().kind,
+            EventKind::TableChanged { table: renamed }
+        );
+        assert_eq!(events.next().await.unwrap().kind, EventKind::TableRemoved);
+        assert!(matches!(
+            store.add_items(2, vec![1], None).await,
+            Err(StoreError::UnknownTable)
+        ));
+    }
+
+    /// helper function that seats a party at `table` as waiter
+    async fn open_session(
+        server: &TestServer,
+        table: usize,
+        details: &SessionDetails,
+    ) -> TestResponse {
+        server
+            .post(&routes::session(table))
+            .add_query_param("key", "waiter")
+            .json(details)
+            .await
+    }
+
+    /// helper function that clears `table` as waiter
+    async fn close_session(server: &TestServer, table: usize) -> TestResponse {
+        server
+            .delete(&routes::session(table))
+            .add_query_param("key", "waiter")
+            .await
+    }
+
+    #[tokio::test]
+    /// test seating a party, changing it, closing it and finding it in the history
+    async fn session_lifecycle() {
+        let server = setup_server().await.unwrap();
+        server
+            .get(&routes::session(1))
+            .add_query_param("key", "waiter")
+            .await
+            .assert_status_not_found();
+        let opened = open_session(
+            &server,
+            1,
+            &SessionDetails {
+                guests: Some(4),
+                staff: Some("Anna".to_string()),
+            },
+        )
+        .await
+        .json::<SessionInfo>();
+        assert_eq!(opened.guests, Some(4));
+        open_session(&server, 1, &SessionDetails::default())
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        let updated = server
+            .patch(&routes::session(1))
+            .add_query_param("key", "waiter")
+            .json(&SessionDetails {
+                guests: Some(5),
+                staff: None,
+            })
+            .await
+            .json::<SessionInfo>();
+        assert_eq!(
+            updated,
+            SessionInfo {
+                guests: Some(5),
+                ..opened.clone()
+            }
+        );
+        assert_eq!(
+            server
+                .get(&routes::session(1))
+                .add_query_param("key", "waiter")
+                .await
+                .json::<SessionInfo>(),
+            updated
+        );
+
+        let ids = add_items(&server, 1, vec![1, 2]).await.json::<Vec<u64>>();
+        assert!(get_items(&server, 1)
+            .await
+            .iter()
+            .all(|item| item.session_id == Some(opened.id)));
+        // the kitchen is not done yet
+        close_session(&server, 1)
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        set_status(&server, 1, ids[0], ItemStatus::Cancelled)
+            .await
+            .assert_status_ok();
+        for status in [ItemStatus::Cooking, ItemStatus::Ready, ItemStatus::Served] {
+            set_status(&server, 1, ids[1], status)
+                .await
+                .assert_status_ok();
+        }
+        let closed = close_session(&server, 1).await.json::<Session>();
+        assert_eq!(closed.info, updated);
+        assert_eq!(closed.table_number, 1);
+        assert_eq!(
+            closed.items.iter().map(|item| item.id).collect::<Vec<_>>(),
+            ids
+        );
+        assert!(get_items(&server, 1).await.is_empty());
+        close_session(&server, 1).await.assert_status_not_found();
+
+        // the next party gets a new session
+        add_items(&server, 1, vec![3]).await.assert_status_ok();
+        let next = get_items(&server, 1).await[0].session_id.unwrap();
+        assert_ne!(next, opened.id);
+        let history = server
+            .get(&routes::sessions(1))
+            .add_query_param("key", "waiter")
+            .await
+            .json::<Vec<Session>>();
+        assert_eq!(history, vec![closed]);
+        assert!(server
+            .get(&routes::sessions(2))
+            .add_query_param("key", "waiter")
+            .await
+            .json::<Vec<Session>>()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    /// test that nonsensical sessions are refused and only staff taking orders may change them
+    async fn session_errors() {
+        let server = setup_server().await.unwrap();
+        open_session(
+            &server,
+            1,
+            &SessionDetails {
+                guests: Some(0),
+                staff: None,
+            },
+        )
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+        open_session(&server, 300, &SessionDetails::default())
+            .await
+            .assert_status_not_found();
+        server
+            .patch(&routes::session(1))
+            .add_query_param("key", "waiter")
+            .json(&SessionDetails::default())
+            .await
+            .assert_status_not_found();
+        server
+            .post(&routes::session(1))
+            .add_query_param("key", "kitchen")
+            .json(&SessionDetails::default())
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        open_session(&server, 1, &SessionDetails::default())
+            .await
+            .assert_status_ok();
+        server
+            .delete(&routes::session(1))
+            .add_query_param("key", "kitchen")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        server
+            .get(&routes::session(1))
+            .add_query_param("key", "kitchen")
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    /// test that subscribers of a table hear about its session
+    async fn session_events() {
+        let store = MemoryStore::with_table_count(2);
+        let mut events = store.subscribe(
+            EventFilter {
+                table_number: Some(1),
+                station: None,
+            },
+            None,
+        );
+        let session = store
+            .open_session(1, SessionDetails::default())
+            .await
+            .unwrap();
+        let closed = store.close_session(1).await.unwrap();
+        assert_eq!(
+            events.next().await.unwrap().kind,
+            EventKind::SessionChanged { session }
+        );
+        assert_eq!(
+            events.next().await.unwrap().kind,
+            EventKind::SessionClosed {
+                session_id: closed.info.id
+            }
+        );
+    }
+
+    /// helper function that starts a testserver working out bills following `rules`
+    fn setup_billing_server(rules: BillingRules) -> TestServer {
+        TestServer::new(router_with_state(Arc::new(
+            MemoryStore::new()
+                .with_menu(Menu::for_tests())
+                .with_credentials(Credentials::for_tests())
+                .with_billing(rules),
+        )))
+        .unwrap()
+    }
+
+    /// helper function that gets the bill of `table`
+    async fn get_bill(server: &TestServer, table: usize) -> Bill {
+        server
+            .get(&routes::bill(table))
+            .add_query_param("key", "waiter")
+            .await
+            .json()
+    }
+
+    /// helper function that puts `kind` on the bill of `table` as manager
+    async fn adjust_bill(server: &TestServer, table: usize, kind: AdjustmentKind) -> TestResponse {
+        server
+            .post(&routes::bill_adjustments(table))
+            .add_query_param("key", "manager")
+            .json(&NewAdjustment {
+                kind,
+                reason: "regulars".to_string(),
+            })
+            .await
+    }
+
+    #[tokio::test]
+    /// test that comps, discounts, service charge and tax add up the way the rules say
+    async fn bill_totals() {
+        let server = setup_billing_server(BillingRules {
+            tax_percent: 10,
+            service_charge_percent: 10,
+            service_charge_min_guests: Some(4),
+            rounding: Rounding::Nearest,
+        });
+        let ids = add_items(&server, 1, vec![1, 2, 3, 4])
+            .await
+            .json::<Vec<u64>>();
+        set_status(&server, 1, ids[3], ItemStatus::Cancelled)
+            .await
+            .assert_status_ok();
+        let bill = get_bill(&server, 1).await;
+        assert_eq!(
+            bill.lines.iter().map(|line| line.price).collect::<Vec<_>>(),
+            vec![100, 200, 300]
+        );
+        assert_eq!(bill.lines[0].name, "Dish 1");
+        // nobody counted the guests, so there is no service charge
+        assert_eq!((bill.subtotal, bill.service_charge, bill.tax), (600, 0, 60));
+        assert_eq!(bill.total, 660);
+        assert!(!bill.closed);
+
+        let comp = adjust_bill(&server, 1, AdjustmentKind::Comp { item_id: ids[2] })
+            .await
+            .json::<Adjustment>();
+        adjust_bill(&server, 1, AdjustmentKind::PercentOff { percent: 15 })
+            .await
+            .assert_status_ok();
+        adjust_bill(&server, 1, AdjustmentKind::AmountOff { amount: 50 })
+            .await
+            .assert_status_ok();
+        server
+            .patch(&routes::session(1))
+            .add_query_param("key", "waiter")
+            .json(&SessionDetails {
+                guests: Some(4),
+                staff: None,
+            })
+            .await
+            .assert_status_ok();
+        let bill = get_bill(&server, 1).await;
+        assert_eq!(bill.lines[2].comped.as_deref(), Some("regulars"));
+        assert_eq!(bill.adjustments.len(), 3);
+        // 300 comped, 15% of the other 300 and 50 off leave 205, plus 20.5 service and 22.55 tax
+        assert_eq!(bill.discount, 395);
+        assert_eq!((bill.service_charge, bill.tax, bill.total), (21, 23, 249));
+
+        let removed = server
+            .delete(&routes::bill_adjustment(1, comp.id))
+            .add_query_param("key", "manager")
+            .await
+            .json::<bool>();
+        assert!(removed);
+        assert_eq!(get_bill(&server, 1).await.discount, 90 + 50);
+        // discounts never make a bill negative, even when they add up to more than it
+        adjust_bill(&server, 1, AdjustmentKind::AmountOff { amount: 600 })
+            .await
+            .assert_status_ok();
+        let bill = get_bill(&server, 1).await;
+        assert_eq!(
+            (bill.discount, bill.service_charge, bill.total),
+            (600, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    /// test that the bill of a closed session is kept as it was, whatever happens to the menu afterwards
+    async fn final_bill() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![5, 5]).await.json::<Vec<u64>>();
+        let entry = server
+            .get(&routes::menu_entry(5))
+            .add_query_param("key", API_KEY)
+            .await
+            .json::<MenuEntry>();
+        // a price change only affects later orders
+        server
+            .put(&routes::menu_entry(5))
+            .add_query_param("key", "manager")
+            .json(&MenuEntry {
+                price: 900,
+                ..entry
+            })
+            .await
+            .assert_status_ok();
+        add_items(&server, 1, vec![5]).await.assert_status_ok();
+        assert_eq!(get_bill(&server, 1).await.subtotal, 500 + 500 + 900);
+
+        for id in get_items(&server, 1).await.iter().map(|item| item.id) {
+            let status = if id == ids[0] {
+                ItemStatus::Cancelled
+            } else {
+                ItemStatus::Cooking
+            };
+            set_status(&server, 1, id, status).await.assert_status_ok();
+        }
+        for id in &ids[1..] {
+            for status in [ItemStatus::Ready, ItemStatus::Served] {
+                set_status(&server, 1, *id, status).await.assert_status_ok();
+            }
+        }
+        let third = get_items(&server, 1).await[2].id;
+        for status in [ItemStatus::Ready, ItemStatus::Served] {
+            set_status(&server, 1, third, status)
+                .await
+                .assert_status_ok();
+        }
+        let closed = close_session(&server, 1).await.json::<Session>();
+        let bill = closed.bill.unwrap();
+        assert!(bill.closed);
+        assert_eq!(bill.issued_at, closed.closed_at);
+        assert_eq!((bill.subtotal, bill.tax, bill.total), (1400, 140, 1540));
+
+        server
+            .delete(&routes::menu_entry(5))
+            .add_query_param("key", "manager")
+            .await
+            .assert_status_ok();
+        let history = server
+            .get(&routes::sessions(1))
+            .add_query_param("key", "waiter")
+            .await
+            .json::<Vec<Session>>();
+        assert_eq!(history[0].bill.as_ref(), Some(&bill));
+        server
+            .get(&routes::bill(1))
+            .add_query_param("key", "waiter")
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    /// test that nonsensical discounts and comps are refused and only managers may give them
+    async fn bill_adjustment_errors() {
+        let server = setup_server().await.unwrap();
+        adjust_bill(&server, 1, AdjustmentKind::AmountOff { amount: 100 })
+            .await
+            .assert_status_not_found();
+        let ids = add_items(&server, 1, vec![1, 2]).await.json::<Vec<u64>>();
+        set_status(&server, 1, ids[1], ItemStatus::Cancelled)
+            .await
+            .assert_status_ok();
+        for kind in [
+            AdjustmentKind::PercentOff { percent: 0 },
+            AdjustmentKind::PercentOff { percent: 101 },
+            AdjustmentKind::AmountOff { amount: 0 },
+            AdjustmentKind::AmountOff {
+                amount: u64::MAX / 2 + 1,
+            },
+            AdjustmentKind::Comp { item_id: ids[1] },
+        ] {
+            adjust_bill(&server, 1, kind)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        adjust_bill(&server, 1, AdjustmentKind::Comp { item_id: 999 })
+            .await
+            .assert_status_not_found();
+        adjust_bill(&server, 1, AdjustmentKind::Comp { item_id: ids[0] })
+            .await
+            .assert_status_ok();
+        adjust_bill(&server, 1, AdjustmentKind::Comp { item_id: ids[0] })
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .post(&routes::bill_adjustments(1))
+            .add_query_param("key", "manager")
+            .json(&NewAdjustment {
+                kind: AdjustmentKind::AmountOff { amount: 100 },
+                reason: " ".to_string(),
+            })
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .post(&routes::bill_adjustments(1))
+            .add_query_param("key", "waiter")
+            .json(&NewAdjustment {
+                kind: AdjustmentKind::AmountOff { amount: 100 },
+                reason: "friend".to_string(),
+            })
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        let removed = server
+            .delete(&routes::bill_adjustment(1, 42))
+            .add_query_param("key", "manager")
+            .await
+            .json::<bool>();
+        assert!(!removed);
+    }
+
+    /// helper function that adds `items` to `table` for the guest on `seat`
+    async fn add_items_for_seat(
+        server: &TestServer,
+        table: usize,
+        seat: u32,
+        items: Vec<usize>,
+    ) -> TestResponse {
+        server
+            .post(&routes::table(table))
+            .add_query_param("key", API_KEY)
+            .add_query_param("seat", seat)
+            .json(&items)
+            .await
+    }
+
+    /// helper function that splits the bill of `table`
+    async fn split_bill(server: &TestServer, table: usize, mode: SplitMode) -> TestResponse {
+        server
+            .post(&routes::bill_split(table))
+            .add_query_param("key", "waiter")
+            .json(&mode)
+            .await
+    }
+
+    #[tokio::test]
+    /// test that every seat pays its own items and an even part of the shared ones
+    async fn split_by_seat() {
+        let server = setup_server().await.unwrap();
+        let first = add_items_for_seat(&server, 1, 1, vec![1])
+            .await
+            .json::<Vec<u64>>();
+        let second = add_items_for_seat(&server, 1, 2, vec![2, 3])
+            .await
+            .json::<Vec<u64>>();
+        let shared = add_items(&server, 1, vec![4]).await.json::<Vec<u64>>();
+        let items = get_items(&server, 1).await;
+        assert_eq!(
+            items.iter().map(|item| item.seat).collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(2), None]
+        );
+
+        let split = split_bill(&server, 1, SplitMode::BySeat)
+            .await
+            .json::<SplitBill>();
+        assert_eq!(split.total, 1100);
+        assert_eq!(split.shared_item_ids, shared);
+        // 100 + 200 of the shared 400 and 500 + 200 of it, with tax
+        assert_eq!(
+            split.shares,
+            vec![
+                BillShare {
+                    seat: Some(1),
+                    item_ids: first,
+                    amount: 330,
+                },
+                BillShare {
+                    seat: Some(2),
+                    item_ids: second,
+                    amount: 770,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    /// test that items can be assigned to shares and comped items are free for their share
+    async fn split_by_item() {
+        let server = setup_server().await.unwrap();
+        let ids = add_items(&server, 1, vec![1, 2, 3])
+            .await
+            .json::<Vec<u64>>();
+        let split = split_bill(
+            &server,
+            1,
+            SplitMode::ByItem {
+                shares: vec![vec![ids[0]], vec![ids[1]]],
+            },
+        )
+        .await
+        .json::<SplitBill>();
+        assert_eq!(split.shared_item_ids, vec![ids[2]]);
+        assert_eq!(
+            split
+                .shares
+                .iter()
+                .map(|share| (share.seat, share.amount))
+                .collect::<Vec<_>>(),
+            vec![(None, 275), (None, 385)]
+        );
+
+        adjust_bill(&server, 1, AdjustmentKind::Comp { item_id: ids[1] })
+            .await
+            .assert_status_ok();
+        let split = split_bill(
+            &server,
+            1,
+            SplitMode::ByItem {
+                shares: vec![vec![ids[0]], vec![ids[1]]],
+            },
+        )
+        .await
+        .json::<SplitBill>();
+        assert_eq!(split.total, 440);
+        assert_eq!(
+            split
+                .shares
+                .iter()
+                .map(|share| share.amount)
+                .collect::<Vec<_>>(),
+            vec![275, 165]
+        );
+    }
+
+    #[tokio::test]
+    /// test that even splits hand the yen left over to the first shares and always add up to the total
+    async fn split_evenly() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![1]).await.assert_status_ok();
+        let split = split_bill(&server, 1, SplitMode::Even { ways: 3 })
+            .await
+            .json::<SplitBill>();
+        assert_eq!(
+            split
+                .shares
+                .iter()
+                .map(|share| share.amount)
+                .collect::<Vec<_>>(),
+            vec![37, 37, 36]
+        );
+        assert_eq!(split.shared_item_ids.len(), 1);
+
+        add_items_for_seat(&server, 1, 2, vec![3, 7])
+            .await
+            .assert_status_ok();
+        add_items_for_seat(&server, 1, 5, vec![2])
+            .await
+            .assert_status_ok();
+        let total = get_bill(&server, 1).await.total;
+        let mut modes = (1..=12)
+            .map(|ways| SplitMode::Even { ways })
+            .collect::<Vec<_>>();
+        modes.push(SplitMode::BySeat);
+        for mode in modes {
+            let split = split_bill(&server, 1, mode).await.json::<SplitBill>();
+            assert_eq!(
+                split.shares.iter().map(|share| share.amount).sum::<u64>(),
+                total
+            );
+        }
+    }
+
+    #[tokio::test]
+    /// test that splits that cannot work answer 400 and seats start at 1
+    async fn split_errors() {
+        let server = setup_server().await.unwrap();
+        split_bill(&server, 1, SplitMode::Even { ways: 2 })
+            .await
+            .assert_status_not_found();
+        let ids = add_items(&server, 1, vec![1, 2]).await.json::<Vec<u64>>();
+        for mode in [
+            SplitMode::BySeat,
+            SplitMode::ByItem { shares: vec![] },
+            SplitMode::ByItem {
+                shares: vec![vec![999]],
+            },
+            SplitMode::ByItem {
+                shares: vec![vec![ids[0]], vec![ids[0], ids[1]]],
+            },
+            SplitMode::Even { ways: 0 },
+            SplitMode::Even { ways: 101 },
+        ] {
+            split_bill(&server, 1, mode)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        add_items_for_seat(&server, 1, 0, vec![1])
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+    }
+
+    #[tokio::test]
+    /// test that items move between seats and subscribers hear about it
+    async fn item_seats() {
+        let store = Arc::new(
+            MemoryStore::new()
+                .with_menu(Menu::for_tests())
+                .with_credentials(Credentials::for_tests()),
+        );
+        let server = TestServer::new(router_with_state(store.clone())).unwrap();
+        let ids = add_items(&server, 1, vec![1]).await.json::<Vec<u64>>();
+        let mut events = store.subscribe(EventFilter::default(), None);
+        let set_seat = |seat| {
+            server
+                .put(&routes::item_seat(1, ids[0]))
+                .add_query_param("key", "waiter")
+                .json(&SeatUpdate { seat })
+        };
+
+        let item = set_seat(Some(3)).await.json::<MenuItem>();
+        assert_eq!(item.seat, Some(3));
+        assert_eq!(
+            events.next().await.unwrap().kind,
+            EventKind::SeatChanged {
+                item_id: ids[0],
+                item_number: 1,
+                seat: Some(3),
+            }
+        );
+        assert_eq!(set_seat(None).await.json::<MenuItem>().seat, None);
+        set_seat(Some(0))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .put(&routes::item_seat(1, 999))
+            .add_query_param("key", "waiter")
+            .json(&SeatUpdate { seat: Some(1) })
+            .await
+            .assert_status_not_found();
+        server
+            .put(&routes::item_seat(1, ids[0]))
+            .add_query_param("key", "kitchen")
+            .json(&SeatUpdate { seat: Some(1) })
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    /// helper function that pays `amount` of the bill of `table` with `method` as waiter
+    async fn pay(
+        server: &TestServer,
+        table: usize,
+        method: PaymentMethod,
+        amount: Option<u64>,
+    ) -> TestResponse {
+        server
+            .post(&routes::bill_payments(table))
+            .add_query_param("key", "waiter")
+            .json(&NewPayment { method, amount })
+            .await
+    }
+
+    /// helper function that refunds `amount` of `payment_id` as manager
+    async fn refund(server: &TestServer, payment_id: u64, amount: Option<u64>) -> TestResponse {
+        server
+            .post(&routes::payment_refund(payment_id))
+            .add_query_param("key", "manager")
+            .json(&NewRefund {
+                amount,
+                reason: "wrong amount".to_string(),
+            })
+            .await
+    }
+
+    /// helper function that gets what was paid of the bill of `table`
+    async fn get_bill_payments(server: &TestServer, table: usize) -> BillPayments {
+        server
+            .get(&routes::bill_payments(table))
+            .add_query_param("key", "waiter")
+            .await
+            .json()
+    }
+
+    /// helper function that creates a store taking card payments through `provider`, retrying right away
+    fn store_with_provider(provider: Arc<dyn PaymentProvider>) -> MemoryStore {
+        MemoryStore::new()
+            .with_menu(Menu::for_tests())
+            .with_credentials(Credentials::for_tests())
+            .with_payments(PaymentGateway::new(provider, 3, Duration::from_millis(1)))
+    }
+
+    #[tokio::test]
+    /// test that cash is taken right away and nobody pays more than the bill
+    async fn cash_payments() {
+        let server = setup_server().await.unwrap();
+        pay(&server, 1, PaymentMethod::Cash, None)
+            .await
+            .assert_status_not_found();
+        add_items(&server, 1, vec![1, 2]).await.assert_status_ok();
+
+        let payment = pay(&server, 1, PaymentMethod::Cash, Some(100))
+            .await
+            .json::<Payment>();
+        assert_eq!(
+            (payment.amount, payment.status),
+            (100, PaymentStatus::Succeeded)
+        );
+        for amount in [Some(0), Some(231)] {
+            pay(&server, 1, PaymentMethod::Cash, amount)
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+        // without a provider only cash is taken
+        pay(&server, 1, PaymentMethod::Card, None)
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        let rest = pay(&server, 1, PaymentMethod::Cash, None)
+            .await
+            .json::<Payment>();
+        assert_eq!(rest.amount, 230);
+        pay(&server, 1, PaymentMethod::Cash, None)
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        let paid = get_bill_payments(&server, 1).await;
+        assert_eq!(
+            (paid.total, paid.paid, paid.pending, paid.balance),
+            (330, 330, 0, 0)
+        );
+        assert_eq!(paid.payments, vec![payment.clone(), rest]);
+        let listed = server
+            .get(&routes::payments())
+            .add_query_param("key", "kitchen")
+            .add_query_param("session_id", paid.session_id)
+            .await
+            .json::<Vec<Payment>>();
+        assert_eq!(listed, paid.payments);
+        let read = server
+            .get(&routes::payment(payment.id))
+            .add_query_param("key", "kitchen")
+            .await
+            .json::<Payment>();
+        assert_eq!(read, payment);
+        server
+            .get(&routes::payment(999))
+            .add_query_param("key", "kitchen")
+            .await
+            .assert_status_not_found();
+        server
+            .post(&routes::bill_payments(1))
+            .add_query_param("key", "kitchen")
+            .json(&NewPayment {
+                method: PaymentMethod::Cash,
+                amount: None,
+            })
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    /// test that payments are refunded in parts, by managers, and never more than was paid
+    async fn refunds() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![3]).await.assert_status_ok();
+        let payment = pay(&server, 1, PaymentMethod::Cash, None)
+            .await
+            .json::<Payment>();
+
+        let first = refund(&server, payment.id, Some(100))
+            .await
+            .json::<Payment>();
+        assert_eq!(first.refund_of, Some(payment.id));
+        assert_eq!(first.reason.as_deref(), Some("wrong amount"));
+        assert_eq!(
+            (first.amount, first.status),
+            (100, PaymentStatus::Succeeded)
+        );
+        let paid = get_bill_payments(&server, 1).await;
+        assert_eq!((paid.paid, paid.balance), (230, 100));
+
+        refund(&server, payment.id, Some(231))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        refund(&server, first.id, None)
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        refund(&server, 999, None).await.assert_status_not_found();
+        server
+            .post(&routes::payment_refund(payment.id))
+            .add_query_param("key", "manager")
+            .json(&NewRefund {
+                amount: None,
+                reason: "".to_string(),
+            })
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .post(&routes::payment_refund(payment.id))
+            .add_query_param("key", "waiter")
+            .json(&NewRefund {
+                amount: None,
+                reason: "wrong amount".to_string(),
+            })
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        let rest = refund(&server, payment.id, None).await.json::<Payment>();
+        assert_eq!(rest.amount, 230);
+        refund(&server, payment.id, None)
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(get_bill_payments(&server, 1).await.balance, 330);
+    }
+
+    #[tokio::test]
+    /// test that a card payment the provider does not answer is retried with the same key until it is
+    async fn card_payment_retries() {
+        let provider = Arc::new(MockProvider::scripted(vec![
+            Err(ProviderError::Unavailable("connection refused".to_string())),
+            Err(ProviderError::Unavailable("503".to_string())),
+        ]));
+        let server = TestServer::new(router_with_state(Arc::new(store_with_provider(
+            provider.clone(),
+        ))))
+        .unwrap();
+        add_items(&server, 1, vec![1]).await.assert_status_ok();
+
+        let payment = pay(&server, 1, PaymentMethod::Card, None)
+            .await
+            .json::<Payment>();
+        assert_eq!(payment.status, PaymentStatus::Succeeded);
+        let key = payments::idempotency_key(&payment);
+        assert_eq!(provider.requests(), vec![key.clone(); 3]);
+        assert_eq!(payment.reference, Some(format!("mock-{}", key)));
+
+        let refunded = refund(&server, payment.id, None).await.json::<Payment>();
+        assert_eq!(refunded.status, PaymentStatus::Succeeded);
+        assert_eq!(provider.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    /// test that declined payments fail, and pending ones hold their amount until they are reconciled
+    async fn declined_and_pending_payments() {
+        let provider = Arc::new(MockProvider::scripted(vec![
+            Ok(Outcome::Declined {
+                reason: "insufficient funds".to_string(),
+            }),
+            Ok(Outcome::Pending {
+                reference: Some("bnpl-1".to_string()),
+            }),
+        ]));
+        let store = store_with_provider(provider.clone());
+        store.add_items(1, vec![1, 2], None).await.unwrap();
+        let mut events = store.subscribe(EventFilter::default(), None);
+
+        let declined = store
+            .pay(
+                1,
+                NewPayment {
+                    method: PaymentMethod::Card,
+                    amount: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(declined.status, PaymentStatus::Failed);
+        assert_eq!(declined.error.as_deref(), Some("insufficient funds"));
+        for status in [PaymentStatus::Pending, PaymentStatus::Failed] {
+            let EventKind::PaymentChanged { payment } = events.next().await.unwrap().kind else {
+                panic!("expected a payment event");
+            };
+            assert_eq!((payment.id, payment.status), (declined.id, status));
+        }
+
+        let deferred = store
+            .pay(
+                1,
+                NewPayment {
+                    method: PaymentMethod::Deferred,
+                    amount: Some(300),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(deferred.status, PaymentStatus::Pending);
+        assert_eq!(deferred.reference.as_deref(), Some("bnpl-1"));
+        let paid = store.bill_payments(1).await.unwrap();
+        assert_eq!((paid.paid, paid.pending, paid.balance), (0, 300, 30));
+        store
+            .refund(
+                deferred.id,
+                NewRefund {
+                    amount: None,
+                    reason: "changed their mind".to_string(),
+                },
+            )
+            .await
+            .unwrap_err();
+
+        store.reconcile_payments().await;
+        let settled = store.payment(deferred.id).await.unwrap();
+        assert_eq!(settled.status, PaymentStatus::Succeeded);
+        let paid = store.bill_payments(1).await.unwrap();
+        assert_eq!((paid.paid, paid.pending, paid.balance), (300, 0, 30));
+        // the declined payment is not asked about again
+        assert_eq!(provider.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    /// test that the http provider sends idempotency keys, retries server errors and fails on client errors
+    async fn http_provider() {
+        #[derive(Default)]
+        struct Stub {
+            charges: Mutex<HashMap<String, serde_json::Value>>,
+            /// the idempotency key and authorization header of every request
+            requests: Mutex<Vec<(String, String)>>,
+        }
+        async fn charge(
+            State(stub): State<Arc<Stub>>,
+            headers: HeaderMap,
+            Json(body): Json<serde_json::Value>,
+        ) -> Response {
+            let key = headers["idempotency-key"].to_str().unwrap().to_string();
+            assert_eq!(body["idempotency_key"], key.as_str());
+            let authorization = headers[AUTHORIZATION].to_str().unwrap().to_string();
+            let mut requests = stub.requests.lock().unwrap();
+            requests.push((key.clone(), authorization));
+            if requests.len() == 1 {
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+            if body["amount"].as_u64().unwrap() > 1000 {
+                return (StatusCode::UNPROCESSABLE_ENTITY, "over the limit").into_response();
+            }
+            let mut charges = stub.charges.lock().unwrap();
+            let count = charges.len();
+            let outcome = charges.entry(key).or_insert_with(
+                || json!({"status": "succeeded", "reference": format!("ch_{}", count + 1)}),
+            );
+            Json(outcome.clone()).into_response()
+        }
+        let stub = Arc::new(Stub::default());
+        let app = axum::Router::new()
+            .route("/v1/charges", axum::routing::post(charge))
+            .route("/v1/refunds", axum::routing::post(charge))
+            .with_state(stub.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let provider =
+            HttpProvider::new(&url, Some("token".to_string()), Duration::from_secs(5)).unwrap();
+        let store = store_with_provider(Arc::new(provider));
+        store.add_items(1, vec![5, 6, 5, 6], None).await.unwrap();
+        let card = NewPayment {
+            method: PaymentMethod::Card,
+            amount: Some(500),
+        };
+        let payment = store.pay(1, card.clone()).await.unwrap();
+        assert_eq!(payment.status, PaymentStatus::Succeeded);
+        assert_eq!(payment.reference.as_deref(), Some("ch_1"));
+        let key = payments::idempotency_key(&payment);
+        assert_eq!(
+            *stub.requests.lock().unwrap(),
+            vec![(key.clone(), "Bearer token".to_string()); 2]
+        );
+
+        let refunded = store
+            .refund(
+                payment.id,
+                NewRefund {
+                    amount: Some(200),
+                    reason: "cold".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(refunded.status, PaymentStatus::Succeeded);
+        assert_eq!(refunded.reference.as_deref(), Some("ch_2"));
+
+        let too_much = store
+            .pay(
+                1,
+                NewPayment {
+                    amount: Some(1001),
+                    ..card
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(too_much.status, PaymentStatus::Failed);
+        assert!(too_much.error.unwrap().contains("over the limit"));
+        assert_eq!(stub.charges.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    /// test that the key is accepted as bearer token in the `Authorization` header
+    async fn authorization_header() {
+        let server = setup_server().await.unwrap();
+        server
+            .post(&routes::table(1))
+            .authorization_bearer("waiter")
+            .json(&vec![10])
+            .await
+            .assert_status_ok();
+        server
+            .post(&routes::table(1))
+            .authorization_bearer("kitchen")
+            .json(&vec![10])
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        for authorization in ["Bearer wrong", "Basic d2FpdGVy", "Bearer"] {
+            server
+                .get(&routes::table(1))
+                .authorization(authorization)
+                .await
+                .assert_status_unauthorized();
+        }
+        // the header wins over the deprecated query parameter
+        server
+            .get(&routes::table(1))
+            .authorization_bearer("wrong")
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    /// test that every change counts up the version of its tables, which reads send as `ETag`
+    async fn table_versions() {
+        let server = setup_server().await.unwrap();
+        let read = |table: usize| {
+            server
+                .get(&routes::table(table))
+                .authorization_bearer(API_KEY)
+        };
+        read(1).await.assert_header(ETAG, "\"0\"");
+        add_items(&server, 1, vec![1, 2]).await.assert_status_ok();
+        let items = get_items(&server, 1).await;
+        set_status(&server, 1, items[0].id, ItemStatus::Cooking)
+            .await
+            .assert_status_ok();
+        read(1).await.assert_header(ETAG, "\"2\"");
+        server
+            .get(&routes::item(1, items[1].id))
+            .authorization_bearer(API_KEY)
+            .await
+            .assert_header(ETAG, "\"2\"");
+        server
+            .get(&routes::session(1))
+            .authorization_bearer(API_KEY)
+            .await
+            .assert_header(ETAG, "\"2\"");
+
+        transfer(&server, 1, 2, Some(vec![items[1].id]))
+            .await
+            .assert_status_ok();
+        read(1).await.assert_header(ETAG, "\"3\"");
+        read(2).await.assert_header(ETAG, "\"1\"");
+        let table = server
+            .get(&routes::all_tables())
+            .authorization_bearer(API_KEY)
+            .await
+            .json::<Vec<Table>>()
+            .into_iter()
+            .find(|table| table.table_number == 1)
+            .unwrap();
+        assert_eq!(table.version, 3);
+
+        // an unchanged table is not sent again
+        let unchanged = read(1).add_header(IF_NONE_MATCH, "\"2\", W/\"3\"").await;
+        unchanged.assert_status(StatusCode::NOT_MODIFIED);
+        unchanged.assert_header(ETAG, "\"3\"");
+        assert!(unchanged.text().is_empty());
+        read(1)
+            .add_header(IF_NONE_MATCH, "\"2\"")
+            .await
+            .assert_status_ok();
+        read(1)
+            .add_header(IF_NONE_MATCH, "*")
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+        server
+            .get(&routes::table(1000))
+            .authorization_bearer(API_KEY)
+            .add_header(IF_NONE_MATCH, "*")
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    /// test that a change naming an older version of the table in `If-Match` is refused without changing anything
+    async fn if_match() {
+        let server = setup_server().await.unwrap();
+        add_items(&server, 1, vec![1, 2, 3])
+            .await
+            .assert_status_ok();
+        let etag = server
+            .get(&routes::table(1))
+            .authorization_bearer(API_KEY)
+            .await
+            .header(ETAG);
+        assert_eq!(etag, "\"1\"");
+        let remove_first = |etag: &str| {
+            server
+                .delete(&routes::item_position(1, 0))
+                .authorization_bearer(API_KEY)
+                .add_header(IF_MATCH, etag)
+        };
+        // somebody else removes the first item in between
+        delete_item(&server, 1, 0).await.assert_status_ok();
+        let stale = remove_first("\"1\"").await;
+        stale.assert_status(StatusCode::PRECONDITION_FAILED);
+        stale.assert_header(ETAG, "\"2\"");
+        assert_eq!(
+            stale.json::<ErrorBody>().error,
+            ErrorKind::PreconditionFailed
+        );
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+
+        // weak tags never match
+        remove_first("W/\"2\"")
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+        assert!(remove_first("\"1\", \"2\"").await.json::<bool>());
+        assert!(remove_first("*").await.json::<bool>());
+        assert!(get_items(&server, 1).await.is_empty());
+
+        server
+            .post(&routes::session(1))
+            .authorization_bearer(API_KEY)
+            .add_header(IF_MATCH, "\"3\"")
+            .json(&SessionDetails::default())
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+        server
+            .delete(&routes::item_position(1000, 0))
+            .authorization_bearer(API_KEY)
+            .add_header(IF_MATCH, "*")
+            .await
+            .assert_status_not_found();
+        // routes without a table do not care
+        server
+            .get(&routes::menu())
+            .authorization_bearer(API_KEY)
+            .add_header(IF_MATCH, "\"7\"")
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    /// test that the store checks the expected version while holding the lock of the table
+    async fn expected_versions() {
+        let store = MemoryStore::new().with_menu(Menu::for_tests());
+        let items = store.add_items(1, vec![1, 2], None).await.unwrap();
+        let expect = |versions: Vec<u64>| Expectation {
+            table_number: 1,
+            versions,
+        };
+        assert!(matches!(
+            expect(vec![0])
+                .scope(store.remove_item(1, items[0].id))
+                .await,
+            Err(StoreError::VersionMismatch { current: 1 })
+        ));
+        assert!(matches!(
+            expect(vec![0]).scope(store.close_session(1)).await,
+            Err(StoreError::VersionMismatch { current: 1 })
+        ));
+        // other tables are not affected
+        assert!(expect(vec![0])
+            .scope(store.transfer_items(2, 1, None))
+            .await
+            .is_ok());
+        assert!(expect(vec![1])
+            .scope(store.remove_item(1, items[0].id))
+            .await
+            .unwrap());
+        assert_eq!(store.table_version(1).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    /// test that a retry with the same idempotency key gets the first answer without ordering or removing again
+    async fn idempotent_retries() {
+        let server = setup_server().await.unwrap();
+        let order = |key: &str, items: &[u64]| {
+            server
+                .post(&routes::table(1))
+                .authorization_bearer("waiter")
+                .add_header(IDEMPOTENCY_KEY, key)
+                .json(&items)
+        };
+        let first = order("order-1", &[1, 2]).await;
+        first.assert_status_ok();
+        assert!(first.maybe_header(IDEMPOTENT_REPLAYED).is_none());
+        let retry = order("order-1", &[1, 2]).await;
+        retry.assert_status_ok();
+        retry.assert_header(IDEMPOTENT_REPLAYED, "true");
+        assert_eq!(retry.text(), first.text());
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+
+        order("order-1", &[3])
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        // keys belong to the api key sending them
+        server
+            .post(&routes::table(1))
+            .authorization_bearer("manager")
+            .add_header(IDEMPOTENCY_KEY, "order-1")
+            .json(&[3])
+            .await
+            .assert_status_ok();
+        // errors are kept as well, the item is not on the menu either way
+        order("order-2", &[1000]).await.assert_status_bad_request();
+        order("order-2", &[1000])
+            .await
+            .assert_header(IDEMPOTENT_REPLAYED, "true");
+
+        let ids = first.json::<Vec<u64>>();
+        let delete = || {
+            server
+                .delete(&routes::item(1, ids[0]))
+                .authorization_bearer("waiter")
+                .add_header(IDEMPOTENCY_KEY, "delete-1")
+        };
+        assert!(delete().await.json::<bool>());
+        assert!(delete().await.json::<bool>());
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+        // without a key every request is handled
+        server
+            .delete(&routes::item(1, ids[1]))
+            .authorization_bearer("waiter")
+            .await
+            .assert_status_ok();
+        assert!(!server
+            .delete(&routes::item(1, ids[1]))
+            .authorization_bearer("waiter")
+            .await
+            .json::<bool>());
+        order(&"x".repeat(256), &[1])
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    /// test that answers are forgotten after the window and the oldest go first when there are too many
+    async fn idempotency_window() {
+        let clock = Arc::new(ManualClock::new(
+            "2024-07-01T12:00:00Z".parse().expect("a valid timestamp"),
+        ));
+        let store = MemoryStore::new()
+            .with_menu(Menu::for_tests())
+            .with_credentials(Credentials::for_tests())
+            .with_clock(clock.clone());
+        let mut config = Config::default();
+        config.idempotency.window_secs = 60;
+        config.idempotency.max_keys = 2;
+        let server = TestServer::new(router_with_config(Arc::new(store), &config)).unwrap();
+        let order = |key: &'static str| {
+            server
+                .post(&routes::table(1))
+                .authorization_bearer("waiter")
+                .add_header(IDEMPOTENCY_KEY, key)
+                .json(&[1])
+        };
+        let replayed =
+            |response: TestResponse| response.maybe_header(IDEMPOTENT_REPLAYED).is_some();
+
+        assert!(!replayed(order("a").await));
+        clock.advance(chrono::Duration::seconds(59));
+        assert!(replayed(order("a").await));
+        clock.advance(chrono::Duration::seconds(1));
+        assert!(!replayed(order("a").await));
+        assert_eq!(get_items(&server, 1).await.len(), 2);
+
+        assert!(!replayed(order("b").await));
+        assert!(!replayed(order("c").await));
+        assert!(replayed(order("c").await));
+        assert!(replayed(order("b").await));
+        assert!(!replayed(order("a").await));
+    }
+
+    /// helper function that sends `body` to `path` signed with the test key `waiter`, which has the id 2
+    async fn send_signed(
+        server: &TestServer,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        timestamp: i64,
+        nonce: &str,
+    ) -> TestResponse {
+        let canonical = signing::canonical_request(method, path, body, timestamp, nonce);
+        let header = signing::SignatureHeader {
+            key_id: 2,
+            timestamp,
+            nonce: nonce.to_string(),
+            signature: signing::sign(&signing::signing_key("waiter"), &canonical),
+        };
+        let request = match method {
+            "POST" => server.post(path),
+            _ => server.get(path),
+        };
+        request
+            .authorization(header.to_string())
+            .content_type("application/json")
+            .bytes(body.to_vec().into())
+            .await
+    }
+
+    #[tokio::test]
+    /// test that signed requests are accepted once, and only as signed
+    async fn signed_requests() {
+        let (server, clock) = setup_server_with_clock();
+        let now = clock.now().timestamp();
+        send_signed(&server, "POST", &routes::table(1), b"[10]", now, "a")
+            .await
+            .assert_status_ok();
+        send_signed(&server, "GET", "/1/?status=ordered", b"", now + 30, "b")
+            .await
+            .assert_status_ok();
+
+        let replayed = send_signed(&server, "POST", &routes::table(1), b"[10]", now, "a").await;
+        replayed.assert_status_unauthorized();
+        assert_eq!(
+            replayed.json::<ErrorBody>().message,
+            "the request was replayed"
+        );
+        assert_eq!(get_items(&server, 1).await.len(), 1);
+
+        // a body or path other than the signed one
+        let canonical = signing::canonical_request("POST", &routes::table(1), b"[10]", now, "c");
+        let signature = signing::sign(&signing::signing_key("waiter"), &canonical);
+        let header = format!(
+            "Signature key_id=2,timestamp={},nonce=c,signature={}",
+            now, signature
+        );
+        for (path, body) in [(routes::table(1), "[10, 10]"), (routes::table(2), "[10]")] {
+            server
+                .post(&path)
+                .authorization(&header)
+                .content_type("application/json")
+                .bytes(body.into())
+                .await
+                .assert_status_unauthorized();
+        }
+        server
+            .post(&routes::table(1))
+            .authorization(header.replace("key_id=2", "key_id=3"))
+            .content_type("application/json")
+            .bytes("[10]".into())
+            .await
+            .assert_status_unauthorized();
+        // the hash kept to check sent keys does not sign
+        let stored_hash = Sha256::digest(b"waiter");
+        let header = format!(
+            "Signature key_id=2,timestamp={},nonce=c,signature={}",
+            now,
+            signing::sign(&stored_hash, &canonical)
+        );
+        server
+            .post(&routes::table(1))
+            .authorization(header)
+            .content_type("application/json")
+            .bytes("[10]".into())
+            .await
+            .assert_status_unauthorized();
+
+        let stale = now - MAX_SKEW_SECONDS - 1;
+        for timestamp in [stale, i64::MIN, i64::MAX] {
+            send_signed(&server, "POST", &routes::table(1), b"[10]", timestamp, "d")
+                .await
+                .assert_status_unauthorized();
+        }
+        clock.advance(chrono::Duration::seconds(MAX_SKEW_SECONDS + 1));
+        send_signed(&server, "GET", &routes::table(1), b"", now, "e")
+            .await
+            .assert_status_unauthorized();
+        assert_eq!(get_items(&server, 1).await.len(), 1);
+    }
+
+    #[test]
+    /// test that nonces are forgotten once their timestamp is too old anyway
+    fn nonces_expire() {
+        let credentials = Credentials::for_tests();
+        assert!(credentials.use_nonce(2, "a", 100, 0));
+        assert!(!credentials.use_nonce(2, "a", 100, 50));
+        assert!(credentials.use_nonce(3, "a", 100, 50));
+        assert!(credentials.use_nonce(2, "a", 200, 101));
+    }
+
+    #[test]
+    /// test that the api key does not end up in the request logs
+    fn key_is_redacted() {
+        let uri = "/1/?key=secret&limit=2".parse().unwrap();
+        assert_eq!(auth::redact(&uri), "/1/?key=redacted&limit=2");
+        let uri = "/1/".parse().unwrap();
+        assert_eq!(auth::redact(&uri), "/1/");
+    }
+
+    #[test]
+    /// test that the file overrides the defaults, the environment the file and flags the environment
+    fn config_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("restaurant.toml");
+        std::fs::write(
+            &path,
+            "tables = 10\n[limits]\nmax_body_bytes = 100\nrequest_timeout_secs = 5\n[auth]\nkeys = \"k.toml\"\n",
+        )
+        .unwrap();
+        let config_flag = path.to_str().unwrap();
+        let env = HashMap::from([
+            ("RESTAURANT_REQUEST_TIMEOUT_SECS", "7"),
+            ("RESTAURANT_TABLES", "30"),
+        ]);
+        let cli = Cli::try_parse_with_env(
+            ["server", "--config", config_flag, "--tables", "20"],
+            |name| env.get(name).map(|value| value.to_string()),
+        );
+        let config = Config::load(&cli.unwrap()).unwrap();
+        assert_eq!(config.tables, 20);
+        assert_eq!(config.limits.request_timeout_secs, 7);
+        assert_eq!(config.limits.max_body_bytes, 100);
+        assert_eq!(config.auth.keys, std::path::PathBuf::from("k.toml"));
+        assert_eq!(config.listen, Config::default().listen);
+        assert!(Cli::try_parse_with_env(["server", "--tables", "many"], |_| None).is_err());
+        let bad_env = HashMap::from([("RESTAURANT_TABLES", "many")]);
+        assert!(Cli::try_parse_with_env(["server"], |name| bad_env
+            .get(name)
+            .map(|v| v.to_string()))
+        .is_err());
+    }
+
+    #[test]
+    /// test that mistakes in the config are reported at once, all of them
+    fn config_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("restaurant.toml");
+        std::fs::write(&path, "tabels = 10\n").unwrap();
+        assert!(Config::from_file(&path).is_err());
+
+        std::fs::write(
+            &path,
+            "tables = 0\n[log]\nlevel = \"loud\"\n[limits]\nrequest_timeout_secs = 20\n[tls]\ncert = \"cert.pem\"\n[billing]\nrounding = \"sideways\"\n[payments]\nprovider_url = \"ftp://x\"\n[idempotency]\nwindow_secs = 0\n",
+        )
+        .unwrap();
+        let message = Config::from_file(&path)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        for problem in [
+            "tables",
+            "log.level",
+            "tls.cert and tls.key",
+            "billing.rounding",
+            "payments.provider_url",
+            "payments.timeout_secs, retries and retry_backoff_ms let a payment take up to 24.6s",
+            "idempotency.window_secs",
+        ] {
+            assert!(message.contains(problem), "{} misses {}", message, problem);
+        }
+        assert!(Config::default().validate().is_ok());
+        let mut config = Config::default();
+        config.payments.provider_url = Some("https://pay.example.com/v1".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    /// test that the printed config can be read back and does not show the bootstrap or provider key
+    fn print_config() {
+        let mut config = Config::default();
+        config.auth.bootstrap_key = "s3cret".to_string();
+        config.payments.provider_url = Some("https://pay.example.com/v1".to_string());
+        config.payments.provider_key = Some("pr0vider".to_string());
+        config.seed = Some(7);
+        let printed = config.to_toml();
+        assert!(!printed.contains("s3cret"));
+        assert!(!printed.contains("pr0vider"));
+        let mut read_back: Config = toml::from_str(&printed).unwrap();
+        read_back.auth.bootstrap_key = "s3cret".to_string();
+        read_back.payments.provider_key = Some("pr0vider".to_string());
+        assert_eq!(read_back, config);
+    }
+
+    #[tokio::test]
+    /// test that the configured number of tables, body limit and query key setting are applied
+    async fn configured_router() {
+        let mut config = Config::default();
+        config.limits.max_body_bytes = 16;
+        config.auth.allow_query_key = false;
+        let store = MemoryStore::with_table_count(5)
+            .with_menu(Menu::for_tests())
+            .with_credentials(Credentials::for_tests());
+        let server = TestServer::new(router_with_config(Arc::new(store), &config)).unwrap();
+        let post = |table: usize, items: &[u64]| {
+            server
+                .post(&routes::table(table))
+                .authorization_bearer(API_KEY)
+                .json(&items)
+        };
+        post(4, &[10]).await.assert_status_ok();
+        server
+            .get(&routes::table(5))
+            .authorization_bearer(API_KEY)
+            .await
+            .assert_status_not_found();
+        post(4, &[10; 10])
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        server
+            .get(&routes::table(4))
+            .add_query_param("key", API_KEY)
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[cfg(feature = "durable")]
+    mod persistence {
+        use std::{fs::OpenOptions, io::Write, path::Path, sync::Arc, time::Duration};
+
+        use super::{add_items, delete_item_by_id, get_items, set_status};
+        use crate::{
+            payments::{self, MockProvider, PaymentGateway, ProviderError},
+            router_with_state,
+            storage::{self, Durability, LogFile, StorageOptions},
+            store::{Credentials, DurableStore, Menu, RestaurantStore},
+            types::{
+                ItemStatus, MenuItem, NewPayment, NewTable, PaymentMethod, PaymentStatus,
+                SessionDetails, TableInfo, AMOUNT_OF_TABLES,
+            },
+            AppState,
+        };
+        use axum_test::TestServer;
+
+        /// A log that takes `room` more bytes, then fails every write
+        struct FailingLog {
+            bytes: Vec<u8>,
+            room: usize,
+        }
+
+        impl Write for FailingLog {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.room == 0 {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                let written = buf.len().min(self.room);
+                self.bytes.extend_from_slice(&buf[..written]);
+                self.room -= written;
+                Ok(written)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl LogFile for FailingLog {
+            fn log_len(&self) -> std::io::Result<u64> {
+                Ok(self.bytes.len() as u64)
+            }
+
+            fn cut_to(&mut self, len: u64) -> std::io::Result<()> {
+                self.bytes.truncate(len as usize);
+                Ok(())
+            }
+        }
+
+        #[test]
+        /// test that a failed append leaves no torn record in front of the next one
+        fn failed_append_is_cut() {
+            let mut log = FailingLog {
+                bytes: vec![],
+                room: 8,
+            };
+            storage::append_frame(&mut log, b"first").unwrap();
+            assert!(storage::append_frame(&mut log, b"second").is_err());
+            assert_eq!(log.bytes, b"first");
+            log.room = usize::MAX;
+            storage::append_frame(&mut log, b"third").unwrap();
+            assert_eq!(log.bytes, b"firstthird");
+        }
+
+        /// helper function that opens the persisted state in `dir`
+        fn open_state(dir: &Path) -> AppState<DurableStore> {
+            let options = StorageOptions {
+                dir: dir.to_path_buf(),
+                ..StorageOptions::default()
+            };
+            Arc::new(
+                DurableStore::open(&options, AMOUNT_OF_TABLES)
+                    .unwrap()
+                    .with_menu(Menu::for_tests())
+                    .with_credentials(Credentials::for_tests()),
+            )
+        }
+
+        /// helper function that starts a testserver on the persisted state in `dir`
+        fn setup_persisted_server(dir: &Path) -> (TestServer, AppState<DurableStore>) {
+            let state = open_state(dir);
+            (
+                TestServer::new(router_with_state(state.clone())).unwrap(),
+                state,
+            )
+        }
+
+        #[tokio::test]
+        /// test that added and deleted items are restored from the log after a restart
+        async fn persisted_log_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            let ids = add_items(&server, 1, vec![10, 20, 30])
+                .await
+                .json::<Vec<u64>>();
+            add_items(&server, 4, vec![40]).await.assert_status_ok();
+            delete_item_by_id(&server, 1, ids[1])
+                .await
+                .assert_status_ok();
+            drop(server);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            let table1 = get_items(&server, 1).await;
+            assert_eq!(
+                table1.iter().map(|i| i.id).collect::<Vec<u64>>(),
+                vec![ids[0], ids[2]]
+            );
+            assert_eq!(
+                get_items(&server, 4)
+                    .await
+                    .iter()
+                    .map(|i| i.item_number)
+                    .collect::<Vec<u64>>(),
+                vec![40]
+            );
+        }
+
+        #[tokio::test]
+        /// test that a restored item keeps its cook time
+        async fn persisted_items_keep_duration() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            add_items(&server, 1, vec![10, 20, 30])
+                .await
+                .assert_status_ok();
+            let before = get_items(&server, 1).await;
+            drop(server);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            let after = get_items(&server, 1).await;
+            assert_eq!(
+                before
+                    .iter()
+                    .map(|i| (i.id, i.duration_in_minutes))
+                    .collect::<Vec<_>>(),
+                after
+                    .iter()
+                    .map(|i| (i.id, i.duration_in_minutes))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        #[tokio::test]
+        /// test that ids are not handed out again after a restart, even for deleted items
+        async fn persisted_ids_are_not_reused() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            let ids = add_items(&server, 1, vec![10, 20]).await.json::<Vec<u64>>();
+            delete_item_by_id(&server, 1, ids[1])
+                .await
+                .assert_status_ok();
+            drop(server);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            let new_ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+            assert!(new_ids[0] > ids[1]);
+        }
+
+        #[tokio::test]
+        /// test that a snapshot empties the log and the state is restored from snapshot and log together
+        async fn snapshot_and_log_are_restored() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, state) = setup_persisted_server(dir.path());
+            let ids = add_items(&server, 1, vec![10, 20]).await.json::<Vec<u64>>();
+            state.checkpoint().await.unwrap();
+            assert_eq!(
+                std::fs::metadata(dir.path().join("wal.log")).unwrap().len(),
+                0
+            );
+            delete_item_by_id(&server, 1, ids[0])
+                .await
+                .assert_status_ok();
+            add_items(&server, 1, vec![30]).await.assert_status_ok();
+            drop(server);
+            drop(state);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(
+                get_items(&server, 1)
+                    .await
+                    .iter()
+                    .map(|i| i.item_number)
+                    .collect::<Vec<u64>>(),
+                vec![20, 30]
+            );
+        }
+
+        #[tokio::test]
+        /// test that the snapshot version keeps counting over restarts, from the snapshot and the log
+        async fn snapshot_version_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, state) = setup_persisted_server(dir.path());
+            add_items(&server, 1, vec![10]).await.assert_status_ok();
+            add_items(&server, 2, vec![20]).await.assert_status_ok();
+            state.checkpoint().await.unwrap();
+            add_items(&server, 3, vec![30]).await.assert_status_ok();
+            let before = state.snapshot_all().await;
+            assert_eq!(before.version, 3);
+            drop(server);
+            drop(state);
+
+            let (server, state) = setup_persisted_server(dir.path());
+            assert_eq!(state.snapshot_all().await, before);
+            add_items(&server, 4, vec![40]).await.assert_status_ok();
+            assert_eq!(state.snapshot_all().await.version, 4);
+        }
+
+        #[tokio::test]
+        /// test that records already contained in a snapshot are not applied twice,
+        /// as happens on a crash between writing the snapshot and emptying the log
+        async fn log_covered_by_snapshot_is_skipped() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, state) = setup_persisted_server(dir.path());
+            add_items(&server, 1, vec![10, 20]).await.assert_status_ok();
+            let log = std::fs::read(dir.path().join("wal.log")).unwrap();
+            state.checkpoint().await.unwrap();
+            drop(server);
+            drop(state);
+            std::fs::write(dir.path().join("wal.log"), log).unwrap();
+
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(get_items(&server, 1).await.len(), 2);
+        }
+
+        #[tokio::test]
+        /// test that a record cut short by a crash is detected and dropped, keeping everything before it
+        async fn truncated_log_tail_is_discarded() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            add_items(&server, 1, vec![10, 20]).await.assert_status_ok();
+            add_items(&server, 1, vec![30]).await.assert_status_ok();
+            drop(server);
+            let wal_path = dir.path().join("wal.log");
+            let len = std::fs::metadata(&wal_path).unwrap().len();
+            OpenOptions::new()
+                .write(true)
+                .open(&wal_path)
+                .unwrap()
+                .set_len(len - 5)
+                .unwrap();
+
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(
+                get_items(&server, 1)
+                    .await
+                    .iter()
+                    .map(|i| i.item_number)
+                    .collect::<Vec<u64>>(),
+                vec![10, 20]
+            );
+            // new records are appended after the last intact one and survive the next restart
+            add_items(&server, 1, vec![40]).await.assert_status_ok();
+            drop(server);
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(get_items(&server, 1).await.len(), 3);
+        }
+
+        #[tokio::test]
+        /// test that garbage at the end of the log, i.e., a record with a wrong checksum, is dropped
+        async fn corrupt_log_tail_is_discarded() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            add_items(&server, 1, vec![10, 20]).await.assert_status_ok();
+            drop(server);
+            let mut wal = OpenOptions::new()
+                .append(true)
+                .open(dir.path().join("wal.log"))
+                .unwrap();
+            wal.write_all(&[4, 0, 0, 0, 1, 2, 3, 4, b'n', b'u', b'l', b'l'])
+                .unwrap();
+            drop(wal);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(get_items(&server, 1).await.len(), 2);
+        }
+
+        #[tokio::test]
+        /// test that status changes are restored after a restart
+        async fn persisted_status_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let (server, _) = setup_persisted_server(dir.path());
+            let ids = add_items(&server, 1, vec![10]).await.json::<Vec<u64>>();
+            set_status(&server, 1, ids[0], ItemStatus::Cooking)
+                .await
+                .assert_status_ok();
+            let before = get_items(&server, 1).await;
+            drop(server);
+
+            let (server, _) = setup_persisted_server(dir.path());
+            assert_eq!(get_items(&server, 1).await, before);
+        }
+
+        #[tokio::test]
+        /// test that the floor plan survives a restart, whatever table count is configured by then
+        async fn floor_plan_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let state = open_state(dir.path());
+            let added = state
+                .add_table(NewTable {
+                    name: Some("Terrace 3".to_string()),
+                    ..NewTable::default()
+                })
+                .await
+                .unwrap();
+            assert!(state.remove_table(3).await.unwrap());
+            state.checkpoint().await.unwrap();
+            let renamed = TableInfo {
+                capacity: Some(2),
+                active: false,
+                ..added.clone()
+            };
+            state.update_table(renamed.clone()).await.unwrap();
+            assert!(state.remove_table(7).await.unwrap());
+            drop(state);
+
+            let options = StorageOptions {
+                dir: dir.path().to_path_buf(),
+                ..StorageOptions::default()
+            };
+            let store = DurableStore::open(&options, 5).unwrap();
+            let floor_plan = store.floor_plan().await;
+            assert_eq!(floor_plan.len(), AMOUNT_OF_TABLES - 1);
+            assert_eq!(floor_plan.last(), Some(&renamed));
+            assert!(floor_plan.iter().all(|t| ![3, 7].contains(&t.table_number)));
+            let next = store.add_table(NewTable::default()).await.unwrap();
+            assert_eq!(next.table_number, added.table_number + 1);
+        }
+
+        #[tokio::test]
+        /// test that moved items are on their new table after a restart, from the log and from a snapshot
+        async fn transfers_survive_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let state = open_state(dir.path());
+            let items = state.add_items(1, vec![1, 2, 3], None).await.unwrap();
+            state
+                .transfer_items(1, 2, Some(vec![items[1].id]))
+                .await
+                .unwrap();
+            drop(state);
+            let items = items
+                .into_iter()
+                .map(|item| MenuItem {
+                    session_id: Some(2),
+                    ..item
+                })
+                .collect::<Vec<_>>();
+
+            let state = open_state(dir.path());
+            assert_eq!(state.table(2).await.unwrap().items, vec![items[1].clone()]);
+            state.checkpoint().await.unwrap();
+            state.transfer_items(1, 2, None).await.unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            assert!(state.table(1).await.unwrap().items.is_empty());
+            assert_eq!(
+                state.table(2).await.unwrap().items,
+                vec![items[1].clone(), items[0].clone(), items[2].clone()]
+            );
+        }
+
+        #[tokio::test]
+        /// test that open and closed sessions are restored, from the log and from a snapshot
+        async fn sessions_survive_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let state = open_state(dir.path());
+            let opened = state
+                .open_session(
+                    1,
+                    SessionDetails {
+                        guests: Some(2),
+                        staff: None,
+                    },
+                )
+                .await
+                .unwrap();
+            let items = state.add_items(1, vec![1], None).await.unwrap();
+            state
+                .set_status(1, items[0].id, ItemStatus::Cancelled)
+                .await
+                .unwrap();
+            let closed = state.close_session(1).await.unwrap();
+            state.add_items(2, vec![2], None).await.unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            assert_eq!(state.sessions(1).await, vec![closed.clone()]);
+            assert!(state.table(1).await.unwrap().items.is_empty());
+            let seated = state.table(2).await.unwrap().session.unwrap();
+            assert!(seated.id > opened.id);
+            state.checkpoint().await.unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            assert_eq!(state.sessions(1).await, vec![closed]);
+            assert_eq!(state.table(2).await.unwrap().session, Some(seated.clone()));
+            let next = state
+                .open_session(3, SessionDetails::default())
+                .await
+                .unwrap();
+            assert!(next.id > seated.id);
+        }
+
+        #[tokio::test]
+        /// test that the seats of items are restored after a restart
+        async fn seats_survive_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let state = open_state(dir.path());
+            let items = state.add_items(1, vec![1, 2], Some(2)).await.unwrap();
+            state.set_seat(1, items[1].id, Some(4)).await.unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            let seats = state
+                .table(1)
+                .await
+                .unwrap()
+                .items
+                .iter()
+                .map(|item| item.seat)
+                .collect::<Vec<_>>();
+            assert_eq!(seats, vec![Some(2), Some(4)]);
+        }
+
+        #[tokio::test]
+        /// test that table versions keep counting up over restarts, from the log and from a snapshot
+        async fn versions_survive_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let state = open_state(dir.path());
+            let items = state.add_items(1, vec![1, 2], None).await.unwrap();
+            state
+                .transfer_items(1, 2, Some(vec![items[0].id]))
+                .await
+                .unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            assert_eq!(state.table_version(1).await.unwrap(), 2);
+            assert_eq!(state.table_version(2).await.unwrap(), 1);
+            state.checkpoint().await.unwrap();
+            drop(state);
+
+            let state = open_state(dir.path());
+            assert_eq!(state.table_version(1).await.unwrap(), 2);
+            state.remove_item(1, items[1].id).await.unwrap();
+            assert_eq!(state.table_version(1).await.unwrap(), 3);
+        }
+
+        #[tokio::test]
+        /// test that a payment left pending is settled with the same idempotency key after a restart
+        async fn pending_payments_survive_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let open = |provider: Arc<MockProvider>| {
+                let options = StorageOptions {
+                    dir: dir.path().to_path_buf(),
+                    ..StorageOptions::default()
+                };
+                DurableStore::open(&options, AMOUNT_OF_TABLES)
+                    .unwrap()
+                    .with_menu(Menu::for_tests())
+                    .with_payments(PaymentGateway::new(provider, 1, Duration::from_millis(1)))
+            };
+            let down = Arc::new(MockProvider::scripted(vec![Err(
+                ProviderError::Unavailable("down".to_string()),
+            )]));
+            let state = open(down);
+            state.add_items(1, vec![1, 2], None).await.unwrap();
+            let card = NewPayment {
+                method: PaymentMethod::Card,
+                amount: Some(100),
+            };
+            let payment = state.pay(1, card).await.unwrap();
+            assert_eq!(payment.status, PaymentStatus::Pending);
+            drop(state);
+
+            let up = Arc::new(MockProvider::default());
+            let state = open(up.clone());
+            assert_eq!(state.payment(payment.id).await, Some(payment.clone()));
+            state.reconcile_payments().await;
+            let settled = state.payment(payment.id).await.unwrap();
+            assert_eq!(settled.status, PaymentStatus::Succeeded);
+            assert_eq!(up.requests(), vec![payments::idempotency_key(&payment)]);
+            state.checkpoint().await.unwrap();
+            drop(state);
+
+            let state = open(Arc::new(MockProvider::default()));
+            assert_eq!(state.payments(None).await, vec![settled.clone()]);
+            let cash = NewPayment {
+                method: PaymentMethod::Cash,
+                amount: Some(100),
+            };
+            assert!(state.pay(1, cash).await.unwrap().id > settled.id);
+        }
+
+        #[tokio::test]
+        /// test that a fresh data directory starts with the configured number of tables
+        async fn fresh_floor_plan() {
+            let dir = tempfile::tempdir().unwrap();
+            let options = StorageOptions {
+                dir: dir.path().to_path_buf(),
+                ..StorageOptions::default()
+            };
+            let store = DurableStore::open(&options, 5).unwrap();
+            assert_eq!(store.floor_plan().await.len(), 5);
+            assert_eq!(
+                store
+                    .add_table(NewTable::default())
+                    .await
+                    .unwrap()
+                    .table_number,
+                5
+            );
+        }
+
+        #[test]
+        /// test parsing the durability policy
+        fn durability_parsing() {
+            assert_eq!("always".parse::<Durability>().unwrap(), Durability::Always);
+            assert_eq!("never".parse::<Durability>().unwrap(), Durability::Never);
+            assert_eq!(
+                "every:10".parse::<Durability>().unwrap(),
+                Durability::EveryN(10)
+            );
+            assert!("every:0".parse::<Durability>().is_err());
+            assert!("sometimes".parse::<Durability>().is_err());
+        }
+    }
+
+    mod tls {
+        use std::{path::Path, sync::Arc, time::Duration};
+
+        use rcgen::{
+            BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa,
+            KeyPair,
+        };
+        use rustls::{
+            crypto::ring,
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+            ClientConfig, RootCertStore,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::TlsConnector;
+
+        use crate::{
+            router,
+            tls::{self, TlsOptions},
+            types::API_KEY,
+        };
+
+        /// A certificate authority issuing certificates for the tests
+        struct Authority {
+            cert: Certificate,
+            key: KeyPair,
+        }
+
+        impl Authority {
+            fn new() -> Self {
+                let key = KeyPair::generate().unwrap();
+                let mut params = CertificateParams::new(vec![]).unwrap();
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                Self {
+                    cert: params.self_signed(&key).unwrap(),
+                    key,
+                }
+            }
+
+            /// a certificate for `localhost` and its key, both as PEM
+            fn issue(&self, usage: ExtendedKeyUsagePurpose) -> (String, String) {
+                let key = KeyPair::generate().unwrap();
+                let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+                params.extended_key_usages = vec![usage];
+                let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+                (cert.pem(), key.serialize_pem())
+            }
+        }
+
+        /// helper function that writes a server certificate issued by `authority` into `dir`
+        fn write_server_cert(dir: &Path, authority: &Authority) {
+            let (cert, key) = authority.issue(ExtendedKeyUsagePurpose::ServerAuth);
+            std::fs::write(dir.join("cert.pem"), cert).unwrap();
+            std::fs::write(dir.join("key.pem"), key).unwrap();
+        }
+
+        /// helper function that serves the test router over https with `options` and returns the port
+        async fn serve(options: &TlsOptions) -> u16 {
+            let config = tls::load(options).unwrap();
+            tokio::spawn(tls::reload_periodically(config.clone(), options.clone()));
+            let handle = axum_server::Handle::new();
+            let server = axum_server::bind_rustls("127.0.0.1:0".parse().unwrap(), config)
+                .handle(handle.clone())
+                .serve(router().into_make_service());
+            tokio::spawn(server);
+            handle.listening().await.unwrap().port()
+        }
+
+        /// helper function that gets all tables trusting `authority`, with a client certificate if given.
+        /// Returns the status line.
+        async fn get(
+            port: u16,
+            authority: &Authority,
+            client_cert: Option<(String, String)>,
+        ) -> std::io::Result<String> {
+            let mut roots = RootCertStore::empty();
+            roots.add(authority.cert.der().clone()).unwrap();
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let config = match client_cert {
+                Some((cert, key)) => builder
+                    .with_client_auth_cert(
+                        vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                        PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                    )
+                    .unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            let tcp = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+            let mut stream = TlsConnector::from(Arc::new(config))
+                .connect(ServerName::try_from("localhost").unwrap(), tcp)
+                .await?;
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nConnection: close\r\n\r\n",
+                API_KEY
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            Ok(response.lines().next().unwrap_or_default().to_string())
+        }
+
+        #[tokio::test]
+        /// test that the api is served over https and only trusted by clients of the right authority
+        async fn serves_https() {
+            let dir = tempfile::tempdir().unwrap();
+            let authority = Authority::new();
+            write_server_cert(dir.path(), &authority);
+            let options = TlsOptions {
+                cert: dir.path().join("cert.pem"),
+                key: dir.path().join("key.pem"),
+                client_ca: None,
+                reload_interval: Duration::from_secs(60),
+            };
+            let port = serve(&options).await;
+            assert_eq!(
+                get(port, &authority, None).await.unwrap(),
+                "HTTP/1.1 200 OK"
+            );
+            assert!(get(port, &Authority::new(), None).await.is_err());
+        }
+
+        #[tokio::test]
+        /// test that with a client CA only clients with a certificate it issued are served
+        async fn requires_client_certificates() {
+            let dir = tempfile::tempdir().unwrap();
+            let authority = Authority::new();
+            write_server_cert(dir.path(), &authority);
+            let tablets = Authority::new();
+            std::fs::write(dir.path().join("tablets.pem"), tablets.cert.pem()).unwrap();
+            let options = TlsOptions {
+                cert: dir.path().join("cert.pem"),
+                key: dir.path().join("key.pem"),
+                client_ca: Some(dir.path().join("tablets.pem")),
+                reload_interval: Duration::from_secs(60),
+            };
+            let port = serve(&options).await;
+            let enrolled = tablets.issue(ExtendedKeyUsagePurpose::ClientAuth);
+            assert_eq!(
+                get(port, &authority, Some(enrolled)).await.unwrap(),
+                "HTTP/1.1 200 OK"
+            );
+            assert!(get(port, &authority, None).await.is_err());
+            let stranger = Authority::new().issue(ExtendedKeyUsagePurpose::ClientAuth);
+            assert!(get(port, &authority, Some(stranger)).await.is_err());
+        }
+
+        #[tokio::test]
+        /// test that changed certificates are picked up without a restart and broken ones are not
+        async fn reloads_certificates() {
+            let dir = tempfile::tempdir().unwrap();
+            let old = Authority::new();
+            write_server_cert(dir.path(), &old);
+            let options = TlsOptions {
+                cert: dir.path().join("cert.pem"),
+                key: dir.path().join("key.pem"),
+                client_ca: None,
+                reload_interval: Duration::from_millis(20),
+            };
+            let port = serve(&options).await;
+            assert!(get(port, &old, None).await.is_ok());
+
+            std::fs::write(dir.path().join("cert.pem"), "not a certificate").unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(get(port, &old, None).await.is_ok());
+
+            let new = Authority::new();
+            write_server_cert(dir.path(), &new);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(get(port, &old, None).await.is_err());
+            assert_eq!(get(port, &new, None).await.unwrap(), "HTTP/1.1 200 OK");
+        }
+
+        #[test]
+        /// test that a key not matching the certificate is refused up front
+        fn mismatched_key() {
+            let dir = tempfile::tempdir().unwrap();
+            let authority = Authority::new();
+            write_server_cert(dir.path(), &authority);
+            let (_, other_key) = authority.issue(ExtendedKeyUsagePurpose::ServerAuth);
+            std::fs::write(dir.path().join("key.pem"), other_key).unwrap();
+            let options = TlsOptions {
+                cert: dir.path().join("cert.pem"),
+                key: dir.path().join("key.pem"),
+                client_ca: None,
+                reload_interval: Duration::from_secs(60),
+            };
+            assert!(tls::server_config(&options).is_err());
+        }
+    }
 }
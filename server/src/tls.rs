@@ -0,0 +1,132 @@
+//! Serving https ourselves instead of behind a proxy.
+//!
+//! The certificate chain and key are read from PEM files. With a client CA only clients presenting a certificate
+//! signed by it get a connection, so only enrolled tablets can talk to us at all. The files are checked
+//! periodically and reloaded when they change, connections made after that use the new certificates.
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+
+use crate::config::TlsConfig;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where the certificates are and how often to look for new ones
+pub(crate) struct TlsOptions {
+    /// the certificate chain, the server's own certificate first
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+    /// require client certificates signed by one of the CAs in this file
+    pub(crate) client_ca: Option<PathBuf>,
+    pub(crate) reload_interval: Duration,
+}
+
+impl TlsOptions {
+    /// The options set in the `tls` section of the config, `None` without a certificate and key
+    pub(crate) fn from_config(config: &TlsConfig) -> Option<Self> {
+        Some(Self {
+            cert: config.cert.clone()?,
+            key: config.key.clone()?,
+            client_ca: config.client_ca.clone(),
+            reload_interval: Duration::from_secs(config.reload_secs),
+        })
+    }
+
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+    }
+
+    /// When each of the files was last modified, unknown ones as `None`
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.files()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificates in {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "No certificate in {}", path.display());
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid key in {}", path.display()))?
+        .with_context(|| format!("No private key in {}", path.display()))
+}
+
+/// The rustls configuration for the files in `options`
+pub(crate) fn server_config(options: &TlsOptions) -> anyhow::Result<ServerConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("No usable TLS versions")?;
+    let builder = match &options.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid client CA in {}", path.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Cannot verify client certificates")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(read_certs(&options.cert)?, read_key(&options.key)?)
+        .context("The key does not match the certificate")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Load the certificates once, for [`axum_server::bind_rustls`]
+pub(crate) fn load(options: &TlsOptions) -> anyhow::Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(server_config(options)?)))
+}
+
+/// Reload `config` whenever one of the files changes, until the server goes away.
+/// Broken files are logged and skipped, the previous certificates stay in use until they are fixed.
+pub(crate) async fn reload_periodically(config: RustlsConfig, options: TlsOptions) {
+    let mut modified = options.modified();
+    let mut interval = tokio::time::interval(options.reload_interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let now_modified = options.modified();
+        if now_modified == modified {
+            continue;
+        }
+        match server_config(&options) {
+            Ok(new) => {
+                config.reload_from_config(Arc::new(new));
+                modified = now_modified;
+                tracing::info!("Reloaded the TLS certificates");
+            }
+            Err(e) => tracing::error!("Keeping the old TLS certificates: {:#}", e),
+        }
+    }
+}
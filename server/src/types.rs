@@ -1,67 +1,53 @@
-use std::sync::Arc;
+use chrono::{DateTime, Duration, Utc};
 
-use rand::Rng;
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+pub(crate) use restaurant_types::{
+    Adjustment, AdjustmentKind, Bill, BillLine, BillPayments, BillShare, CookTimeModel, EventKind,
+    IssuedKey, ItemStatus, ItemTransfer, KeyInfo, MenuEntry, MenuItem, NewAdjustment, NewKey,
+    NewPayment, NewRefund, NewTable, OrderQuery, Payment, PaymentMethod, PaymentQuery,
+    PaymentStatus, QueryParam, Role, SeatUpdate, Session, SessionDetails, SessionInfo, Snapshot,
+    SplitBill, SplitMode, StatusChange, StatusUpdate, Table, TableInfo, API_KEY,
+};
 
-/// For clarity we ignore off by one here
+/// How many tables a fresh restaurant starts with unless the config says otherwise
 pub(crate) static AMOUNT_OF_TABLES: usize = 100;
-/// we validate against this secret key. Not perfect security but better than nothing.
-pub(crate) static API_KEY: &str = "QXlj";
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-/// an item on the menu
-pub(crate) struct MenuItem {
-    /// the number of the menu, i.e., 1 for Potato Fries, 2 for Karaage, etc.
-    pub(crate) item_number: u64,
-    /// the duration the menu item needs to cook in minutes. We do not need finer granularity.
-    pub(crate) duration_in_minutes: u64,
-}
-
-impl MenuItem {
-    /// Create a new menuitem with a random duration
-    pub(crate) fn new(item_number: u64) -> Self {
-        let mut rng = rand::thread_rng();
-        let val = rng.gen_range(5..16);
-        Self {
-            item_number,
-            duration_in_minutes: val,
-        }
+/// Create a new menuitem for `entry` with the given `id`, taking `val` minutes to cook and ordered at `now`
+/// during the session `session_id` by the guest at `seat`
+pub(crate) fn new_menu_item(
+    id: u64,
+    entry: &MenuEntry,
+    val: u64,
+    now: DateTime<Utc>,
+    session_id: u64,
+    seat: Option<u32>,
+) -> MenuItem {
+    MenuItem {
+        id,
+        item_number: entry.item_number,
+        duration_in_minutes: val,
+        ordered_at: now,
+        ready_at: now + Duration::minutes(val as i64),
+        remaining_minutes: val,
+        status: ItemStatus::Ordered,
+        status_history: vec![StatusChange {
+            status: ItemStatus::Ordered,
+            at: now,
+        }],
+        session_id: Some(session_id),
+        price: Some(entry.price),
+        seat,
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-/// A table in the restaurant having various menuitems
-pub(crate) struct Table {
-    pub(crate) table_number: usize,
-    pub(crate) items: Vec<MenuItem>,
-}
-
-pub(crate) async fn is_table_empty(table: &RwLock<Table>) -> bool {
-    table.read().await.items.is_empty()
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-/// the query parameter, having the API_key and a optional limit
-pub(crate) struct QueryParam {
-    /// API Key we will check
-    pub(crate) key: String,
-    /// The limit if we want
-    pub(crate) limit: Option<u64>,
-}
-
-/// The whole state of the app is a vector of tables.
-/// We use RwLock inside as multiple people rarely will add items to the same table
-pub(crate) type AppState = Arc<Vec<RwLock<Table>>>;
-
-/// Create a new AppState, filling the table vector with RwLocks
-pub(crate) fn new_app_state() -> AppState {
-    let mut tables = Vec::with_capacity(AMOUNT_OF_TABLES);
-    for i in 0..AMOUNT_OF_TABLES {
-        tables.push(RwLock::new(Table {
-            table_number: i,
-            items: vec![],
-        }));
-    }
-    Arc::new(tables)
+/// Set `remaining_minutes` of `item` as seen at `now`, rounded up so an item is only at zero once it is due.
+/// Items the kitchen is done with have nothing remaining.
+pub(crate) fn with_countdown(mut item: MenuItem, now: DateTime<Utc>) -> MenuItem {
+    item.remaining_minutes = match item.status {
+        ItemStatus::Ordered | ItemStatus::Cooking => {
+            let seconds_left = (item.ready_at - now).num_seconds().max(0) as u64;
+            seconds_left.div_ceil(60)
+        }
+        ItemStatus::Ready | ItemStatus::Served | ItemStatus::Cancelled => 0,
+    };
+    item
 }
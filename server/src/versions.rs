@@ -0,0 +1,120 @@
+//! Optimistic concurrency on tables. Every table counts its changes in its `version`, which the routes reading
+//! a table, its items or its session send as `ETag: "<version>"`.
+//!
+//! A request changing a table with `If-Match` is only handled while the table is at one of the named versions,
+//! otherwise it answers 412 without changing anything. A tablet that found the position of an item in an older
+//! state of the table cannot remove the wrong one that way. A read with `If-None-Match` naming the current version
+//! answers 304 without a body.
+use axum::{
+    extract::{MatchedPath, RawPathParams, Request, State},
+    http::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use restaurant_types::routes;
+
+use crate::{
+    error::ApiError,
+    store::{Expectation, RestaurantStore, StoreError},
+    AppState,
+};
+
+/// the reads answered with the version of their table
+const TAGGED: [&str; 4] = [
+    routes::TABLE,
+    routes::ITEM,
+    routes::ITEM_POSITION,
+    routes::SESSION,
+];
+
+/// The versions named in the header `name`, `None` for `*`.
+/// Weak tags only count with `weak`, tags that are not one of our versions never match.
+fn named_versions(headers: &HeaderMap, name: &str, weak: bool) -> Option<Vec<u64>> {
+    let value = headers.get(name)?.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return None;
+    }
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter_map(|tag| match tag.strip_prefix("W/") {
+                Some(tag) if weak => Some(tag),
+                Some(_) => None,
+                None => Some(tag),
+            })
+            .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+            .collect(),
+    )
+}
+
+/// `"<version>"`
+fn etag(version: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version))
+        .expect("digits and quotes are a valid header")
+}
+
+/// Middleware sending the version of the table a read is about as `ETag` and handling `If-None-Match` and
+/// `If-Match`. Routes without a table number are left alone.
+pub(crate) async fn conditional<S: RestaurantStore>(
+    State(state): State<AppState<S>>,
+    route: MatchedPath,
+    params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(table_number) = params
+        .iter()
+        .find(|(name, _)| *name == "table_number")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+    else {
+        return Ok(next.run(request).await);
+    };
+
+    if request.method() == Method::GET {
+        if !TAGGED.contains(&route.as_str()) {
+            return Ok(next.run(request).await);
+        }
+        // read before answering, so the tag is never newer than what we answer with
+        let Ok(version) = state.table_version(table_number).await else {
+            return Ok(next.run(request).await);
+        };
+        let unchanged = match named_versions(request.headers(), IF_NONE_MATCH.as_str(), true) {
+            Some(versions) => versions.contains(&version),
+            None => request.headers().contains_key(IF_NONE_MATCH),
+        };
+        let mut response = if unchanged {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            next.run(request).await
+        };
+        if response.status().is_success() || unchanged {
+            response.headers_mut().insert(ETAG, etag(version));
+        }
+        return Ok(response);
+    }
+
+    if !request.headers().contains_key(IF_MATCH) {
+        return Ok(next.run(request).await);
+    }
+    let current = state.table_version(table_number).await?;
+    match named_versions(request.headers(), IF_MATCH.as_str(), false) {
+        None => Ok(next.run(request).await),
+        Some(versions) if !versions.contains(&current) => {
+            let mut response =
+                ApiError::from(StoreError::VersionMismatch { current }).into_response();
+            response.headers_mut().insert(ETAG, etag(current));
+            Ok(response)
+        }
+        // the table may still change until the store locks it, which checks again
+        Some(versions) => Ok(Expectation {
+            table_number,
+            versions,
+        }
+        .scope(next.run(request))
+        .await),
+    }
+}